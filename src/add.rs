@@ -1,18 +1,32 @@
-use crate::{settings::Settings, unbuilder::Unbuilder};
+use crate::{
+    builder::BuildConfig,
+    diagnostic::Code,
+    dump_index::{DumpIndex, Location, Source},
+    message::{self, status},
+    sarc_meta::SarcMeta,
+    settings::GameProfile,
+    unbuilder::{SarcOutput, Unbuilder, HANDLED, ROOT_PACKS},
+};
 use anyhow::{anyhow, Context, Result};
+use botw_utils::{get_canon_name, get_canon_name_without_root};
 use join_str::jstr;
+use path_slash::PathExt;
 use roead::{
     aamp::{hash_name, Parameter, ParameterIO, ParameterListing},
     byml::Byml,
-    sarc::Sarc,
-    yaz0::decompress,
+    sarc::{Sarc, SarcWriter},
+    yaz0::{compress, decompress, decompress_if},
+    Endian,
 };
 use std::{
+    collections::{BTreeMap, HashMap, HashSet},
     fs,
     path::{Path, PathBuf},
 };
 use structopt::StructOpt;
 
+const CRC32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
 pub static AOC_PACKS: &[&str] = &[
     "AocMainField",
     "Dungeon120",
@@ -39,10 +53,658 @@ pub static AOC_PACKS: &[&str] = &[
     "RemainsWind",
 ];
 
+#[inline]
+fn content_root(aoc: bool, be: bool) -> &'static str {
+    match (aoc, be) {
+        (true, true) => "aoc/0010",
+        (true, false) => "01007EF00011F001/romfs",
+        (false, true) => "content",
+        (false, false) => "01007EF00011E000/romfs",
+    }
+}
+
+/// Unbuilds a stock root pack into the project under a (possibly new) name, returning the
+/// folder it was unbuilt to
+fn unbuild_pack(
+    index: &DumpIndex,
+    project: &Path,
+    be: bool,
+    pack: &str,
+    name: &str,
+) -> Result<PathBuf> {
+    let rel_path = jstr!("Pack/{pack}.pack");
+    let (data, location) = if AOC_PACKS.contains(&pack) {
+        index.read_canon(&jstr!("Aoc/0010/{&rel_path}"))?
+    } else {
+        index.read_canon(&rel_path)?
+    };
+    let out = project
+        .join(content_root(location.source == Source::Dlc, be))
+        .join(jstr!("Pack/{name}.pack"));
+    let unbuilder = Unbuilder::new(be, project, PathBuf::new());
+    unbuilder.unbuild_sarc(&data, SarcOutput::Folder(&out))?;
+    Ok(out)
+}
+
+/// Unbuilds one file from the game dump into the project, returning its path in the project. A
+/// file inside a pack keeps the pack's layout and the rest of its stock entries, so building
+/// doesn't replace the whole stock pack with one holding only the new file.
+fn add_dump_file(index: &DumpIndex, project: &Path, path: &str, be: bool) -> Result<PathBuf> {
+    let path = path.replace('\\', "/");
+    let canon = get_canon_name(&path)
+        .unwrap_or_else(|| get_canon_name_without_root(path.trim_start_matches('/')));
+    let location = index
+        .get(&canon)
+        .or_else(|| index.get(&jstr!("Aoc/0010/{&canon}")))
+        .with_context(|| format!("{} not found in game files", canon))?;
+    // Files in actor and event packs are unbuilt to the content root, like the packs
+    let mut rel = PathBuf::new();
+    let mut in_root_pack = false;
+    // The folders of the packs the file is in, with how deep each is nested
+    let mut packs: Vec<(PathBuf, usize)> = vec![];
+    for (depth, part) in std::iter::once(&location.file)
+        .chain(location.nested.iter())
+        .enumerate()
+    {
+        if in_root_pack {
+            rel = PathBuf::new();
+            packs.clear();
+        }
+        rel.push(part.trim_start_matches('/'));
+        in_root_pack = rel
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| ROOT_PACKS.contains(&e))
+            .unwrap_or(false);
+        if depth < location.nested.len() && !in_root_pack {
+            packs.push((rel.clone(), depth));
+        }
+    }
+    let file_name = rel
+        .file_name()
+        .and_then(|n| n.to_str())
+        .context("File has no name")?;
+    if file_name == "ActorInfo.product.sbyml" {
+        return Err(anyhow!("Use `add actorinfo` to add actor info"));
+    } else if HANDLED.contains(&file_name) {
+        return Err(anyhow!(
+            "{} is generated when building and cannot be added",
+            file_name
+        ));
+    }
+    let root = Path::new(content_root(location.source == Source::Dlc, be));
+    let rel = root.join(rel);
+    let out = project.join(&rel);
+    let mut out_yml = out.clone().into_os_string();
+    out_yml.push(".yml");
+    if out.exists() || Path::new(&out_yml).exists() {
+        return Err(anyhow!(
+            "{} already exists in the project",
+            rel.to_slash_lossy()
+        ));
+    }
+    status!("Extracting {}...", canon);
+    for (pack, depth) in packs {
+        let pack_dir = project.join(root).join(pack);
+        let mut meta = match SarcMeta::load(&pack_dir)? {
+            Some(meta) => meta,
+            None => {
+                let data = index.read(&Location {
+                    nested: location.nested[..depth].to_vec(),
+                    ..location.clone()
+                })?;
+                let data = decompress_if(&data);
+                let (mut meta, unnamed) = SarcMeta::from_binary(&data)?;
+                fs::create_dir_all(&pack_dir)?;
+                for (hash, data) in unnamed {
+                    fs::write(pack_dir.join(SarcMeta::unnamed_file(hash)), data)?;
+                }
+                // Only the new file is unbuilt, so the rest is restored from the stock pack
+                meta.vanilla = meta.entries.iter().filter_map(|e| e.name.clone()).collect();
+                meta
+            }
+        };
+        meta.vanilla.retain(|name| name != &location.nested[depth]);
+        meta.save(&pack_dir)?;
+    }
+    let data = index.read(location)?;
+    let unbuilder = Unbuilder::new(be, project, PathBuf::new());
+    unbuilder.unbuild_file(&rel, &data)?;
+    Ok(rel)
+}
+
+/// Where `id` appears in `text` as a whole token, not next to other letters or digits, so
+/// `Dungeon000` is found in `DgnMrgPrt_Dungeon000` and `Dungeon000_sub` but not in `Dungeon0001`
+fn id_positions<'a>(text: &'a str, id: &'a str) -> impl Iterator<Item = usize> + 'a {
+    text.match_indices(id).map(|(i, _)| i).filter(move |&i| {
+        !text[..i]
+            .chars()
+            .next_back()
+            .map(|c| c.is_ascii_alphanumeric())
+            .unwrap_or(false)
+            && !text[i + id.len()..]
+                .chars()
+                .next()
+                .map(|c| c.is_ascii_alphanumeric())
+                .unwrap_or(false)
+    })
+}
+
+#[inline]
+fn contains_id(text: &str, id: &str) -> bool {
+    id_positions(text, id).next().is_some()
+}
+
+/// Replaces `id` with `new` wherever it is a whole token
+fn replace_id(text: &str, id: &str, new: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    for i in id_positions(text, id) {
+        out.push_str(&text[last..i]);
+        out.push_str(new);
+        last = i + id.len();
+    }
+    out.push_str(&text[last..]);
+    out
+}
+
+/// Whether any string in a BYML node has `id` as a whole token
+fn mentions_id(node: &Byml, id: &str) -> bool {
+    match node {
+        Byml::Array(array) => array.iter().any(|child| mentions_id(child, id)),
+        Byml::Map(map) => map.values().any(|child| mentions_id(child, id)),
+        Byml::String(text) => contains_id(text, id),
+        _ => false,
+    }
+}
+
+/// Replaces `id` with `new` in every string of a BYML node
+fn replace_id_in(node: &mut Byml, id: &str, new: &str) {
+    match node {
+        Byml::Array(array) => {
+            for child in array.iter_mut() {
+                replace_id_in(child, id, new);
+            }
+        }
+        Byml::Map(map) => {
+            for child in map.values_mut() {
+                replace_id_in(child, id, new);
+            }
+        }
+        Byml::String(text) => {
+            if contains_id(text, id) {
+                *text = replace_id(text, id, new).into();
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Clones every entry of a map unit which refers to the base shrine, such as its entrance,
+/// merged exterior, travel point, and location marker. The clones get the new shrine ID and new
+/// hash IDs, and links between them are moved to the clones. Returns how many were added.
+fn clone_field_entries(unit: &mut Byml, base: &str, new: &str) -> Result<usize> {
+    let unit = unit.as_mut_map()?;
+    let hash_id = |entry: &Byml| match entry.as_map().ok()?.get("HashId")? {
+        Byml::U32(id) => Some(*id),
+        _ => None,
+    };
+    let mut used: HashSet<u32> = unit
+        .values()
+        .filter_map(|entries| entries.as_array().ok())
+        .flatten()
+        .filter_map(hash_id)
+        .collect();
+    let mut new_ids = HashMap::new();
+    let mut clones = vec![];
+    for (key, entries) in unit.iter() {
+        let Ok(entries) = entries.as_array() else {
+            continue;
+        };
+        for entry in entries.iter().filter(|e| mentions_id(e, base)) {
+            let mut clone = entry.clone();
+            replace_id_in(&mut clone, base, new);
+            if let Some(old_id) = hash_id(entry) {
+                let mut id = CRC32.checksum(format!("{}/{:08x}", new, old_id).as_bytes());
+                while !used.insert(id) {
+                    id = id.wrapping_add(1);
+                }
+                new_ids.insert(old_id, id);
+                clone.as_mut_map()?.insert("HashId".into(), Byml::U32(id));
+            }
+            clones.push((key.clone(), clone));
+        }
+    }
+    let count = clones.len();
+    for (key, mut clone) in clones {
+        if let Some(Byml::Array(links)) = clone.as_mut_map()?.get_mut("LinksToObj") {
+            for link in links.iter_mut().filter_map(|l| l.as_mut_map().ok()) {
+                if let Some(Byml::U32(dest)) = link.get_mut("DestUnitHashId") {
+                    if let Some(id) = new_ids.get(dest) {
+                        *dest = *id;
+                    }
+                }
+            }
+        }
+        unit.get_mut(&key)
+            .context("Map unit entries missing")?
+            .as_mut_array()?
+            .push(clone);
+    }
+    Ok(count)
+}
+
+/// Clones the overworld entries of a shrine into the project's copies of the `MainField` map
+/// units they are in, adding the units from the game files if needed. Returns the units changed.
+fn clone_overworld(
+    index: &DumpIndex,
+    project: &Path,
+    be: bool,
+    base: &str,
+    new: &str,
+) -> Result<Vec<String>> {
+    // The DLC copy of a map unit replaces the base game one
+    let mut units: BTreeMap<&str, &Location> = BTreeMap::new();
+    for (canon, location) in index.iter().filter(|(c, l)| {
+        l.nested.is_empty() && c.contains("Map/MainField/") && c.ends_with(".mubin")
+    }) {
+        let key = canon.trim_start_matches("Aoc/0010/");
+        if location.source == Source::Dlc || !units.contains_key(key) {
+            units.insert(key, location);
+        }
+    }
+    let mut changed = vec![];
+    for (key, location) in units {
+        let out = project
+            .join(content_root(location.source == Source::Dlc, be))
+            .join(&location.file)
+            .with_extension("smubin.yml");
+        let mut unit = if out.exists() {
+            let text = fs::read_to_string(&out)?;
+            if !contains_id(&text, base) || contains_id(&text, new) {
+                continue;
+            }
+            Byml::from_text(text)?
+        } else {
+            let data = decompress_if(&index.read(location)?).into_owned();
+            // Most units never mention the shrine, so only those that might are parsed
+            if !contains_id(&String::from_utf8_lossy(&data), base) {
+                continue;
+            }
+            Byml::from_binary(data)?
+        };
+        if clone_field_entries(&mut unit, base, new)? > 0 {
+            fs::create_dir_all(out.parent().unwrap())?;
+            fs::write(&out, unit.to_text())?;
+            changed.push(key.to_owned());
+        }
+    }
+    Ok(changed)
+}
+
+/// Adds actor info for every actor named after the base shrine, such as its merged exterior
+/// `DgnMrgPrt_` actor, under the new shrine's name. The stock actor info is added to the project
+/// first if it doesn't have any.
+fn clone_shrine_actorinfo(
+    index: &DumpIndex,
+    project: &Path,
+    be: bool,
+    base: &str,
+    new: &str,
+) -> Result<Vec<String>> {
+    let info_root = project
+        .join(content_root(false, be))
+        .join("Actor/ActorInfo");
+    let (data, _) = index.read_canon("Actor/ActorInfo.product.byml")?;
+    if !info_root.exists() {
+        Unbuilder::new(be, project, PathBuf::new()).unbuild_actorinfo_data(&data)?;
+    }
+    let actorinfo = Byml::from_binary(decompress_if(&data))?;
+    let mut cloned = vec![];
+    for actor in actorinfo["Actors"].as_array()? {
+        let name = actor["name"].as_string()?;
+        if !contains_id(name, base) {
+            continue;
+        }
+        let path = info_root.join(jstr!("{name}.info.yml"));
+        let mut info = if path.exists() {
+            Byml::from_text(fs::read_to_string(&path)?)?
+        } else {
+            actor.clone()
+        };
+        replace_id_in(&mut info, base, new);
+        let new_name = replace_id(name, base, new);
+        fs::write(
+            info_root.join(jstr!("{&new_name}.info.yml")),
+            info.to_text(),
+        )?;
+        cloned.push(new_name);
+    }
+    Ok(cloned)
+}
+
+/// Renames every file and folder under `root` with `base` as a token in its name, and replaces
+/// `base` with `new` in every YAML file
+fn rename_tree(root: &Path, base: &str, new: &str) -> Result<()> {
+    let mut paths: Vec<PathBuf> = glob::glob(&root.join("**/*").to_string_lossy())?
+        .filter_map(Result::ok)
+        .collect();
+    // Deepest first, so renaming a folder never moves a path still to be renamed
+    paths.sort_by_key(|p| std::cmp::Reverse(p.components().count()));
+    for path in paths {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .context("Invalid file name")?;
+        let renamed = replace_id(name, base, new);
+        let path = if renamed != name {
+            let renamed = path.with_file_name(renamed);
+            fs::rename(&path, &renamed)?;
+            renamed
+        } else {
+            path
+        };
+        if path.is_file() && path.extension().and_then(|e| e.to_str()) == Some("yml") {
+            let text = fs::read_to_string(&path)?;
+            let replaced = replace_id(&text, base, new);
+            if replaced != text {
+                fs::write(&path, replaced)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Languages with unbuilt texts in the project
+fn project_languages(content: &Path) -> Result<Vec<String>> {
+    let message_root = content.join("Message");
+    if !message_root.exists() {
+        return Ok(vec![]);
+    }
+    Ok(fs::read_dir(message_root)?
+        .filter_map(Result::ok)
+        .filter(|e| e.path().is_dir())
+        .filter_map(|e| e.file_name().to_str().map(|n| n.to_owned()))
+        .collect())
+}
+
+/// Loads a message file, e.g. `StaticMsg/Dungeon.msyt`, from the project texts for a language,
+/// or from the game files if the project doesn't have it yet
+fn load_msyt(
+    index: &DumpIndex,
+    content: &Path,
+    lang: &str,
+    file: &str,
+) -> Result<serde_yml::Value> {
+    let path = content.join("Message").join(lang).join(file);
+    let text = if path.exists() {
+        fs::read_to_string(&path)?
+    } else {
+        let (data, _) = index.read_canon(&jstr!("Pack/Bootup_{lang}.pack"))?;
+        let bootup = Sarc::new(data)?;
+        let messages = Sarc::new(
+            bootup
+                .get(&jstr!("Message/Msg_{lang}.product.ssarc"))
+                .context("Bootup pack missing messages")?
+                .data,
+        )?;
+        let msbt = messages
+            .get(&Path::new(file).with_extension("msbt").to_string_lossy())
+            .with_context(|| format!("{} not found in {} texts", file, lang))?;
+        serde_yml::to_string(
+            &msyt::Msyt::from_msbt_bytes(msbt.data).map_err(|e| anyhow!("{}", e))?,
+        )?
+    };
+    Ok(serde_yml::from_str(&text)?)
+}
+
+/// Copies message entries to new names in a message file for every language in the project.
+/// Entries missing from the base are skipped.
+fn clone_messages(
+    index: &DumpIndex,
+    content: &Path,
+    file: &str,
+    entries: &[(String, String)],
+) -> Result<()> {
+    for lang in project_languages(content)? {
+        let mut msyt = load_msyt(index, content, &lang, file)?;
+        let messages = msyt
+            .get_mut("entries")
+            .and_then(|e| e.as_mapping_mut())
+            .with_context(|| format!("No entries in {} texts for {}", lang, file))?;
+        for (base, new) in entries {
+            if let Some(entry) = messages.get(base.as_str()).cloned() {
+                messages.insert(new.as_str().into(), entry);
+            }
+        }
+        let path = content.join("Message").join(&lang).join(file);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, serde_yml::to_string(&msyt)?)?;
+    }
+    Ok(())
+}
+
+/// Clones the texts, inventory icon, and `IsGet` flag of an item actor
+fn clone_item(
+    index: &DumpIndex,
+    content: &Path,
+    be: bool,
+    profile: &str,
+    base_actor: &str,
+    new_actor: &str,
+) -> Result<()> {
+    if project_languages(content)?.is_empty() {
+        status!("No texts in project, skipping item name and description");
+    } else {
+        status!("Cloning item name and description...");
+        clone_messages(
+            index,
+            content,
+            &jstr!("ActorType/{profile}.msyt"),
+            &["_Name", "_Desc", "_PictureBook"].map(|suffix| {
+                (
+                    jstr!("{base_actor}{suffix}"),
+                    jstr!("{new_actor}{suffix}"),
+                )
+            }),
+        )?;
+    }
+
+    status!("Cloning inventory icon...");
+    let icon = content.join(jstr!("UI/StockItem/{new_actor}.sbitemico"));
+    if !icon.exists() {
+        let base_icon = content.join(jstr!("UI/StockItem/{base_actor}.sbitemico"));
+        let data = if base_icon.exists() {
+            fs::read(base_icon)?
+        } else {
+            index
+                .read_canon(&jstr!("UI/StockItem/{base_actor}.bitemico"))
+                .context("Base actor has no inventory icon")?
+                .0
+        };
+        fs::create_dir_all(icon.parent().unwrap())?;
+        fs::write(icon, data)?;
+    }
+
+    status!("Adding IsGet flag...");
+    add_is_get_flag(index, content, be, base_actor, new_actor)
+}
+
+/// Gets a file from Bootup.pack in the project, or from the game files if the project doesn't
+/// have it yet. The pack's `.sarc.yml` is written or updated so building restores the rest of
+/// the pack from the stock copy.
+fn bootup_file(index: &DumpIndex, content: &Path, name: &str) -> Result<(PathBuf, Vec<u8>)> {
+    let pack_dir = content.join("Pack/Bootup.pack");
+    let path = pack_dir.join(name);
+    if path.is_dir() {
+        return Err(anyhow!(
+            "{} is unbuilt in the project, so its flags must be edited by hand",
+            name
+        ));
+    } else if path.exists() {
+        return Ok((path.clone(), fs::read(&path)?));
+    }
+    let (bootup, _) = index.read_canon("Pack/Bootup.pack")?;
+    let mut meta = match SarcMeta::load(&pack_dir)? {
+        Some(meta) => meta,
+        None => {
+            let (mut meta, _) = SarcMeta::from_binary(&bootup)?;
+            meta.vanilla = meta.entries.iter().filter_map(|e| e.name.clone()).collect();
+            meta
+        }
+    };
+    meta.vanilla.retain(|n| n != name);
+    fs::create_dir_all(&pack_dir)?;
+    meta.save(&pack_dir)?;
+    let data = Sarc::new(&bootup[..])?
+        .get(name)
+        .with_context(|| format!("Bootup.pack missing {}", name))?
+        .data
+        .to_vec();
+    Ok((path, data))
+}
+
+/// Copies the flag named `base` in a list of game data or save data flags to one named `new`.
+/// Returns whether the new flag was added, or `None` if the base flag isn't in the list.
+fn clone_flag(flags: &mut Vec<Byml>, base: &str, new: &str) -> Result<Option<bool>> {
+    let is_flag = |flag: &Byml, name: &str| {
+        flag.as_map()
+            .ok()
+            .and_then(|f| f.get("DataName"))
+            .and_then(|n| n.as_string().ok())
+            .map(|n| n.as_str() == name)
+            .unwrap_or(false)
+    };
+    if flags.iter().any(|f| is_flag(f, new)) {
+        return Ok(Some(false));
+    }
+    match flags.iter().find(|f| is_flag(f, base)).cloned() {
+        Some(mut flag) => {
+            let flag_map = flag.as_mut_map()?;
+            flag_map.insert("DataName".into(), Byml::String(new.into()));
+            flag_map.insert(
+                "HashValue".into(),
+                Byml::I32(CRC32.checksum(new.as_bytes()) as i32),
+            );
+            flags.push(flag);
+            Ok(Some(true))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Adds the `IsGet` flag of a cloned item to the game data, and to the format of the save file
+/// the base item's flag is saved in, since a flag missing from the save data is never saved
+fn add_is_get_flag(
+    index: &DumpIndex,
+    content: &Path,
+    be: bool,
+    base_actor: &str,
+    new_actor: &str,
+) -> Result<()> {
+    let endian = if be { Endian::Big } else { Endian::Little };
+    let (base_flag, new_flag) = (jstr!("IsGet_{base_actor}"), jstr!("IsGet_{new_actor}"));
+
+    let (gamedata_path, gamedata) = bootup_file(index, content, "GameData/gamedata.ssarc")?;
+    let gamedata = Sarc::new(gamedata)?;
+    let mut writer = SarcWriter::from_sarc(&gamedata);
+    let mut added = false;
+    for file in gamedata
+        .files()
+        .filter(|f| f.name().map(|n| n.contains("bool_data")).unwrap_or(false))
+    {
+        let mut bgdata = Byml::from_binary(file.data())?;
+        let flags = bgdata
+            .as_mut_map()?
+            .get_mut("bool_data")
+            .context("Invalid bool game data")?
+            .as_mut_array()?;
+        if let Some(changed) = clone_flag(flags, &base_flag, &new_flag)? {
+            if changed {
+                writer.add_file(file.name().unwrap(), bgdata.to_binary(endian));
+            }
+            added = true;
+            break;
+        }
+    }
+    if !added {
+        return Err(anyhow!("{} not found in game data", base_flag));
+    }
+    fs::create_dir_all(gamedata_path.parent().unwrap())?;
+    fs::write(gamedata_path, compress(writer.to_binary()))?;
+
+    let (savedata_path, savedata) = bootup_file(index, content, "GameData/savedataformat.ssarc")?;
+    let savedata = Sarc::new(savedata)?;
+    let mut writer = SarcWriter::from_sarc(&savedata);
+    let mut added = false;
+    for file in savedata
+        .files()
+        .filter(|f| f.name().map(|n| n.ends_with(".bgsvdata")).unwrap_or(false))
+    {
+        let mut svdata = Byml::from_binary(file.data())?;
+        // The first item is the save file the format is for, and the second its flags
+        let file_list = svdata
+            .as_mut_map()?
+            .get_mut("file_list")
+            .context("Invalid save data format")?
+            .as_mut_array()?;
+        let is_game_data = file_list
+            .first()
+            .and_then(|f| f.as_map().ok())
+            .and_then(|f| f.get("file_name"))
+            .and_then(|n| n.as_string().ok())
+            .map(|n| n.as_str() == "game_data.sav")
+            .unwrap_or(false);
+        if !is_game_data {
+            continue;
+        }
+        let flags = file_list
+            .get_mut(1)
+            .context("Invalid save data format")?
+            .as_mut_array()?;
+        if let Some(changed) = clone_flag(flags, &base_flag, &new_flag)? {
+            if changed {
+                writer.add_file(file.name().unwrap(), svdata.to_binary(endian));
+            }
+            added = true;
+            break;
+        }
+    }
+    if !added {
+        return Err(anyhow!("{} not found in save data format", base_flag));
+    }
+    fs::create_dir_all(savedata_path.parent().unwrap())?;
+    fs::write(savedata_path, compress(writer.to_binary()))?;
+    Ok(())
+}
+
+/// Finds a stock actor pack in the game files, whether loose, in the DLC, or inside
+/// `TitleBG.pack`. Also returns whether the actor came from `TitleBG.pack`.
+fn find_actor_pack(index: &DumpIndex, actor: &str) -> Result<(Vec<u8>, bool)> {
+    let canon = jstr!("Actor/Pack/{actor}.bactorpack");
+    let (data, location) = index
+        .read_canon(&canon)
+        .or_else(|_| index.read_canon(&jstr!("Aoc/0010/{&canon}")))
+        .with_context(|| format!("Base actor {} not found in game files", actor))?;
+    let title_actor = !location.nested.is_empty() && location.file.ends_with("TitleBG.pack");
+    Ok((data, title_actor))
+}
+
+/// Adds an actor to the `title_actors` option in the project's config.yml, creating it if needed
+fn add_title_actor(project: &Path, actor: &str) -> Result<()> {
+    let mut config = BuildConfig::load_or_default(project)?;
+    if !config.options.title_actors.iter().any(|a| a == actor) {
+        config.options.title_actors.push(actor.to_owned());
+        config.save(project)?;
+    }
+    Ok(())
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(rename_all = "kebab-case")]
 pub enum AddCommand {
     /// Add an actor to the current project, either modifying a vanilla actor or duplicating it as a new one
+    /// {n}Actors are found in the update, base game, or DLC files, or inside TitleBG.pack
     Actor {
         #[structopt(help = "Base actor name")]
         base_actor: String,
@@ -54,6 +716,13 @@ pub enum AddCommand {
             help = "Add only actor link/info, don't duplicate other files"
         )]
         minimal: bool,
+        #[structopt(
+            long,
+            short,
+            requires = "new-actor",
+            help = "Also clone the item name and description, inventory icon, and IsGet flag"
+        )]
+        item: bool,
     },
     /// Adds unbuilt actor info to the current project
     Actorinfo,
@@ -80,35 +749,44 @@ pub enum AddCommand {
         #[structopt(help = "Pack to add (`.pack` can be omitted)")]
         pack: String,
     },
+    /// Clone a stock shrine as a new one, renaming its pack, maps, overworld entrance, and other
+    /// files
+    Shrine {
+        #[structopt(help = "Base shrine name (e.g. `Dungeon000`)")]
+        base_shrine: String,
+        #[structopt(help = "New shrine name")]
+        new_shrine: String,
+    },
+    /// Add any single stock file to the mod, wherever it is found in the game files
+    File {
+        #[structopt(
+            help = "Canonical path of the file, e.g. `Actor/GeneralParamList/Enemy_Lynel_Senior.bgparamlist`"
+        )]
+        path: String,
+    },
 }
 
 impl AddCommand {
-    pub fn add_actor(&self, project: PathBuf, config: Settings, be: bool) -> Result<()> {
+    pub fn add_actor(&self, project: PathBuf, config: GameProfile, be: bool) -> Result<()> {
         if let AddCommand::Actor {
             base_actor,
             minimal,
             new_actor,
+            item,
         } = self
         {
-            let project = project.join(if be {
+            let root = project;
+            let project = root.join(if be {
                 "content"
             } else {
                 "01007EF00011E000/romfs"
             });
-            let base_pack = if be {
-                config.update_dir
-            } else {
-                config.game_dir_nx
-            }
-            .context("Game directory not set")?
-            .join(jstr!("Actor/Pack/{&base_actor}.sbactorpack"));
-            println!("Loading base actor pack...");
-            let sarc = Sarc::new(
-                fs::read(&base_pack)
-                    .with_context(|| format!("Base pack not found at {}", base_pack.display()))?,
-            )?;
+            status!("Loading base actor pack...");
+            let index = DumpIndex::load(&config, be)?;
+            let (data, title_actor) = find_actor_pack(&index, base_actor)?;
+            let sarc = Sarc::new(data)?;
             let actorlink = Some(jstr!("Actor/ActorLink/{&base_actor}.bxml"));
-            println!("Cloning actor files...");
+            status!("Cloning actor files...");
             for file in sarc.files() {
                 let (is_yml, out_data) = match &file.data[..4] {
                     b"AAMP" => (true, {
@@ -158,7 +836,7 @@ impl AddCommand {
                 }
             }
             if let Some(new_actor) = new_actor {
-                println!("Cloning base actor info...");
+                status!("Cloning base actor info...");
                 let actorinfo_root = project.join("Actor/ActorInfo");
                 if !actorinfo_root.exists() {
                     return Err(anyhow!("Cannot clone actor without actor info in mod"));
@@ -172,10 +850,20 @@ impl AddCommand {
                         actorinfo_root.join(jstr!("{&new_actor}.info.yml")),
                         info.to_text(),
                     )?;
-                    println!("Successfully cloned {} as {}", base_actor, new_actor);
+                    if title_actor {
+                        add_title_actor(&root, new_actor)?;
+                        status!("Added {} to title actors in config.yml", new_actor);
+                    }
+                    if *item {
+                        let profile = info["profile"]
+                            .as_string()
+                            .context("Base actor info has no profile")?;
+                        clone_item(&index, &project, be, profile, base_actor, new_actor)?;
+                    }
+                    status!("Successfully cloned {} as {}", base_actor, new_actor);
                 }
             } else {
-                println!("Successfully added {}", base_actor);
+                status!("Successfully added {}", base_actor);
             }
             Ok(())
         } else {
@@ -183,25 +871,16 @@ impl AddCommand {
         }
     }
 
-    pub fn add_actorinfo(&self, project: PathBuf, config: Settings, be: bool) -> Result<()> {
-        let base_path = if be {
-            config.update_dir
-        } else {
-            config.game_dir_nx
-        }
-        .context("Game directory not set")?
-        .join("Actor/ActorInfo.product.sbyml");
-        let unbuilder = Unbuilder {
-            be,
-            output: &project,
-            source: PathBuf::new(),
-        };
-        unbuilder.unbuild_actorinfo(&base_path)?;
-        println!("Actor info added to project");
+    pub fn add_actorinfo(&self, project: PathBuf, config: GameProfile, be: bool) -> Result<()> {
+        let index = DumpIndex::load(&config, be)?;
+        let (data, _) = index.read_canon("Actor/ActorInfo.product.byml")?;
+        let unbuilder = Unbuilder::new(be, &project, PathBuf::new());
+        unbuilder.unbuild_actorinfo_data(&data)?;
+        status!("Actor info added to project");
         Ok(())
     }
 
-    pub fn add_map(&self, project: PathBuf, config: Settings, be: bool) -> Result<()> {
+    pub fn add_map(&self, project: PathBuf, config: GameProfile, be: bool) -> Result<()> {
         if let Self::Map {
             unit,
             map_type,
@@ -213,34 +892,28 @@ impl AddCommand {
                 "dynamic" => "Dynamic".to_owned(),
                 _ => return Err(anyhow!("Invalid map unit type")),
             };
-            println!("Loading map...");
+            status!("Loading map...");
             let map_path = Path::new("Map")
                 .join(if *aocfield { "AocField" } else { "MainField" })
                 .join(jstr!("{&unit}/{&unit}_{&map_type}.smubin"));
-            let source = if be {
-                config.dlc_dir.or(config.update_dir)
-            } else {
-                config.dlc_dir_nx.or(config.game_dir_nx)
-            }
-            .context("Game directories not set")?
-            .join(&map_path);
-            let mubin = Byml::from_binary(decompress(fs::read(source)?)?)?;
+            let index = DumpIndex::load(&config, be)?;
+            let canon = map_path.with_extension("mubin").to_slash_lossy().into_owned();
+            let (data, location) = index
+                .read_canon(&jstr!("Aoc/0010/{&canon}"))
+                .or_else(|_| index.read_canon(&canon))?;
+            let mubin = Byml::from_binary(decompress(data)?)?;
             let out = project
-                .join(if be {
-                    "aoc/0010"
-                } else {
-                    "01007EF00011F001/romfs"
-                })
+                .join(content_root(location.source == Source::Dlc, be))
                 .join(&map_path)
                 .with_extension("smubin.yml");
             fs::create_dir_all(out.parent().unwrap())?;
             fs::write(out, mubin.to_text())?;
-            println!("Map {} {} added", &unit, map_type);
+            status!("Map {} {} added", &unit, map_type);
         };
         Ok(())
     }
 
-    pub fn add_event(&self, project: PathBuf, config: Settings, be: bool) -> Result<()> {
+    pub fn add_event(&self, project: PathBuf, config: GameProfile, be: bool) -> Result<()> {
         if let Self::Event {
             base_event,
             new_event,
@@ -252,17 +925,12 @@ impl AddCommand {
             } else {
                 "01007EF00011E000/romfs"
             });
-            let base_pack = if be {
-                config.update_dir
-            } else {
-                config.game_dir_nx
-            }
-            .context("Game directory not set")?
-            .join(jstr!("Event/{&base_event}.sbeventpack"));
-            println!("Loading base event pack...");
-            let sarc = Sarc::new(decompress(fs::read(&base_pack).with_context(|| {
-                format!("Base pack not found at {}", base_pack.display())
-            })?)?)?;
+            status!("Loading base event pack...");
+            let index = DumpIndex::load(&config, be)?;
+            let (data, _) = index
+                .read_canon(&jstr!("Event/{&base_event}.beventpack"))
+                .with_context(|| format!("Base event {} not found in game files", base_event))?;
+            let sarc = Sarc::new(data)?;
             for (file, data) in sarc
                 .files()
                 .filter_map(|file| file.name.map(|n| (n, file.data)))
@@ -297,7 +965,7 @@ impl AddCommand {
                 }
             }
             if let Some(new_event) = new_event {
-                println!("Cloning base event info...");
+                status!("Cloning base event info...");
                 let eventinfo_root = project.join("Event/EventInfo");
                 if !eventinfo_root.exists() {
                     return Err(anyhow!("Cannot clone event without event info in mod"));
@@ -333,49 +1001,395 @@ impl AddCommand {
                         eventinfo_root.join(jstr!("{&new_event}.info.yml")),
                         info.to_text(),
                     )?;
-                    println!("Successfully cloned {} as {}", base_event, new_event);
+                    status!("Successfully cloned {} as {}", base_event, new_event);
                 }
             } else {
-                println!("Successfully added {}", base_event);
+                status!("Successfully added {}", base_event);
             }
         };
         Ok(())
     }
 
-    pub fn add_pack(&self, project: PathBuf, config: Settings, be: bool) -> Result<()> {
+    pub fn add_pack(&self, project: PathBuf, config: GameProfile, be: bool) -> Result<()> {
         if let Self::Pack { pack } = self {
             let pack = pack.trim_end_matches(".pack");
-            let rel_path = jstr!("Pack/{pack}.pack");
-            let aoc_pack = AOC_PACKS.contains(&pack);
-            let base_path = match (aoc_pack, be) {
-                (true, true) => config.dlc_dir,
-                (true, false) => config.dlc_dir_nx,
-                (false, true) => config.update_dir,
-                (false, false) => config.game_dir_nx,
+            let index = DumpIndex::load(&config, be)?;
+            unbuild_pack(&index, &project, be, pack, pack)?;
+            status!("{}.pack added to project", &pack);
+        };
+        Ok(())
+    }
+
+    pub fn add_file(&self, project: PathBuf, config: GameProfile, be: bool) -> Result<()> {
+        if let Self::File { path } = self {
+            let index = DumpIndex::load(&config, be)?;
+            let rel = add_dump_file(&index, &project, path, be)?;
+            status!("{} added to project", rel.to_slash_lossy());
+        };
+        Ok(())
+    }
+
+    pub fn add_shrine(&self, project: PathBuf, config: GameProfile, be: bool) -> Result<()> {
+        if let Self::Shrine {
+            base_shrine,
+            new_shrine,
+        } = self
+        {
+            let index = DumpIndex::load(&config, be)?;
+            status!("Cloning shrine pack...");
+            let pack_dir = unbuild_pack(&index, &project, be, base_shrine, new_shrine)?;
+            rename_tree(&pack_dir, base_shrine, new_shrine)?;
+            status!("Cloning shrine map files...");
+            let unbuilder = Unbuilder::new(be, &project, PathBuf::new());
+            let marker = jstr!("CDungeon/{&base_shrine}/");
+            for (canon, location) in index
+                .iter()
+                .filter(|(c, l)| l.nested.is_empty() && c.contains(&marker))
+            {
+                let rel = Path::new(content_root(location.source == Source::Dlc, be))
+                    .join(replace_id(&location.file, base_shrine, new_shrine));
+                unbuilder
+                    .unbuild_file(&rel, &index.read(location)?)
+                    .with_context(|| format!("Failed to clone {}", canon))?;
+                let mut out_yml = project.join(&rel).into_os_string();
+                out_yml.push(".yml");
+                let out_yml = PathBuf::from(out_yml);
+                if out_yml.exists() {
+                    fs::write(
+                        &out_yml,
+                        replace_id(&fs::read_to_string(&out_yml)?, base_shrine, new_shrine),
+                    )?;
+                }
             }
-            .context("Game directory not set")?
-            .join(&rel_path);
-            let sarc = Sarc::new(fs::read(base_path)?)?;
-            let unbuilder = Unbuilder {
-                be,
-                output: &project,
-                source: PathBuf::new(),
-            };
-            unbuilder.unbuild_sarc(
-                sarc,
-                Some(
-                    &project
-                        .join(match (aoc_pack, be) {
-                            (true, true) => "aoc/0010",
-                            (true, false) => "01007EF00011F001/romfs",
-                            (false, true) => "content",
-                            (false, false) => "01007EF00011E000/romfs",
-                        })
-                        .join(&rel_path),
-                ),
-            )?;
-            println!("{}.pack added to project", &pack);
+            let content = project.join(content_root(false, be));
+            if project_languages(&content)?.is_empty() {
+                status!("No texts in project, skipping shrine names");
+            } else {
+                status!("Cloning shrine names...");
+                clone_messages(
+                    &index,
+                    &content,
+                    "StaticMsg/Dungeon.msyt",
+                    &[
+                        (base_shrine.to_owned(), new_shrine.to_owned()),
+                        (jstr!("{&base_shrine}_sub"), jstr!("{&new_shrine}_sub")),
+                    ],
+                )?;
+            }
+            status!("Cloning shrine actor info...");
+            let actors = clone_shrine_actorinfo(&index, &project, be, base_shrine, new_shrine)?;
+            if actors.is_empty() {
+                message::warning(
+                    Code::ManualStep,
+                    &format!(
+                        "No actor info found for the actors of {}, add it for {} by hand",
+                        base_shrine, new_shrine
+                    ),
+                );
+            }
+            status!("Cloning overworld entrance...");
+            let units = clone_overworld(&index, &project, be, base_shrine, new_shrine)?;
+            if units.is_empty() {
+                message::warning(
+                    Code::ManualStep,
+                    &format!(
+                        "No overworld entrance found for {}. Place an entrance to {} and its \
+                         travel point in the overworld map by hand.",
+                        base_shrine, new_shrine
+                    ),
+                );
+            } else {
+                status!(
+                    "Cloned the overworld entrance in {} at the same place as {}, move it to \
+                     where the new shrine goes",
+                    units.join(", "),
+                    base_shrine
+                );
+            }
+            status!("Successfully cloned {} as {}", base_shrine, new_shrine);
         };
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    fn map(entries: &[(&str, Byml)]) -> Byml {
+        Byml::Map(
+            entries
+                .iter()
+                .map(|(k, v)| ((*k).into(), v.clone()))
+                .collect(),
+        )
+    }
+
+    fn flag(name: &str) -> Byml {
+        map(&[
+            ("DataName", Byml::String(name.into())),
+            (
+                "HashValue",
+                Byml::I32(CRC32.checksum(name.as_bytes()) as i32),
+            ),
+        ])
+    }
+
+    /// Builds a fake game dump with a stock Bootup.pack, and a profile pointing to it. The saved
+    /// settings are left alone, so tests using this can run at the same time.
+    fn fake_dump(temp: &Path) -> GameProfile {
+        let dump = temp.join("dump/content");
+        fs::create_dir_all(dump.join("Pack")).unwrap();
+        let byml = |key: &str| compress(map(&[(key, Byml::I32(1))]).to_binary(Endian::Big));
+        let mut gamedata = SarcWriter::new(Endian::Big);
+        gamedata.add_file(
+            "/bool_data_0.bgdata",
+            map(&[(
+                "bool_data",
+                Byml::Array(vec![flag("IsGet_Weapon_Sword_001")]),
+            )])
+            .to_binary(Endian::Big),
+        );
+        let mut savedata = SarcWriter::new(Endian::Big);
+        for (i, (file_name, flags)) in [
+            ("game_data.sav", vec![flag("IsGet_Weapon_Sword_001")]),
+            ("option.sav", vec![flag("IsGet_Weapon_Sword_001")]),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            savedata.add_file(
+                &format!("/saveformat_{}.bgsvdata", i),
+                map(&[(
+                    "file_list",
+                    Byml::Array(vec![
+                        map(&[("file_name", Byml::String(file_name.into()))]),
+                        Byml::Array(flags),
+                    ]),
+                )])
+                .to_binary(Endian::Big),
+            );
+        }
+        let mut bootup = SarcWriter::new(Endian::Big);
+        bootup.add_file("Actor/AIDef/AIDef_Game.product.sbyml", byml("AI"));
+        bootup.add_file("Event/EventInfo.product.sbyml", byml("Event"));
+        bootup.add_file("Font/Test.bfarc", b"Stock font".to_vec());
+        bootup.add_file("GameData/gamedata.ssarc", compress(gamedata.to_binary()));
+        bootup.add_file(
+            "GameData/savedataformat.ssarc",
+            compress(savedata.to_binary()),
+        );
+        fs::write(dump.join("Pack/Bootup.pack"), bootup.to_binary()).unwrap();
+        GameProfile {
+            game_dir: Some(dump),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn replace_whole_ids() {
+        assert_eq!(
+            replace_id(
+                "Map/CDungeon/Dungeon000/Dungeon000_Static.mubin",
+                "Dungeon000",
+                "Dungeon200"
+            ),
+            "Map/CDungeon/Dungeon200/Dungeon200_Static.mubin"
+        );
+        assert_eq!(
+            replace_id(
+                "DgnMrgPrt_Dungeon000, Dungeon0001, XDungeon000, Dungeon000_sub",
+                "Dungeon000",
+                "Dungeon200"
+            ),
+            "DgnMrgPrt_Dungeon200, Dungeon0001, XDungeon000, Dungeon200_sub"
+        );
+    }
+
+    #[test]
+    fn clone_shrine_entrance() {
+        let obj = |hash_id: u32, name: &str, links: Vec<Byml>| {
+            map(&[
+                ("HashId", Byml::U32(hash_id)),
+                ("UnitConfigName", Byml::String(name.into())),
+                ("LinksToObj", Byml::Array(links)),
+            ])
+        };
+        let link = |dest: u32| map(&[("DestUnitHashId", Byml::U32(dest))]);
+        let mut unit = map(&[
+            (
+                "Objs",
+                Byml::Array(vec![
+                    obj(1, "DgnMrgPrt_Dungeon000", vec![link(2), link(3)]),
+                    map(&[
+                        ("HashId", Byml::U32(2)),
+                        ("UnitConfigName", Byml::String("Obj_WarpPoint".into())),
+                        (
+                            "!Parameters",
+                            map(&[(
+                                "WarpDestMapName",
+                                Byml::String("CDungeon/Dungeon000".into()),
+                            )]),
+                        ),
+                    ]),
+                    obj(3, "Obj_Other", vec![]),
+                    obj(4, "DgnMrgPrt_Dungeon0001", vec![]),
+                ]),
+            ),
+            (
+                "LocationMarker",
+                Byml::Array(vec![map(&[(
+                    "MessageID",
+                    Byml::String("Dungeon000".into()),
+                )])]),
+            ),
+        ]);
+        assert_eq!(
+            clone_field_entries(&mut unit, "Dungeon000", "Dungeon200").unwrap(),
+            3
+        );
+        let objs = unit["Objs"].as_array().unwrap();
+        assert_eq!(objs.len(), 6);
+        let (entrance, warp) = (&objs[4], &objs[5]);
+        assert_eq!(
+            entrance["UnitConfigName"].as_string().unwrap().as_str(),
+            "DgnMrgPrt_Dungeon200"
+        );
+        let ids: Vec<u32> = objs.iter().map(|o| o["HashId"].as_u32().unwrap()).collect();
+        assert!(!ids[..4].contains(&ids[4]) && !ids[..4].contains(&ids[5]));
+        // The link to the cloned warp point moves to the clone, the other stays
+        let links = entrance["LinksToObj"].as_array().unwrap();
+        assert_eq!(links[0]["DestUnitHashId"].as_u32().unwrap(), ids[5]);
+        assert_eq!(links[1]["DestUnitHashId"].as_u32().unwrap(), 3);
+        assert_eq!(
+            warp["!Parameters"]["WarpDestMapName"]
+                .as_string()
+                .unwrap()
+                .as_str(),
+            "CDungeon/Dungeon200"
+        );
+        assert_eq!(unit["LocationMarker"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn build_after_add_nested_file() {
+        let temp = TempDir::new("add");
+        let dump = fake_dump(&temp);
+        let index = DumpIndex::build(&dump, true).unwrap();
+        let project = temp.join("project");
+        fs::create_dir_all(project.join("content")).unwrap();
+        let rel = add_dump_file(
+            &index,
+            &project,
+            "Actor/AIDef/AIDef_Game.product.sbyml",
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            rel,
+            Path::new("content/Pack/Bootup.pack/Actor/AIDef/AIDef_Game.product.sbyml")
+        );
+        let mut vanilla = SarcMeta::load(&project.join("content/Pack/Bootup.pack"))
+            .unwrap()
+            .unwrap()
+            .vanilla;
+        vanilla.sort();
+        assert_eq!(
+            vanilla,
+            [
+                "Event/EventInfo.product.sbyml",
+                "Font/Test.bfarc",
+                "GameData/gamedata.ssarc",
+                "GameData/savedataformat.ssarc"
+            ]
+        );
+
+        crate::build(
+            crate::BuildOptions {
+                be: true,
+                source: Some(project.clone()),
+                output: Some(temp.join("build")),
+                no_deploy: true,
+                no_cache: true,
+                game_dump: Some(dump),
+                ..Default::default()
+            },
+            false,
+        )
+        .unwrap();
+        let built = fs::read(temp.join("build/content/Pack/Bootup.pack")).unwrap();
+        let built = Sarc::new(&built[..]).unwrap();
+        assert_eq!(built.len(), 5);
+        assert_eq!(
+            built.get_data("Font/Test.bfarc").unwrap(),
+            b"Stock font".as_slice()
+        );
+    }
+
+    #[test]
+    fn add_flag_to_game_and_save_data() {
+        let temp = TempDir::new("flag");
+        let dump = fake_dump(&temp);
+        let index = DumpIndex::build(&dump, true).unwrap();
+        let content = temp.join("project/content");
+        add_is_get_flag(
+            &index,
+            &content,
+            true,
+            "Weapon_Sword_001",
+            "Weapon_Sword_900",
+        )
+        .unwrap();
+        // Running it again doesn't add the flag twice
+        add_is_get_flag(
+            &index,
+            &content,
+            true,
+            "Weapon_Sword_001",
+            "Weapon_Sword_900",
+        )
+        .unwrap();
+
+        let flag_names = |flags: &Byml| -> Vec<String> {
+            flags
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|f| {
+                    f.as_map().unwrap()["DataName"]
+                        .as_string()
+                        .unwrap()
+                        .to_string()
+                })
+                .collect()
+        };
+        let pack_dir = content.join("Pack/Bootup.pack");
+        let gamedata =
+            Sarc::new(fs::read(pack_dir.join("GameData/gamedata.ssarc")).unwrap()).unwrap();
+        let bgdata = Byml::from_binary(gamedata.get_data("/bool_data_0.bgdata").unwrap()).unwrap();
+        assert_eq!(
+            flag_names(&bgdata.as_map().unwrap()["bool_data"]),
+            ["IsGet_Weapon_Sword_001", "IsGet_Weapon_Sword_900"]
+        );
+        let savedata =
+            Sarc::new(fs::read(pack_dir.join("GameData/savedataformat.ssarc")).unwrap()).unwrap();
+        let flags = |i: usize| {
+            let svdata = Byml::from_binary(
+                savedata
+                    .get_data(&format!("/saveformat_{}.bgsvdata", i))
+                    .unwrap(),
+            )
+            .unwrap();
+            flag_names(&svdata.as_map().unwrap()["file_list"].as_array().unwrap()[1])
+        };
+        assert_eq!(
+            flags(0),
+            ["IsGet_Weapon_Sword_001", "IsGet_Weapon_Sword_900"]
+        );
+        // Only the game data save file gets the flag
+        assert_eq!(flags(1), ["IsGet_Weapon_Sword_001"]);
+        let meta = SarcMeta::load(&pack_dir).unwrap().unwrap();
+        assert!(!meta.vanilla.iter().any(|n| n.starts_with("GameData/")));
+    }
+}
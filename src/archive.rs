@@ -0,0 +1,184 @@
+use anyhow::{anyhow, format_err, Context, Result};
+use std::{
+    collections::BTreeSet,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::mpsc::SyncSender,
+};
+
+/// Folder names which mark the root of mod files inside an archive, paired with the root they
+/// correspond to in a project.
+static MOD_ROOTS: &[(&str, &str)] = &[
+    ("01007EF00011E000/romfs/", "01007EF00011E000/romfs"),
+    ("01007EF00011F001/romfs/", "01007EF00011F001/romfs"),
+    ("aoc/0010/", "aoc/0010"),
+    ("content/", "content"),
+    ("romfs/", "01007EF00011E000/romfs"),
+];
+
+/// BCML keeps merge logs and optional content in these folders, neither of which belong in the
+/// unbuilt project.
+static BNP_EXCLUDE: &[&str] = &["logs/", "options/"];
+
+/// Most that is reserved up front for one file, since the size in an archive header could be
+/// anything. Larger files still read in full, growing the buffer as they go.
+const MAX_PREALLOC: u64 = 0x400_0000;
+
+#[inline]
+pub fn is_archive(file: &Path) -> bool {
+    file.is_file()
+        && file
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| ["zip", "7z", "bnp"].contains(&e.to_lowercase().as_str()))
+            .unwrap_or(false)
+}
+
+/// Maps a file name inside an archive to its path relative to a mod root folder, e.g.
+/// `MyMod/content/Actor/ActorInfo.product.sbyml` to `content/Actor/ActorInfo.product.sbyml`.
+pub fn mod_path(name: &str) -> Option<PathBuf> {
+    let name = name.replace('\\', "/");
+    MOD_ROOTS
+        .iter()
+        .filter_map(|(marker, root)| {
+            if name.starts_with(marker) {
+                Some((0, marker, root))
+            } else {
+                name.find(&["/", *marker].concat())
+                    .map(|i| (i + 1, marker, root))
+            }
+        })
+        .min_by_key(|(i, ..)| *i)
+        .map(|(i, marker, root)| Path::new(root).join(&name[i + marker.len()..]))
+}
+
+/// Sends the name and contents of every file in a zip, 7z, or BNP archive down the channel,
+/// one at a time, so they can be unbuilt without extracting the archive first.
+pub fn read_files(archive: &Path, tx: SyncSender<(String, Vec<u8>)>) -> Result<()> {
+    let ext = archive
+        .extension()
+        .and_then(|e| e.to_str())
+        .context("Archive has no extension")?
+        .to_lowercase();
+    let mut roots = ModRoots::default();
+    let mut send = |name: String, data: Vec<u8>| -> Result<()> {
+        roots.note(&name);
+        tx.send((name, data))
+            .map_err(|_| anyhow!("Unbuilding stopped before the archive was read"))
+    };
+    match ext.as_str() {
+        "zip" => {
+            let mut zip = zip::ZipArchive::new(fs_err::File::open(archive)?)
+                .with_context(|| format!("Failed to open zip archive {}", archive.display()))?;
+            for i in 0..zip.len() {
+                let mut file = zip.by_index(i)?;
+                if file.is_dir() {
+                    continue;
+                }
+                let mut data = Vec::with_capacity(file.size().min(MAX_PREALLOC) as usize);
+                file.read_to_end(&mut data)?;
+                send(file.name().to_owned(), data)?;
+            }
+        }
+        "7z" | "bnp" => {
+            let mut reader =
+                sevenz_rust::SevenZReader::open(archive, sevenz_rust::Password::empty())
+                    .with_context(|| {
+                        format!("Failed to open 7z archive {}", archive.display())
+                    })?;
+            let mut result = Ok(());
+            reader.for_each_entries(|entry, file| {
+                if entry.is_directory()
+                    || (ext == "bnp" && BNP_EXCLUDE.iter().any(|d| entry.name().starts_with(d)))
+                {
+                    // Solid archives still have to be read through to reach the next entry
+                    std::io::copy(file, &mut std::io::sink())?;
+                    return Ok(true);
+                }
+                let mut data = Vec::with_capacity(entry.size().min(MAX_PREALLOC) as usize);
+                file.read_to_end(&mut data)?;
+                result = send(entry.name().to_owned(), data);
+                Ok(result.is_ok())
+            })?;
+            result?;
+        }
+        _ => return Err(format_err!("Unsupported archive format: {}", ext)),
+    }
+    roots.check(archive)
+}
+
+/// Tracks whether an archive has any mod files, and which folders are at its top level
+#[derive(Debug, Default)]
+struct ModRoots {
+    found: bool,
+    top: BTreeSet<String>,
+}
+
+impl ModRoots {
+    fn note(&mut self, name: &str) {
+        self.found |= mod_path(name).is_some();
+        if let Some((dir, _)) = name.replace('\\', "/").split_once('/') {
+            self.top.insert(dir.to_owned());
+        }
+    }
+
+    /// Fails if the archive had no files in any folder a mod root is recognised by
+    fn check(&self, archive: &Path) -> Result<()> {
+        if self.found {
+            return Ok(());
+        }
+        Err(format_err!(
+            "No mod files found in {}, expected a content, aoc/0010, or romfs folder, but the \
+             top-level folders are: {}",
+            archive.display(),
+            if self.top.is_empty() {
+                "none".to_owned()
+            } else {
+                self.top.iter().cloned().collect::<Vec<_>>().join(", ")
+            }
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mod_path, ModRoots};
+    use std::path::Path;
+
+    #[test]
+    fn find_mod_roots() {
+        assert_eq!(
+            mod_path("content/Actor/ActorInfo.product.sbyml").unwrap(),
+            Path::new("content/Actor/ActorInfo.product.sbyml")
+        );
+        assert_eq!(
+            mod_path("BreathOfTheWild_Mod\\aoc\\0010\\Map\\MainField\\A-1").unwrap(),
+            Path::new("aoc/0010/Map/MainField/A-1")
+        );
+        assert_eq!(
+            mod_path("Mod/01007EF00011F001/romfs/Pack/AocMainField.pack").unwrap(),
+            Path::new("01007EF00011F001/romfs/Pack/AocMainField.pack")
+        );
+        assert_eq!(
+            mod_path("romfs/Pack/Bootup.pack").unwrap(),
+            Path::new("01007EF00011E000/romfs/Pack/Bootup.pack")
+        );
+        assert!(mod_path("info.json").is_none());
+    }
+
+    #[test]
+    fn no_mod_roots() {
+        let mut roots = ModRoots::default();
+        roots.note("info.json");
+        roots.note("MyMod/Actor/Pack/Obj_Test.sbactorpack");
+        roots.note("Docs\\readme.txt");
+        let err = roots.check(Path::new("mod.zip")).unwrap_err().to_string();
+        assert!(
+            err.ends_with("top-level folders are: Docs, MyMod"),
+            "{}",
+            err
+        );
+        roots.note("MyMod/content/Pack/Bootup.pack");
+        assert!(roots.check(Path::new("mod.zip")).is_ok());
+    }
+}
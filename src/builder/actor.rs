@@ -1,4 +1,7 @@
-use crate::util::*;
+use crate::{
+    diagnostic::{Code, Diagnostic, InFile},
+    util::*,
+};
 use anyhow::{Context, Result};
 use join_str::jstr;
 use path_slash::PathBufExt;
@@ -6,7 +9,6 @@ use phf::phf_map;
 use roead::{
     aamp::{hash_name, ParameterIO, ParameterListing},
     sarc::SarcWriter,
-    yaz0::compress,
 };
 use std::{
     fmt::Debug,
@@ -82,7 +84,7 @@ pub struct Link {
 }
 
 impl Link {
-    fn yaml_path(&self, user: &str) -> PathBuf {
+    pub fn yaml_path(&self, user: &str) -> PathBuf {
         Path::new("Actor")
             .join(self.path)
             .join(jstr!("{user}.{self.ext}.yml"))
@@ -175,6 +177,8 @@ pub static ACTOR_LINKS: phf::Map<u32, Link> = phf_map! {
 pub struct Actor<'a> {
     builder: &'a super::Builder,
     pub name: String,
+    /// The actor link the actor was found from
+    pub link: PathBuf,
     files: Vec<PathBuf>,
 }
 
@@ -189,64 +193,30 @@ impl<'a> Debug for Actor<'a> {
 
 impl<'a> Actor<'a> {
     pub fn new(builder: &'a super::Builder, file: &Path) -> Result<Option<Self>> {
-        let actor_link = ParameterIO::from_text(fs::read_to_string(file)?)?;
         let root = builder.source_content();
-        let files: Vec<PathBuf> = actor_link
-            .objects()
-            .get(hash_name("LinkTarget"))
-            .context("Actor link missing LinkTarget")?
-            .iter()
-            .filter(|(k, v)| ACTOR_LINKS.contains_key(&k.hash()) && v.as_str().unwrap() != "Dummy")
-            .map(|(k, v)| -> Result<Vec<PathBuf>> {
-                let file = root.join(
-                    ACTOR_LINKS
-                        .get(&k.hash())
-                        .unwrap()
-                        .yaml_path(v.as_str().unwrap()),
-                );
-                let mut files: Vec<PathBuf> = vec![file.clone()];
-                match k.hash() {
-                    110127898 => {
-                        // ASUser
-                        files.extend(process_aslist(&file)?);
-                    }
-                    1086735552 => {
-                        // AttentionUser
-                        files.extend(process_attcllist(&file)?);
-                    }
-                    4022948047 => {
-                        // RgConfigListUser
-                        files.extend(process_rgconfiglist(&file)?);
-                    }
-                    2366604039 => {
-                        // PhysicsUser
-                        files.extend(process_physics(&file)?);
-                    }
-                    _ => {}
-                }
-                Ok(files)
-            })
-            .collect::<Result<Vec<_>>>()?
-            .into_iter()
-            .flatten()
-            .chain([file.to_owned()])
-            .collect();
+        let files = actor_files(&root, file)?;
+        let name = file
+            .with_extension("")
+            .file_stem()
+            .context("Missing filename")?
+            .to_string_lossy()
+            .into_owned();
+        let variant_root = root.join("Variants").join(jstr!("{&name}.sbactorpack"));
         if files
             .iter()
             .chain(&[file.to_owned()])
             .any(|f| builder.modified_files.contains(f))
+            || builder
+                .modified_files
+                .any(|f| f.starts_with(&variant_root))
         {
-            let name = file
-                .with_extension("")
-                .file_stem()
-                .unwrap()
-                .to_string_lossy()
-                .into_owned();
             builder.vprint(&jstr!("Actor {&name} modified"));
+            builder.expect_files(&jstr!("{&name}.sbactorpack"), &files);
             Ok(Some(Self {
                 builder,
                 files,
                 name,
+                link: file.to_owned(),
             }))
         } else {
             Ok(None)
@@ -257,26 +227,33 @@ impl<'a> Actor<'a> {
         self.builder.vprint(&jstr!("Building actor {&self.name}"));
         let mut pack = SarcWriter::new(self.builder.endian());
         let root = self.builder.source.join(&self.builder.content);
+        let pack_name = jstr!("{&self.name}.sbactorpack");
         self.files.into_iter().try_for_each(|f| -> Result<()> {
             let mut filename = f.strip_prefix(&root)?.to_owned();
             if get_ext(&filename)? == "yml" {
                 filename = filename.with_extension("");
             }
+            let f = self.builder.pack_variant(&pack_name, &f).unwrap_or(f);
             match self.builder.get_resource_data(&f) {
                 Ok(data) => pack.add_file(
                     filename.to_slash_lossy(),
-                    data,
+                    data.to_vec(),
                 ),
                 Err(e) => {
-                    if let Some(err) = e.downcast_ref::<std::io::Error>() {
-                        if let std::io::ErrorKind::NotFound = err.kind() {
-                            if filename.starts_with("Physics") {
-                                self.builder.warn(&(
-                                    jstr!("Havok file {&f.to_slash_lossy()} not found for actor {&self.name}.\n")
-                                    + "Ignore if intentionally using a file not in the actor pack."))?;
-                                return Ok(())
-                            }
+                    if e.downcast_ref::<Diagnostic>().map(|d| d.code) == Some(Code::MissingFile) {
+                        if filename.starts_with("Physics") {
+                            self.builder.warn(
+                                Code::MissingHavok,
+                                &f,
+                                &jstr!("Havok file not found, but actor {&self.name} uses it. Ignore if it's intentionally not in the actor pack."),
+                            )?;
+                            return Ok(())
                         }
+                        return Err(Diagnostic::missing_file(
+                            &f,
+                            jstr!("File not found, but actor {&self.name} uses it"),
+                        )
+                        .into());
                     }
                     return Err(e);
                 }
@@ -287,13 +264,71 @@ impl<'a> Actor<'a> {
         let data = pack.to_binary();
         self.builder
             .set_resource_size(&jstr!("Actor/Pack/{&self.name}.bactorpack"), &data);
-        Ok(compress(data))
+        Ok(self.builder.compress(data))
     }
 }
 
+/// Lists every file used by an actor, from its actor link down to files referenced by its
+/// AS list, attention client list, ragdoll config list, and physics
+pub fn actor_files(root: &Path, link: &Path) -> Result<Vec<PathBuf>> {
+    let actor_link = ParameterIO::from_text(fs::read_to_string(link)?)
+        .map_err(|e| Diagnostic::syntax(link, "AAMP", e))?;
+    Ok(actor_link
+        .objects()
+        .get(hash_name("LinkTarget"))
+        .context("Actor link missing LinkTarget")
+        .in_file(link)?
+        .iter()
+        .filter(|(k, _)| ACTOR_LINKS.contains_key(&k.hash()))
+        .map(|(k, v)| -> Result<Vec<PathBuf>> {
+            let target = v
+                .as_str()
+                .context("Actor link has a user which isn't a string")
+                .in_file(link)?;
+            if target == "Dummy" {
+                return Ok(vec![]);
+            }
+            let file = root.join(ACTOR_LINKS.get(&k.hash()).unwrap().yaml_path(target));
+            let mut files: Vec<PathBuf> = vec![file.clone()];
+            // A missing file is left for the caller to report, so the rest can still be resolved
+            if !file.exists() {
+                return Ok(files);
+            }
+            match k.hash() {
+                110127898 => {
+                    // ASUser
+                    files.extend(process_aslist(&file).in_file(&file)?);
+                }
+                1086735552 => {
+                    // AttentionUser
+                    files.extend(process_attcllist(&file).in_file(&file)?);
+                }
+                4022948047 => {
+                    // RgConfigListUser
+                    files.extend(process_rgconfiglist(&file).in_file(&file)?);
+                }
+                2366604039 => {
+                    // PhysicsUser
+                    files.extend(process_physics(&file).in_file(&file)?);
+                }
+                _ => {}
+            }
+            Ok(files)
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .chain([link.to_owned()])
+        .collect())
+}
+
 fn process_aslist(aslist_path: &Path) -> Result<Vec<PathBuf>> {
     let aslist = parse_aamp(aslist_path)?;
-    let asroot = aslist_path.parent().unwrap().parent().unwrap().join("AS");
+    let asroot = aslist_path
+        .ancestors()
+        .nth(2)
+        .context("Invalid AS list path")?
+        .join("AS");
     Ok(aslist
         .lists()
         .get(hash_name("ASDefines"))
@@ -318,10 +353,9 @@ fn process_aslist(aslist_path: &Path) -> Result<Vec<PathBuf>> {
 fn process_attcllist(attcllist_path: &Path) -> Result<Vec<PathBuf>> {
     let attcllist = parse_aamp(attcllist_path)?;
     let attclroot = attcllist_path
-        .parent()
-        .unwrap()
-        .parent()
-        .unwrap()
+        .ancestors()
+        .nth(2)
+        .context("Invalid attention client list path")?
         .join("AttClient");
     Ok(attcllist
         .lists()
@@ -347,10 +381,9 @@ fn process_attcllist(attcllist_path: &Path) -> Result<Vec<PathBuf>> {
 fn process_rgconfiglist(rgconfig_path: &Path) -> Result<Vec<PathBuf>> {
     let rgconfig = parse_aamp(rgconfig_path)?;
     let rgconfig_root = rgconfig_path
-        .parent()
-        .unwrap()
-        .parent()
-        .unwrap()
+        .ancestors()
+        .nth(2)
+        .context("Invalid ragdoll config list path")?
         .join("RagdollConfig");
     Ok(rgconfig
         .lists()
@@ -376,12 +409,9 @@ fn process_rgconfiglist(rgconfig_path: &Path) -> Result<Vec<PathBuf>> {
 fn process_physics(physics_path: &Path) -> Result<Vec<PathBuf>> {
     let physics = parse_aamp(physics_path)?;
     let physics_root = physics_path
-        .parent()
-        .unwrap()
-        .parent()
-        .unwrap()
-        .parent()
-        .unwrap()
+        .ancestors()
+        .nth(3)
+        .context("Invalid physics path")?
         .join("Physics");
     let param_set = physics
         .lists()
@@ -458,24 +488,23 @@ fn process_physics(physics_path: &Path) -> Result<Vec<PathBuf>> {
         .as_int::<u32>()?
         > 0
     {
-        files.extend(
-            param_set
-                .lists
-                .get(hash_name("RigidBodySet"))
-                .context("Physics missing RigidBodySet")?
-                .lists
-                .0
-                .values()
-                .filter_map(|l| {
-                    l.objects
-                        .get(4288596824)
-                        .context("RigidBody missing 4288596824")
-                        .unwrap()
-                        .get(hash_name("setup_file_path"))
-                        .and_then(|p| p.as_str().ok())
-                        .map(|p| physics_root.join("RigidBody").join(p))
-                }),
-        )
+        for set in param_set
+            .lists
+            .get(hash_name("RigidBodySet"))
+            .context("Physics missing RigidBodySet")?
+            .lists
+            .0
+            .values()
+        {
+            files.extend(
+                set.objects
+                    .get(4288596824)
+                    .context("RigidBody missing 4288596824")?
+                    .get(hash_name("setup_file_path"))
+                    .and_then(|p| p.as_str().ok())
+                    .map(|p| physics_root.join("RigidBody").join(p)),
+            )
+        }
     }
     Ok(files)
 }
@@ -0,0 +1,289 @@
+use rayon::prelude::*;
+use roead::yaz0::compress_with_level;
+use std::{
+    convert::Infallible,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
+};
+
+/// Bumped whenever the same input would be compiled differently, so old artifacts are ignored
+const CACHE_VERSION: u32 = 2;
+
+/// Inputs smaller than this are quicker to compile again than to look up
+const MIN_SIZE: usize = 0x1000;
+
+/// The most the cache may hold after a build, in bytes, unless the `cache_size` option says
+/// otherwise. Past this, the artifacts used least recently are deleted.
+pub const DEFAULT_MAX_SIZE: u64 = 2 << 30;
+
+static KINDS: &[Artifact] = &[Artifact::Aamp, Artifact::Byml, Artifact::Yaz0];
+
+/// What an artifact was made by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Artifact {
+    /// Binary AAMP compiled from YAML
+    Aamp,
+    /// Binary BYML compiled from YAML, for one platform
+    Byml,
+    /// Yaz0 compressed data, at one compression level
+    Yaz0,
+}
+
+impl Artifact {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Aamp => "aamp",
+            Self::Byml => "byml",
+            Self::Yaz0 => "yaz0",
+        }
+    }
+}
+
+/// Compiled and compressed files stored on disk by a hash of what they were made from, so they
+/// can be reused by any project, branch, or clean build which has the same source. Keys also
+/// cover the Hyrule Builder version and the settings which change the output, so a stale
+/// artifact is never used. The cache is only an optimisation: files which can't be read are
+/// made again, and files which can't be written are skipped.
+#[derive(Debug)]
+pub struct ArtifactCache {
+    root: PathBuf,
+    be: bool,
+    compression: u8,
+    /// The most the cache may hold after a build, in bytes
+    max_size: u64,
+    reused: AtomicUsize,
+    made: AtomicUsize,
+    /// Artifacts reused by this build, to be marked as used when it finishes
+    used: Mutex<Vec<PathBuf>>,
+}
+
+impl ArtifactCache {
+    pub fn new(root: PathBuf, be: bool, compression: u8, max_size: u64) -> Self {
+        Self {
+            root,
+            be,
+            compression,
+            max_size,
+            reused: AtomicUsize::new(0),
+            made: AtomicUsize::new(0),
+            used: Mutex::default(),
+        }
+    }
+
+    /// The shared cache folder, next to the settings
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs2::data_local_dir().map(|dir| dir.join("hyrule_builder/artifacts"))
+    }
+
+    fn path(&self, kind: Artifact, input: &[u8]) -> PathBuf {
+        let params: &[u8] = match kind {
+            Artifact::Aamp => &[],
+            Artifact::Byml => &[self.be as u8],
+            Artifact::Yaz0 => &[self.compression],
+        };
+        let key = blake3::Hasher::new()
+            .update(env!("CARGO_PKG_VERSION").as_bytes())
+            .update(&CACHE_VERSION.to_le_bytes())
+            .update(kind.as_str().as_bytes())
+            .update(params)
+            .update(input)
+            .finalize()
+            .to_hex();
+        self.root
+            .join(kind.as_str())
+            .join(&key[..2])
+            .join(&key[2..])
+    }
+
+    /// Writes through a temporary file, so other builds never read half an artifact
+    fn store(path: &Path, data: &[u8]) -> std::io::Result<()> {
+        static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        std::fs::create_dir_all(path.parent().expect("Artifacts are always in a folder"))?;
+        let temp = path.with_extension(format!(
+            "{}-{}.tmp",
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&temp, data)?;
+        std::fs::rename(&temp, path).inspect_err(|_| {
+            let _ = std::fs::remove_file(&temp);
+        })
+    }
+
+    fn get_or_insert<E>(
+        &self,
+        kind: Artifact,
+        input: &[u8],
+        make: impl FnOnce() -> Result<Vec<u8>, E>,
+    ) -> Result<Vec<u8>, E> {
+        if input.len() < MIN_SIZE {
+            return make();
+        }
+        let path = self.path(kind, input);
+        if let Ok(data) = std::fs::read(&path) {
+            self.reused.fetch_add(1, Ordering::Relaxed);
+            self.used.lock().unwrap().push(path);
+            return Ok(data);
+        }
+        let data = make()?;
+        self.made.fetch_add(1, Ordering::Relaxed);
+        let _ = Self::store(&path, &data);
+        Ok(data)
+    }
+
+    /// Compiles a YAML source file with `make`, or gets it from the cache if the same source
+    /// was compiled before
+    pub fn compile(
+        &self,
+        kind: Artifact,
+        source: &[u8],
+        make: impl FnOnce() -> anyhow::Result<Vec<u8>>,
+    ) -> anyhow::Result<Vec<u8>> {
+        self.get_or_insert(kind, source, make)
+    }
+
+    /// Yaz0 compresses data, or gets it from the cache if the same data was compressed before
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        self.get_or_insert(Artifact::Yaz0, data, || {
+            Ok::<_, Infallible>(compress_with_level(data, self.compression))
+        })
+        .unwrap_or_else(|e| match e {})
+    }
+
+    /// Deletes the artifacts used least recently until the cache is no larger than `max_size`
+    /// bytes, returning how many were deleted
+    pub fn trim(&self, max_size: u64) -> usize {
+        let mut artifacts: Vec<(SystemTime, u64, PathBuf)> = KINDS
+            .iter()
+            .filter_map(|kind| std::fs::read_dir(self.root.join(kind.as_str())).ok())
+            .flatten()
+            .filter_map(|dir| std::fs::read_dir(dir.ok()?.path()).ok())
+            .flatten()
+            .filter_map(|file| {
+                let file = file.ok()?;
+                let meta = file.metadata().ok()?;
+                let path = file.path();
+                // Artifacts still being written by another build are left alone
+                if !meta.is_file() || path.extension().is_some() {
+                    return None;
+                }
+                Some((meta.modified().ok()?, meta.len(), path))
+            })
+            .collect();
+        let mut size: u64 = artifacts.iter().map(|(_, len, _)| len).sum();
+        if size <= max_size {
+            return 0;
+        }
+        artifacts.sort_unstable_by_key(|(used, ..)| *used);
+        let mut removed = 0;
+        for (_, len, path) in artifacts {
+            if size <= max_size {
+                break;
+            }
+            if std::fs::remove_file(path).is_ok() {
+                size -= len;
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// Marks every artifact reused by this build as just used, by its modified time, and trims
+    /// the cache to its size limit if anything was added to it. Returns how many artifacts were
+    /// deleted.
+    pub fn finish(&self) -> usize {
+        let used = std::mem::take(&mut *self.used.lock().unwrap());
+        let now = SystemTime::now();
+        used.par_iter().for_each(|path| {
+            let _ = std::fs::File::options()
+                .write(true)
+                .open(path)
+                .and_then(|f| f.set_modified(now));
+        });
+        if self.made.load(Ordering::Relaxed) > 0 {
+            self.trim(self.max_size)
+        } else {
+            0
+        }
+    }
+
+    /// How many artifacts were reused from the cache, and how many were made and stored
+    pub fn stats(&self) -> (usize, usize) {
+        (
+            self.reused.load(Ordering::Relaxed),
+            self.made.load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn reuse_artifacts() {
+        let root = TempDir::new("artifacts");
+        let cache = ArtifactCache::new(root.to_path_buf(), true, 7, DEFAULT_MAX_SIZE);
+        let source = vec![b'a'; MIN_SIZE];
+        let compiled = cache.compile(Artifact::Byml, &source, || Ok(vec![1, 2, 3]));
+        assert_eq!(compiled.unwrap(), vec![1, 2, 3]);
+        let compiled = cache.compile(Artifact::Byml, &source, || panic!("Should be cached"));
+        assert_eq!(compiled.unwrap(), vec![1, 2, 3]);
+        assert_eq!(cache.stats(), (1, 1));
+
+        // Artifacts for the other platform or a different source aren't shared
+        let nx = ArtifactCache::new(root.to_path_buf(), false, 7, DEFAULT_MAX_SIZE);
+        assert_ne!(
+            nx.path(Artifact::Byml, &source),
+            cache.path(Artifact::Byml, &source)
+        );
+        assert_eq!(
+            nx.path(Artifact::Aamp, &source),
+            cache.path(Artifact::Aamp, &source)
+        );
+        assert_ne!(
+            cache.path(Artifact::Byml, &source),
+            cache.path(Artifact::Byml, &source[1..])
+        );
+    }
+
+    #[test]
+    fn trim_least_recently_used() {
+        let root = TempDir::new("artifacts_trim");
+        let cache = ArtifactCache::new(root.to_path_buf(), true, 7, 12);
+        let sources: Vec<Vec<u8>> = (0..3).map(|i| vec![i; MIN_SIZE]).collect();
+        for (i, source) in sources.iter().enumerate() {
+            cache
+                .compile(Artifact::Aamp, source, || Ok(vec![0; 4]))
+                .unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(cache.path(Artifact::Aamp, source))
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(i as u64))
+                .unwrap();
+        }
+        // Using the oldest makes it the newest once the build finishes
+        cache
+            .compile(Artifact::Aamp, &sources[0], || panic!("Should be cached"))
+            .unwrap();
+        assert_eq!(cache.finish(), 0);
+        assert_eq!(cache.trim(8), 1);
+        assert!(!cache.path(Artifact::Aamp, &sources[1]).exists());
+        assert!(cache.path(Artifact::Aamp, &sources[0]).exists());
+        assert!(cache.path(Artifact::Aamp, &sources[2]).exists());
+
+        // A build which only reuses artifacts leaves the cache as it is, even past its limit
+        let reader = ArtifactCache::new(root.to_path_buf(), true, 7, 0);
+        reader
+            .compile(Artifact::Aamp, &sources[2], || panic!("Should be cached"))
+            .unwrap();
+        assert_eq!(reader.finish(), 0);
+        assert!(cache.path(Artifact::Aamp, &sources[2]).exists());
+    }
+}
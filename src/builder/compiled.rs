@@ -0,0 +1,148 @@
+use anyhow::Result;
+use rustc_hash::FxBuildHasher;
+use scc::HashMap as SyncMap;
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// A compiled file, shared without copying by every pack which uses it. A `SarcWriter` owns its
+/// files, so each pack still makes one copy when it adds the file.
+pub type Buffer = Arc<[u8]>;
+
+#[derive(Debug, Default)]
+struct Entry {
+    /// The compiled file, locked while the first pack to ask for it compiles it, so packs
+    /// asking at the same time wait for it instead of compiling it again
+    data: Arc<Mutex<Option<Buffer>>>,
+    /// How many packs still to be built will ask for the file
+    pending: usize,
+}
+
+/// Compiled project files, kept only while a pack still to be built needs them. Packs declare
+/// the files they use with [`Compiled::expect`] before the build starts, and each use counts
+/// one down, so a shared animation used by hundreds of actors is compiled once and dropped after
+/// the last of them is built. Files no pack is waiting for are never kept.
+#[derive(Debug, Default)]
+pub struct Compiled {
+    entries: SyncMap<PathBuf, Entry, FxBuildHasher>,
+}
+
+impl Compiled {
+    /// Notes that a pack will ask for a file once
+    pub fn expect(&self, file: PathBuf) {
+        self.entries.entry(file).or_default().get_mut().pending += 1;
+    }
+
+    /// Gets a compiled file if it's kept, or compiles it with `compile`, counting one use of it.
+    /// If another pack is compiling the same file, waits for it to finish and uses its result.
+    pub fn get_or_compile(
+        &self,
+        file: &Path,
+        compile: impl FnOnce() -> Result<Buffer>,
+    ) -> Result<Buffer> {
+        let slot = match self.entries.get(file) {
+            Some(entry) => entry.get().data.clone(),
+            None => return compile(),
+        };
+        let data = {
+            // A pack which panicked while compiling left nothing behind, so try again
+            let mut data = slot.lock().unwrap_or_else(|e| e.into_inner());
+            match data.as_ref() {
+                Some(data) => data.clone(),
+                None => data.insert(compile()?).clone(),
+            }
+        };
+        if let Some(mut entry) = self.entries.get(file) {
+            let value = entry.get_mut();
+            value.pending = value.pending.saturating_sub(1);
+            if value.pending == 0 {
+                let _ = entry.remove();
+            }
+        }
+        Ok(data)
+    }
+
+    /// Keeps a built file, such as a TitleBG actor pack, until a pack built later takes it
+    pub fn put(&self, file: PathBuf, data: Buffer) {
+        self.entries.entry(file).or_default().get_mut().data = Arc::new(Mutex::new(Some(data)));
+    }
+
+    /// Takes every kept file inside a folder or pack
+    pub fn take_all(&self, prefix: &Path) -> Vec<(PathBuf, Buffer)> {
+        let mut files = vec![];
+        self.entries.scan(|path, entry| {
+            if path.starts_with(prefix) {
+                files.push(path.clone());
+            }
+        });
+        files
+            .into_iter()
+            .filter_map(|path| {
+                self.entries.remove(&path).and_then(|(path, entry)| {
+                    let data = entry.data.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                    Some((path, data?))
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_after_last_use() {
+        let compiled = Compiled::default();
+        let shared = Path::new("Actor/AS/Shared.bas.yml");
+        let once = Path::new("Actor/AS/Once.bas.yml");
+        for _ in 0..3 {
+            compiled.expect(shared.to_owned());
+        }
+        compiled.expect(once.to_owned());
+        let make = |data: &'static [u8]| move || -> Result<Buffer> { Ok(Buffer::from(data)) };
+        compiled.get_or_compile(once, make(b"once")).unwrap();
+        assert!(compiled.entries.get(once).is_none());
+        let first = compiled.get_or_compile(shared, make(b"shared")).unwrap();
+        let second = compiled
+            .get_or_compile(shared, || panic!("Should be kept"))
+            .unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        compiled.get_or_compile(shared, make(b"shared")).unwrap();
+        assert!(compiled.entries.is_empty());
+
+        compiled.put("TitleBG.pack/Actor/Pack/Test.sbactorpack".into(), first);
+        assert_eq!(compiled.take_all(Path::new("TitleBG.pack")).len(), 1);
+        assert!(compiled.entries.is_empty());
+    }
+
+    #[test]
+    fn compiles_once_at_a_time() {
+        let compiled = Compiled::default();
+        let file = Path::new("Actor/AS/Shared.bas.yml");
+        for _ in 0..8 {
+            compiled.expect(file.to_owned());
+        }
+        let compiles = std::sync::atomic::AtomicUsize::new(0);
+        let results: Vec<Buffer> = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        compiled
+                            .get_or_compile(file, || {
+                                compiles.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                                std::thread::sleep(std::time::Duration::from_millis(50));
+                                Ok(Buffer::from(&b"shared"[..]))
+                            })
+                            .unwrap()
+                    })
+                })
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        assert_eq!(compiles.into_inner(), 1);
+        assert!(results.iter().all(|r| Arc::ptr_eq(r, &results[0])));
+        assert!(compiled.entries.is_empty());
+    }
+}
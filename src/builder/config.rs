@@ -1 +1,785 @@
+use super::WarnLevel;
+use crate::{diagnostic::Code, message};
+use anyhow::{anyhow, Context, Result};
+use fs_err as fs;
+use path_slash::PathExt;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    path::{Path, PathBuf},
+};
 
+/// The Yaz0 compression levels supported, from fastest to smallest
+pub const COMPRESSION_LEVELS: std::ops::RangeInclusive<u8> = 6..=9;
+pub const DEFAULT_COMPRESSION: u8 = 7;
+
+/// A switch which can be turned on in the `Flags` section of `config.yml`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Flag {
+    Be,
+    #[serde(alias = "hard-warnings")]
+    HardWarnings,
+    #[serde(alias = "ignore-warnings")]
+    IgnoreWarnings,
+    Verbose,
+    /// Ignore the state of the last build, so the output only depends on the project files
+    Reproducible,
+    /// Build everything possible after an error
+    #[serde(alias = "keep-going")]
+    KeepGoing,
+    /// Compile and compress every file again instead of using the artifact cache
+    #[serde(alias = "no-cache")]
+    NoCache,
+}
+
+/// Accepts a list either as a YAML sequence or as a comma separated string
+fn list_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum List {
+        Items(Vec<String>),
+        Joined(String),
+    }
+    Ok(match List::deserialize(deserializer)? {
+        List::Items(items) => items,
+        List::Joined(joined) => joined
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_owned())
+            .collect(),
+    })
+}
+
+/// The values which can be set in the `Options` section of `config.yml`
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Options {
+    /// Custom actors to add to `TitleBG.pack`
+    #[serde(
+        default,
+        alias = "title-actors",
+        deserialize_with = "list_or_string",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub title_actors: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    /// The game profile to find stock files with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// The Yaz0 compression level
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<u8>,
+    /// A folder to mirror the output into after each successful build
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deploy: Option<PathBuf>,
+    /// The folder for the artifact cache, if not the one shared by every project
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<PathBuf>,
+    /// The most the artifact cache may hold after a build, in MB
+    #[serde(default, alias = "cache-size", skip_serializing_if = "Option::is_none")]
+    pub cache_size: Option<u64>,
+}
+
+impl Options {
+    /// Overrides these options with any set in `other`
+    fn merge(&mut self, other: Options) {
+        for actor in other.title_actors {
+            if !self.title_actors.contains(&actor) {
+                self.title_actors.push(actor);
+            }
+        }
+        self.output = other.output.or(self.output.take());
+        self.profile = other.profile.or(self.profile.take());
+        self.compression = other.compression.or(self.compression);
+        self.deploy = other.deploy.or(self.deploy.take());
+        self.cache = other.cache.or(self.cache.take());
+        self.cache_size = other.cache_size.or(self.cache_size);
+    }
+}
+
+/// Hides warnings with a code for files matching any of the glob patterns, given relative to
+/// the project, e.g. `content/Physics/Cloth/Npc_*`
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Suppression {
+    pub code: Code,
+    #[serde(deserialize_with = "list_or_string")]
+    pub paths: Vec<String>,
+}
+
+/// How each kind of warning is reported, set in the `Warnings` section of `config.yml`. Codes
+/// not listed follow `ignore_warnings` and `hard_warnings`.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Warnings {
+    /// Warnings which are never shown
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<Code>,
+    /// Warnings which are shown but never fail the build
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warn: Vec<Code>,
+    /// Warnings which fail the build
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<Code>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suppress: Vec<Suppression>,
+}
+
+impl Warnings {
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Overrides these levels with any set in `other`
+    fn merge(&mut self, other: Warnings) {
+        for (codes, level) in [
+            (other.allow, WarnLevel::None),
+            (other.warn, WarnLevel::Warn),
+            (other.deny, WarnLevel::Error),
+        ] {
+            for code in codes {
+                self.allow.retain(|c| *c != code);
+                self.warn.retain(|c| *c != code);
+                self.deny.retain(|c| *c != code);
+                match level {
+                    WarnLevel::None => self.allow.push(code),
+                    WarnLevel::Warn => self.warn.push(code),
+                    WarnLevel::Error => self.deny.push(code),
+                }
+            }
+        }
+        self.suppress.extend(other.suppress);
+    }
+
+    fn validate(&self) -> Result<()> {
+        for code in self
+            .allow
+            .iter()
+            .chain(&self.warn)
+            .chain(&self.deny)
+            .chain(self.suppress.iter().map(|s| &s.code))
+        {
+            if !code.is_build_warning() {
+                return Err(anyhow!("{} is not a build warning code", code));
+            }
+        }
+        for pattern in self.suppress.iter().flat_map(|s| &s.paths) {
+            glob::Pattern::new(pattern)
+                .with_context(|| format!("Invalid warning suppression path {}", pattern))?;
+        }
+        Ok(())
+    }
+
+    /// How to report a warning about a file relative to the project, where `default` is the
+    /// level for codes without one of their own
+    pub fn level(&self, code: Code, file: &Path, default: WarnLevel) -> WarnLevel {
+        let file = file.to_slash_lossy();
+        if self.suppress.iter().any(|s| {
+            s.code == code
+                && s.paths.iter().any(|p| {
+                    glob::Pattern::new(p)
+                        .map(|p| p.matches(&file))
+                        .unwrap_or(false)
+                })
+        }) {
+            WarnLevel::None
+        } else if self.deny.contains(&code) {
+            WarnLevel::Error
+        } else if self.warn.contains(&code) {
+            WarnLevel::Warn
+        } else if self.allow.contains(&code) {
+            WarnLevel::None
+        } else {
+            default
+        }
+    }
+}
+
+/// A named set of flags and options, applied over the main ones with `build --profile`
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct BuildProfile {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<Flag>,
+    #[serde(default)]
+    pub options: Options,
+    #[serde(default, skip_serializing_if = "Warnings::is_empty")]
+    pub warnings: Warnings,
+}
+
+/// A project's `config.yml`
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase", deny_unknown_fields)]
+pub struct BuildConfig {
+    /// Data written into `rules.txt`
+    #[serde(default)]
+    pub meta: BTreeMap<String, String>,
+    #[serde(default)]
+    pub flags: Vec<Flag>,
+    #[serde(default)]
+    pub options: Options,
+    #[serde(default, skip_serializing_if = "Warnings::is_empty")]
+    pub warnings: Warnings,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, BuildProfile>,
+}
+
+impl BuildConfig {
+    #[inline]
+    pub fn path(project: &Path) -> PathBuf {
+        project.join("config.yml")
+    }
+
+    /// Loads and checks the project's config, if it has one
+    pub fn load(project: &Path) -> Result<Option<Self>> {
+        let path = Self::path(project);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let config: Self = serde_yml::from_str(&fs::read_to_string(&path)?)
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        for options in std::iter::once(&config.options)
+            .chain(config.profiles.values().map(|p| &p.options))
+        {
+            if let Some(level) = options.compression {
+                if !COMPRESSION_LEVELS.contains(&level) {
+                    return Err(anyhow!(
+                        "Invalid compression level {} in {}, must be from {} to {}",
+                        level,
+                        path.display(),
+                        COMPRESSION_LEVELS.start(),
+                        COMPRESSION_LEVELS.end()
+                    ));
+                }
+            }
+        }
+        for warnings in std::iter::once(&config.warnings)
+            .chain(config.profiles.values().map(|p| &p.warnings))
+        {
+            warnings
+                .validate()
+                .with_context(|| format!("Invalid warning settings in {}", path.display()))?;
+        }
+        Ok(Some(config))
+    }
+
+    /// Loads the project's config, or a blank one if it doesn't have one yet
+    #[inline]
+    pub fn load_or_default(project: &Path) -> Result<Self> {
+        Ok(Self::load(project)?.unwrap_or_default())
+    }
+
+    /// Writes the config to the project. If it already has a config.yml, only the entries which
+    /// changed are rewritten, so comments and the order of everything else are kept. That only
+    /// works for the plain YAML described at [`unsupported_yaml`]; anything else, or an edit
+    /// which doesn't load back as this config, is replaced by a full rewrite with a warning.
+    pub fn save(&self, project: &Path) -> Result<()> {
+        let path = Self::path(project);
+        let text = match Self::load(project)? {
+            Some(old) => {
+                let text = fs::read_to_string(&path)?;
+                let edited = match unsupported_yaml(&text) {
+                    Some(reason) => Err(reason),
+                    None => self
+                        .edit_lines(&text, old)?
+                        .ok_or_else(|| "the edited file didn't match".to_owned()),
+                };
+                match edited {
+                    Ok(edited) => edited,
+                    Err(reason) => {
+                        message::warning(
+                            Code::Other,
+                            &format!(
+                                "Rewrote {} in full, so its comments and formatting are lost: {}",
+                                path.display(),
+                                reason
+                            ),
+                        );
+                        serde_yml::to_string(self)?
+                    }
+                }
+            }
+            None => serde_yml::to_string(self)?,
+        };
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Rewrites the entries of the text which differ from `old`, or returns `None` if the result
+    /// doesn't load back as this config
+    fn edit_lines(&self, text: &str, old: Self) -> Result<Option<String>> {
+        let mut lines: Vec<String> = text.lines().map(|l| l.to_owned()).collect();
+        let end = lines.len();
+        let new = serde_yml::to_value(self)?;
+        match (serde_yml::to_value(old)?, &new) {
+            (serde_yml::Value::Mapping(old), serde_yml::Value::Mapping(new)) => {
+                update_entries(&mut lines, 0..end, 0, &old, new)?;
+            }
+            _ => unreachable!("configs are mappings"),
+        }
+        let edited = lines.join("\n") + "\n";
+        Ok(serde_yml::from_str::<Self>(&edited)
+            .ok()
+            .and_then(|c| serde_yml::to_value(c).ok())
+            .filter(|c| c == &new)
+            .map(|_| edited))
+    }
+
+    /// Applies a build profile's flags and options over the main ones
+    pub fn with_profile(mut self, profile: Option<&str>) -> Result<Self> {
+        if let Some(name) = profile {
+            let profile = self.profiles.get(name).cloned().with_context(|| {
+                format!(
+                    "Build profile {} not found in config.yml, expected one of: {}",
+                    name,
+                    self.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+                )
+            })?;
+            for flag in profile.flags {
+                if !self.flags.contains(&flag) {
+                    self.flags.push(flag);
+                }
+            }
+            self.options.merge(profile.options);
+            self.warnings.merge(profile.warnings);
+        }
+        Ok(self)
+    }
+
+    #[inline]
+    pub fn has(&self, flag: Flag) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    /// The level of warnings not set in the `Warnings` section, from the flags here or on the
+    /// command line
+    pub fn warn_level(&self, hard_warnings: bool, ignore_warnings: bool) -> WarnLevel {
+        if self.has(Flag::HardWarnings) || hard_warnings {
+            WarnLevel::Error
+        } else if self.has(Flag::IgnoreWarnings) || ignore_warnings {
+            WarnLevel::None
+        } else {
+            WarnLevel::Warn
+        }
+    }
+}
+
+/// Options accept both `-` and `_` in their names
+#[inline]
+fn same_key(text: &str, key: &str) -> bool {
+    let text = text.trim();
+    text == key || text.replace('-', "_") == key
+}
+
+/// Splits a `key: value` line, without its indent, into its key and value
+fn split_key(content: &str) -> Option<(&str, &str)> {
+    match content.find(": ") {
+        Some(i) => Some((&content[..i], &content[i + 2..])),
+        None => content.strip_suffix(':').map(|key| (key, "")),
+    }
+}
+
+/// Why a config.yml can't be edited line by line, if it can't. The line editor only finds and
+/// replaces `key:` lines, so it needs block mappings with plain keys, each indented the same as
+/// the other keys of its mapping, and values which each fit on their key's line or are block
+/// sequences. Comments and blank lines can be anywhere. Flow mappings, multi-line scalars,
+/// anchors and aliases, tags, quoted or complex keys, and tabs or other odd indentation are not
+/// handled.
+fn unsupported_yaml(text: &str) -> Option<String> {
+    // Indents of the mappings which the current line could belong to, innermost last
+    let mut levels = vec![0];
+    // The indent of the last key if it had no value, so a nested block may start after it
+    let mut opener: Option<usize> = None;
+    for (n, line) in text.lines().enumerate() {
+        if is_blank(line) {
+            continue;
+        }
+        let at = |what: &str| Some(format!("{} on line {}", what, n + 1));
+        let indent = indent_of(line);
+        let mut content = line[indent..].trim_end();
+        if content.starts_with('\t') {
+            return at("tab indentation");
+        }
+        if content.starts_with("---") || content.starts_with("...") {
+            return at("document marker");
+        }
+        let mut key_indent = indent;
+        let mut new_level = false;
+        if content == "-" || content.starts_with("- ") {
+            // An item ends any mapping inside the item before it
+            while levels.last().map(|l| *l > indent).unwrap_or(false) {
+                levels.pop();
+            }
+            if indent < opener.unwrap_or(*levels.last().unwrap()) {
+                return at("odd indentation");
+            }
+            let rest = content[1..].trim_start();
+            key_indent = indent + content.len() - rest.len();
+            content = rest;
+            new_level = true;
+        }
+        let (value, is_key) = match split_key(content) {
+            Some((key, value)) => {
+                if key.starts_with(['"', '\'']) {
+                    return at("quoted key");
+                }
+                if key.starts_with(['?', '-']) || key.starts_with("<<") || key.trim() != key {
+                    return at("complex key");
+                }
+                match opener {
+                    _ if new_level => levels.push(key_indent),
+                    Some(parent) if key_indent > parent => levels.push(key_indent),
+                    _ => {
+                        while levels.last().map(|l| *l > key_indent).unwrap_or(false) {
+                            levels.pop();
+                        }
+                        if levels.last() != Some(&key_indent) {
+                            return at("odd indentation");
+                        }
+                    }
+                }
+                opener = None;
+                (value, true)
+            }
+            None if new_level => {
+                opener = None;
+                (content, false)
+            }
+            None => return at("multi-line scalar"),
+        };
+        let value = if value.starts_with('#') {
+            ""
+        } else {
+            match value.find(" #") {
+                Some(comment) => value[..comment].trim_end(),
+                None => value.trim(),
+            }
+        };
+        match value.chars().next() {
+            Some('{') => return at("flow mapping"),
+            Some('&' | '*') => return at("anchor or alias"),
+            Some('!') => return at("tag"),
+            Some('|' | '>') => return at("multi-line scalar"),
+            Some('[') if !value.ends_with(']') => return at("multi-line flow sequence"),
+            Some(quote @ ('"' | '\'')) if value.len() < 2 || !value.ends_with(quote) => {
+                return at("multi-line scalar")
+            }
+            _ => (),
+        }
+        if is_key && value.is_empty() {
+            opener = Some(key_indent);
+        }
+    }
+    None
+}
+
+/// Whether a line holds nothing but a comment or whitespace
+#[inline]
+fn is_blank(line: &str) -> bool {
+    let line = line.trim_start();
+    line.is_empty() || line.starts_with('#')
+}
+
+#[inline]
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+/// Finds the lines of a key's entry within `range`: the key itself and every line after it which
+/// is indented further or continues a sequence, up to its last line with a value
+fn find_entry(
+    lines: &[String],
+    range: Range<usize>,
+    indent: usize,
+    key: &str,
+) -> Option<Range<usize>> {
+    let start = range.clone().find(|&i| {
+        let line = &lines[i];
+        indent_of(line) == indent
+            && !is_blank(line)
+            && !line[indent..].starts_with('-')
+            && line[indent..]
+                .split_once(':')
+                .map(|(k, _)| same_key(k, key))
+                .unwrap_or(false)
+    })?;
+    let mut end = start + 1;
+    for (i, line) in lines.iter().enumerate().take(range.end).skip(start + 1) {
+        if is_blank(line) {
+            continue;
+        }
+        let item = line[indent_of(line)..].trim_end();
+        if indent_of(line) > indent
+            || (indent_of(line) == indent && (item == "-" || item.starts_with("- ")))
+        {
+            end = i + 1;
+        } else {
+            break;
+        }
+    }
+    Some(start..end)
+}
+
+/// The lines of a single entry, indented to fit where it goes
+fn entry_lines(
+    key: &serde_yml::Value,
+    value: &serde_yml::Value,
+    indent: usize,
+) -> Result<Vec<String>> {
+    let mut entry = serde_yml::Mapping::new();
+    entry.insert(key.clone(), value.clone());
+    Ok(serde_yml::to_string(&entry)?
+        .lines()
+        .map(|l| [" ".repeat(indent).as_str(), l].concat())
+        .collect())
+}
+
+/// Rewrites the entries of a mapping, spanning `range` of a YAML document, whose values differ
+/// between `old` and `new`. Block mappings are updated key by key, and other lines are left
+/// alone. Returns where the mapping ends afterwards.
+fn update_entries(
+    lines: &mut Vec<String>,
+    range: Range<usize>,
+    indent: usize,
+    old: &serde_yml::Mapping,
+    new: &serde_yml::Mapping,
+) -> Result<usize> {
+    let mut end = range.end;
+    for (key, _) in old.iter().filter(|(k, _)| !new.contains_key(*k)) {
+        if let Some(entry) = key
+            .as_str()
+            .and_then(|k| find_entry(lines, range.start..end, indent, k))
+        {
+            end -= entry.len();
+            lines.drain(entry);
+        }
+    }
+    for (key, value) in new {
+        let old_value = old.get(key);
+        if old_value == Some(value) {
+            continue;
+        }
+        let entry = key
+            .as_str()
+            .and_then(|k| find_entry(lines, range.start..end, indent, k));
+        match (entry, old_value, value) {
+            (Some(entry), Some(serde_yml::Value::Mapping(old)), serde_yml::Value::Mapping(new))
+                if entry.len() > 1 && !new.is_empty() =>
+            {
+                let body = entry.start + 1..entry.end;
+                let inner = body
+                    .clone()
+                    .find(|&i| !is_blank(&lines[i]))
+                    .map(|i| indent_of(&lines[i]))
+                    .unwrap_or(indent + 2);
+                let body_end = update_entries(lines, body, inner, old, new)?;
+                end = end + body_end - entry.end;
+            }
+            (Some(entry), ..) => {
+                let replacement = entry_lines(key, value, indent)?;
+                end = end + replacement.len() - entry.len();
+                lines.splice(entry, replacement);
+            }
+            (None, ..) => {
+                let at = (range.start..end)
+                    .rev()
+                    .find(|&i| !is_blank(&lines[i]))
+                    .map(|i| i + 1)
+                    .unwrap_or(range.start);
+                let addition = entry_lines(key, value, indent)?;
+                end += addition.len();
+                lines.splice(at..at, addition);
+            }
+        }
+    }
+    Ok(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn parse_config() {
+        let config: BuildConfig = serde_yml::from_str(
+            "Meta:\n  name: Test\nFlags:\n  - be\n  - ignore-warnings\nOptions:\n  \
+             title-actors: Weapon_Bow_001, Enemy_Lizalfos_Senior\nProfiles:\n  release:\n    \
+             Flags: [reproducible]\n    Options:\n      compression: 9\n      title_actors: \
+             [Obj_Test]\n",
+        )
+        .unwrap();
+        assert!(config.has(Flag::Be) && config.has(Flag::IgnoreWarnings));
+        assert_eq!(
+            config.options.title_actors,
+            vec!["Weapon_Bow_001", "Enemy_Lizalfos_Senior"]
+        );
+        let release = config.with_profile(Some("release")).unwrap();
+        assert!(release.has(Flag::Reproducible));
+        assert_eq!(release.options.compression, Some(9));
+        assert_eq!(release.options.title_actors.len(), 3);
+
+        assert!(serde_yml::from_str::<BuildConfig>("Flags: [hard_warning]").is_err());
+        assert!(serde_yml::from_str::<BuildConfig>("Warnings:\n  deny: [missing-havoc]").is_err());
+    }
+
+    #[test]
+    fn warning_levels() {
+        let config: BuildConfig = serde_yml::from_str(
+            "Warnings:\n  deny: [missing-havok]\n  suppress:\n    - code: missing-havok\n      \
+             paths: content/Physics/Cloth/Npc_*\nProfiles:\n  quiet:\n    Warnings:\n      \
+             allow: [missing-havok, missing-event-file]\n",
+        )
+        .unwrap();
+        let warnings = &config.warnings;
+        let cloth = Path::new("content/Physics/Cloth/Npc_Test.hkcl");
+        let ragdoll = Path::new("content/Physics/Ragdoll/Npc_Test.hkrg");
+        assert_eq!(
+            warnings.level(Code::MissingHavok, cloth, WarnLevel::Warn),
+            WarnLevel::None
+        );
+        assert_eq!(
+            warnings.level(Code::MissingHavok, ragdoll, WarnLevel::None),
+            WarnLevel::Error
+        );
+        assert_eq!(
+            warnings.level(Code::MissingEventFile, ragdoll, WarnLevel::Error),
+            WarnLevel::Error
+        );
+        let quiet = config.with_profile(Some("quiet")).unwrap();
+        assert_eq!(
+            quiet
+                .warnings
+                .level(Code::MissingHavok, ragdoll, WarnLevel::Error),
+            WarnLevel::None
+        );
+        assert!(serde_yml::from_str::<Warnings>("deny: [syntax]")
+            .unwrap()
+            .validate()
+            .is_err());
+        assert!(serde_yml::from_str::<BuildConfig>("Options:\n  title_actor: A").is_err());
+    }
+
+    #[test]
+    fn save_keeps_comments() {
+        let project = TempDir::new("config");
+        fs::create_dir_all(&project).unwrap();
+        fs::write(
+            BuildConfig::path(&project),
+            "# My mod\nMeta:\n  # Shown in Cemu\n  name: Test\n  version: '1.0'\nFlags: [be]  # \
+             Wii U\nOptions:\n  # Always in the title screen\n  title-actors: Obj_A, Obj_B\n  \
+             compression: 9 # smallest\n",
+        )
+        .unwrap();
+        let mut config = BuildConfig::load(&project).unwrap().unwrap();
+        config.options.title_actors.retain(|a| a != "Obj_A");
+        config.options.title_actors.push("Obj_C".into());
+        config
+            .meta
+            .insert("url".into(), "https://example.com".into());
+        config.save(&project).unwrap();
+
+        let text = fs::read_to_string(BuildConfig::path(&project)).unwrap();
+        for kept in [
+            "# My mod\nMeta:\n  # Shown in Cemu\n  name: Test\n",
+            "Flags: [be]  # Wii U\n",
+            "  # Always in the title screen\n",
+            "  compression: 9 # smallest\n",
+        ] {
+            assert!(text.contains(kept), "{:?} missing from:\n{}", kept, text);
+        }
+        let saved = BuildConfig::load(&project).unwrap().unwrap();
+        assert_eq!(saved.options.title_actors, vec!["Obj_B", "Obj_C"]);
+        assert_eq!(saved.meta["url"], "https://example.com");
+        assert_eq!(saved.options.compression, Some(9));
+    }
+
+    #[test]
+    fn save_nested_options() {
+        let project = TempDir::new("config_nested");
+        fs::create_dir_all(&project).unwrap();
+        fs::write(
+            BuildConfig::path(&project),
+            "Meta:\n  name: Test\nWarnings:\n  suppress:\n  - code: missing-havok\n    # Only \
+             cloth\n    paths: content/Physics/Cloth/*\nProfiles:\n  release:\n    # \
+             Smallest files\n    Options:\n      # Slow\n      compression: 9 # smallest\n      \
+             output: build/release\n  debug:\n    Flags: [verbose]\n",
+        )
+        .unwrap();
+        let mut config = BuildConfig::load(&project).unwrap().unwrap();
+        let release = config.profiles.get_mut("release").unwrap();
+        release.options.compression = Some(8);
+        release.options.deploy = Some("mods/Test".into());
+        config.save(&project).unwrap();
+
+        let text = fs::read_to_string(BuildConfig::path(&project)).unwrap();
+        for kept in [
+            "    # Only cloth\n",
+            "  release:\n    # Smallest files\n    Options:\n      # Slow\n      compression: 8\n",
+            "      output: build/release\n",
+            "  debug:\n    Flags: [verbose]\n",
+        ] {
+            assert!(text.contains(kept), "{:?} missing from:\n{}", kept, text);
+        }
+        let saved = BuildConfig::load(&project).unwrap().unwrap();
+        assert_eq!(saved.profiles["release"], config.profiles["release"]);
+        assert_eq!(saved.warnings, config.warnings);
+    }
+
+    #[test]
+    fn unsupported_yaml_is_rewritten() {
+        assert_eq!(
+            unsupported_yaml(
+                "# Comment\nMeta:\n  name: Test # Name\n\nFlags:\n- be\nWarnings:\n  \
+                 suppress:\n    - code: missing-havok\n      paths: [a, b]\n"
+            ),
+            None
+        );
+        for (text, reason) in [
+            ("Meta: {name: Test}\n", "flow mapping on line 1"),
+            (
+                "Meta:\n  name: >\n    Test\n",
+                "multi-line scalar on line 2",
+            ),
+            (
+                "Meta:\n  name: Long\n    name\n",
+                "multi-line scalar on line 3",
+            ),
+            ("Meta: &meta\n  name: Test\n", "anchor or alias on line 1"),
+            ("Meta:\n  'name': Test\n", "quoted key on line 2"),
+            (
+                "Meta:\n  name: Test\n   version: '1'\n",
+                "odd indentation on line 3",
+            ),
+            ("Flags: [\n  be]\n", "multi-line flow sequence on line 1"),
+        ] {
+            assert_eq!(
+                unsupported_yaml(text).as_deref(),
+                Some(reason),
+                "{:?}",
+                text
+            );
+        }
+
+        let project = TempDir::new("config_flow");
+        fs::create_dir_all(&project).unwrap();
+        fs::write(
+            BuildConfig::path(&project),
+            "Meta: {name: Test} # Shown in Cemu\nOptions:\n  compression: 9\n",
+        )
+        .unwrap();
+        let mut config = BuildConfig::load(&project).unwrap().unwrap();
+        config.meta.insert("version".into(), "1.0".into());
+        config.save(&project).unwrap();
+        let text = fs::read_to_string(BuildConfig::path(&project)).unwrap();
+        assert!(!text.contains("# Shown in Cemu"));
+        let saved = BuildConfig::load(&project).unwrap().unwrap();
+        assert_eq!(saved.meta, config.meta);
+        assert_eq!(saved.options.compression, Some(9));
+    }
+}
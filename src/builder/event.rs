@@ -1,12 +1,14 @@
 use super::{Builder, Result};
-use crate::util::get_ext;
+use crate::{
+    diagnostic::{Code, Diagnostic},
+    util::get_ext,
+};
 use anyhow::Context;
 use join_str::jstr;
 use path_slash::PathBufExt;
 use roead::{
     byml::{Byml, Map},
     sarc::SarcWriter,
-    yaz0::compress,
 };
 use std::{
     collections::HashSet,
@@ -47,6 +49,8 @@ pub static TITLE_EVENTS: &[&str] = &[
 pub struct Event<'a> {
     builder: &'a Builder,
     pub name: String,
+    /// The event info file the event was found from
+    pub info: PathBuf,
     files: HashSet<PathBuf>,
 }
 
@@ -71,13 +75,13 @@ impl<'a> Event<'a> {
             .file_stem()
             .context("Missing filename")?
             .to_str()
-            .unwrap()
+            .context("Invalid UTF 8 in event name")?
             .trim_end_matches(".info");
         let event_info: Map = Byml::from_text(
             fs::read_to_string(file)
                 .with_context(|| format!("Failed to read event file at {}", file.display()))?,
         )
-        .with_context(|| format!("Failed to parse YAML at {}", file.display()))?
+        .map_err(|e| Diagnostic::syntax(file, "BYML", e))?
         .into_map()?
         .into_iter()
         .map(|(mut k, v)| {
@@ -91,24 +95,17 @@ impl<'a> Event<'a> {
             return Ok((event_info.into_iter(), None));
         }
         let root = builder.source_content();
-        let event_flow_root = root.join("EventFlow");
-        let as_root = root.join("Actor/AS").join(name);
-        let camera_root = root.join("Camera").join(name);
         let main_exts = [Some(OsStr::new("bfevfl")), Some(OsStr::new("bfevtm"))];
-        let files: HashSet<PathBuf> = find_subfiles(&event_info)?
-            .map(|file| event_flow_root.join(file))
-            .chain(
-                find_as_files(&event_info)?
-                    .map(|file| as_root.join(file).with_extension("bas.yml")),
-            )
-            .chain(find_camera_files(&event_info)?.map(|file| camera_root.join(file)))
-            .chain(find_single_files(&event_info, name)?.map(|file| root.join(file)))
-            .collect();
+        let files = event_files(&root, name, &event_info)?;
+        let variant_root = root.join("Variants").join(jstr!("{name}.sbeventpack"));
         if !files.is_empty()
-            && files
+            && (files
                 .iter()
                 .chain(&[file.to_owned()])
                 .any(|f| builder.modified_files.contains(f))
+                || builder
+                    .modified_files
+                    .any(|f| f.starts_with(&variant_root)))
             && !files
                 .iter()
                 .filter(|f| {
@@ -123,12 +120,14 @@ impl<'a> Event<'a> {
                 .any(|f| !f.exists())
         {
             builder.vprint(&jstr!("Event {&name} modified"));
+            builder.expect_files(&jstr!("{name}.sbeventpack"), &files);
             Ok((
                 event_info.into_iter(),
                 Some(Self {
                     builder,
                     files,
                     name: name.to_owned(),
+                    info: file.to_owned(),
                 }),
             ))
         } else {
@@ -141,6 +140,7 @@ impl<'a> Event<'a> {
             .vprint(&jstr!("Building event pack {&self.name}"));
         let mut pack = SarcWriter::new(self.builder.endian());
         let root = self.builder.source.join(&self.builder.content);
+        let pack_name = jstr!("{&self.name}.sbeventpack");
         self.files.into_iter().try_for_each(|f| -> Result<()> {
             let mut filename = f
                 .strip_prefix(&root)
@@ -153,27 +153,45 @@ impl<'a> Event<'a> {
                     .any(|e| f.file_name().unwrap().to_str().unwrap().contains(e))
                     && !root.join("Pack/TitleBG.pack").join(&filename).exists()
                 {
-                    self.builder.warn(&jstr!(
-                        "Event {&self.name} missing file {&f.to_slash_lossy()}"
-                    ))?;
+                    self.builder.warn(
+                        Code::MissingEventFile,
+                        &f,
+                        &jstr!("File not found, but event {&self.name} uses it"),
+                    )?;
                 }
                 return Ok(());
             };
             if get_ext(&filename)? == "yml" {
                 filename = filename.with_extension("");
             }
+            let f = self.builder.pack_variant(&pack_name, &f).unwrap_or(f);
             let data = self.builder.get_resource_data(&f)?;
-            pack.add_file(filename.to_slash_lossy(), data);
+            pack.add_file(filename.to_slash_lossy(), data.to_vec());
             Ok(())
         })?;
         let data = pack.to_binary();
         self.builder
             .set_resource_size(&jstr!("Event/{&self.name}.beventpack"), &data);
         self.builder.vprint(&jstr!("Built event {&self.name}"));
-        Ok(compress(data))
+        Ok(self.builder.compress(data))
     }
 }
 
+/// Lists every file used by an event, from its event info
+pub fn event_files(root: &Path, name: &str, event_info: &Map) -> Result<HashSet<PathBuf>> {
+    let event_flow_root = root.join("EventFlow");
+    let as_root = root.join("Actor/AS").join(name);
+    let camera_root = root.join("Camera").join(name);
+    Ok(find_subfiles(event_info)?
+        .map(|file| event_flow_root.join(file))
+        .chain(
+            find_as_files(event_info)?.map(|file| as_root.join(file).with_extension("bas.yml")),
+        )
+        .chain(find_camera_files(event_info)?.map(|file| camera_root.join(file)))
+        .chain(find_single_files(event_info, name)?.map(|file| root.join(file)))
+        .collect())
+}
+
 fn find_subfiles(event_info: &Map) -> Result<impl Iterator<Item = &str>> {
     Ok(event_info
         .values()
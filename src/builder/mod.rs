@@ -1,15 +1,30 @@
 pub mod actor;
+pub mod artifacts;
+pub mod compiled;
 pub mod config;
 pub mod event;
+pub mod timings;
+
+pub use config::BuildConfig;
 
 use super::util::*;
 use crate::{
-    builder::{actor::Actor, event::Event},
+    builder::{
+        actor::Actor,
+        artifacts::{Artifact, ArtifactCache},
+        compiled::{Buffer, Compiled},
+        event::Event,
+        timings::{Span, Timings},
+    },
+    diagnostic::{Code, Diagnostic, InFile},
+    game_dump::StockHashes,
+    message::{self, Stage},
+    sarc_meta::SarcMeta,
+    settings::GameProfile,
     unzip_some::unzip_some,
 };
-use anyhow::{anyhow, format_err, Context, Result};
-use botw_utils::{get_canon_name, get_canon_name_without_root, hashes::StockHashTable};
-use colored::*;
+use anyhow::{anyhow, Context, Result};
+use botw_utils::{get_canon_name, get_canon_name_without_root};
 use fs_err as fs;
 use join_str::jstr;
 use path_slash::{PathBufExt, PathExt};
@@ -18,30 +33,28 @@ use roead::{
     aamp::ParameterIO,
     byml::{Byml, Map},
     sarc::{Sarc, SarcWriter},
-    yaz0::compress,
+    yaz0::compress_with_level,
     Endian,
 };
 use rstb::ResourceSizeTable;
 use rustc_hash::{FxBuildHasher, FxHashMap as HashMap};
-use scc::{HashMap as SyncMap, HashSet};
-use serde::{Deserialize, Serialize};
+use scc::HashSet;
 use std::{
+    borrow::Cow,
     collections::BTreeMap,
     ffi::OsStr,
     io::Write,
+    panic::{catch_unwind, AssertUnwindSafe},
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
 pub type Hash = BTreeMap<String, Byml>;
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "PascalCase")]
-pub struct BuildConfig {
-    pub meta: HashMap<String, String>,
-    pub flags: Vec<String>,
-    pub options: HashMap<String, String>,
-}
+/// Lists outputs of removed actors and events, relative to the output folder, to delete on the
+/// next build. Files inside packs are given as `{pack}//{file}`.
+pub static REMOVED_FILE: &str = ".removed";
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -60,15 +73,31 @@ pub struct Builder {
     pub aoc: PathBuf,
     pub file_times: HashMap<PathBuf, u64>,
     pub modified_files: HashSet<PathBuf, FxBuildHasher>,
-    pub hash_table: StockHashTable,
-    pub compiled: SyncMap<PathBuf, Vec<u8>, FxBuildHasher>,
+    pub hash_table: StockHashes,
+    pub compiled: Compiled,
     pub size_table: Arc<Mutex<ResourceSizeTable>>,
     pub title_actors: HashSet<String, FxBuildHasher>,
     pub title_events: HashSet<String, FxBuildHasher>,
     pub actorinfo: Option<Hash>,
-    pub meta: HashMap<String, String>,
+    pub meta: BTreeMap<String, String>,
+    /// The level for warnings without one set in `warnings`
     pub warn: WarnLevel,
+    pub warnings: config::Warnings,
     pub verbose: bool,
+    /// Yaz0 compression level
+    pub compression: u8,
+    /// Ignore the file times from the last build, so everything is rebuilt
+    pub reproducible: bool,
+    /// The game folders to find stock files in
+    pub dump: GameProfile,
+    /// Record errors in actors, events, and files and carry on, instead of stopping at the
+    /// first one
+    pub keep_going: bool,
+    pub diagnostics: Mutex<Vec<Diagnostic>>,
+    /// Record how long each stage and item takes, for `--timings`
+    pub timings: Option<Timings>,
+    /// Reuse compiled and compressed files from earlier builds, unless `no_cache` is set
+    pub artifacts: Option<ArtifactCache>,
 }
 
 impl Builder {
@@ -81,21 +110,96 @@ impl Builder {
         }
     }
 
+    #[inline]
+    fn compress(&self, data: impl AsRef<[u8]>) -> Vec<u8> {
+        match self.artifacts.as_ref() {
+            Some(artifacts) => artifacts.compress(data.as_ref()),
+            None => compress_with_level(data, self.compression),
+        }
+    }
+
+    #[inline]
+    fn compile(
+        &self,
+        kind: Artifact,
+        source: &[u8],
+        make: impl FnOnce() -> Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        match self.artifacts.as_ref() {
+            Some(artifacts) => artifacts.compile(kind, source, make),
+            None => make(),
+        }
+    }
+
     #[inline]
     fn vprint(&self, message: &str) {
         if self.verbose {
-            println!("{}", message.bright_black());
+            message::detail(message);
         }
     }
 
-    fn warn(&self, message: &str) -> Result<()> {
-        match self.warn {
+    /// Reports a warning about a file at the level set for its code and file, failing the
+    /// build if it's denied
+    fn warn(&self, code: Code, file: &Path, message: &str) -> Result<()> {
+        let rel = file.strip_prefix(&self.source).unwrap_or(file);
+        match self.warnings.level(code, rel, self.warn) {
             WarnLevel::None => Ok(()),
             WarnLevel::Warn => {
-                println!("{}", message.yellow());
+                Diagnostic::warning(code, message)
+                    .with_file(file)
+                    .print(&self.source);
                 Ok(())
             }
-            WarnLevel::Error => Err(format_err!("{}", message.red())),
+            WarnLevel::Error => Err(Diagnostic::error(code, message).with_file(file).into()),
+        }
+    }
+
+    /// Runs a stage of the build, recording its time if timings are on
+    fn stage(&mut self, name: &'static str, f: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        let start = Instant::now();
+        let result = f(self);
+        if let Some(timings) = self.timings.as_ref() {
+            timings.record(Span::Stage, name, start);
+        }
+        result
+    }
+
+    /// Builds one actor pack, event pack, or map unit, recording its time if timings are on
+    fn timed<T>(&self, span: Span, name: impl FnOnce() -> String, f: impl FnOnce() -> T) -> T {
+        match self.timings.as_ref() {
+            Some(timings) => {
+                let start = Instant::now();
+                let result = f();
+                timings.record(span, name(), start);
+                result
+            }
+            None => f(),
+        }
+    }
+
+    /// Runs one unit of the build, such as an actor pack or a file, turning a panic into an
+    /// error. With `keep_going`, errors are recorded and `None` is returned so the rest of the
+    /// build can go on.
+    fn attempt<T>(&self, file: &Path, f: impl FnOnce() -> Result<T>) -> Result<Option<T>> {
+        let result = catch_unwind(AssertUnwindSafe(f))
+            .unwrap_or_else(|payload| {
+                Err(Diagnostic::from_panic(
+                    payload,
+                    &jstr!("Building {&file.to_slash_lossy()}"),
+                )
+                .into())
+            })
+            .in_file(file);
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if self.keep_going => {
+                self.diagnostics
+                    .lock()
+                    .unwrap()
+                    .push(Diagnostic::from_error(&e));
+                Ok(None)
+            }
+            Err(e) => Err(e),
         }
     }
 
@@ -109,6 +213,28 @@ impl Builder {
         self.output.join(&self.content)
     }
 
+    /// Files which differed between root-merged packs on unbuild may be kept as variants under
+    /// `Variants/{pack}`, which take precedence when building that pack.
+    fn pack_variant(&self, pack: &str, file: &Path) -> Option<PathBuf> {
+        let variant = self
+            .source_content()
+            .join("Variants")
+            .join(pack)
+            .join(file.strip_prefix(self.source_content()).ok()?);
+        variant.exists().then_some(variant)
+    }
+
+    /// Notes the files a pack will be built from, so each is compiled once and kept until the
+    /// last pack which uses it is built
+    fn expect_files<'f>(&self, pack: &str, files: impl IntoIterator<Item = &'f PathBuf>) {
+        for file in files {
+            self.compiled.expect(
+                self.pack_variant(pack, file)
+                    .unwrap_or_else(|| file.clone()),
+            );
+        }
+    }
+
     fn get_canon_name(&self, file: &Path) -> Option<String> {
         if let Some(sarc_root) = file
             .ancestors()
@@ -127,23 +253,39 @@ impl Builder {
         }
     }
 
-    fn get_resource_data(&self, file: &Path) -> Result<Vec<u8>> {
-        if let Some(data) = self.compiled.get(file) {
-            return Ok(data.clone());
-        }
-        let bytes = std::fs::read(file)
-            .with_context(|| jstr!("Failed to read {file.to_str().unwrap()}"))?;
+    /// Compiles a project file, or gets it already compiled if another pack used it. The
+    /// returned buffer is shared, so it has to be copied to be added to a pack.
+    fn get_resource_data(&self, file: &Path) -> Result<Buffer> {
+        self.compiled
+            .get_or_compile(file, || self.compile_resource(file))
+    }
+
+    fn compile_resource(&self, file: &Path) -> Result<Buffer> {
+        let bytes = match std::fs::read(file) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Diagnostic::missing_file(file, "File not found").into());
+            }
+            Err(e) => {
+                return Err(e).with_context(|| jstr!("Failed to read {&file.to_slash_lossy()}"))
+            }
+        };
         let mut ext = get_ext(file)?;
         let data = if ext == "yml" {
-            let text = std::str::from_utf8(&bytes)?;
+            let text = std::str::from_utf8(&bytes)
+                .map_err(|e| Diagnostic::syntax(file, "YAML", e))?;
             if text.len() >= 3 && &text[0..3] == "!io" {
-                ParameterIO::from_text(text)
-                    .with_context(|| jstr!("Failed to parse AAMP file {file.to_str().unwrap()}"))?
-                    .to_binary()
+                self.compile(Artifact::Aamp, &bytes, || {
+                    Ok(ParameterIO::from_text(text)
+                        .map_err(|e| Diagnostic::syntax(file, "AAMP", e))?
+                        .to_binary())
+                })?
             } else {
-                Byml::from_text(text)
-                    .with_context(|| jstr!("Failed to parse BYML file {file.to_str().unwrap()}"))?
-                    .to_binary(self.endian())
+                self.compile(Artifact::Byml, &bytes, || {
+                    Ok(Byml::from_text(text)
+                        .map_err(|e| Diagnostic::syntax(file, "BYML", e))?
+                        .to_binary(self.endian()))
+                })?
             }
         } else {
             bytes
@@ -184,12 +326,11 @@ impl Builder {
                 }
             }
         };
-        let data = if &data[0..4] != b"Yaz0" && ext.starts_with('s') && ext != "sarc" {
-            compress(data)
+        let data: Buffer = if &data[0..4] != b"Yaz0" && ext.starts_with('s') && ext != "sarc" {
+            self.compress(data).into()
         } else {
-            data
+            data.into()
         };
-        let _ = self.compiled.insert(file.to_owned(), data.to_vec());
         Ok(data)
     }
 
@@ -216,9 +357,9 @@ impl Builder {
     }
 
     fn load_modified_files(&mut self) -> Result<()> {
-        println!("Scanning project files");
+        let mut stage = Stage::start("scan", None, "Scanning project files");
         let db = self.source.join(".db");
-        if db.exists() && fs::metadata(&db)?.len() > 1 {
+        if !self.reproducible && db.exists() && fs::metadata(&db)?.len() > 1 {
             self.file_times.extend(
                 fs::read_to_string(db)?
                     .lines()
@@ -259,32 +400,36 @@ impl Builder {
             }
             files
         };
+        stage.count(self.modified_files.len());
+        stage.finish();
         Ok(())
     }
 
     fn load_actorinfo(&mut self) -> Result<()> {
-        println!("Loading actor info");
-        self.actorinfo = Some(
-            glob::glob(
-                self.source_content()
-                    .join("Actor/ActorInfo/**/*.info.yml")
-                    .as_os_str()
-                    .to_str()
-                    .unwrap(),
-            )?
-            .filter_map(Result::ok)
-            .try_fold(BTreeMap::new(), |mut actorinfo, file| -> Result<Hash> {
-                actorinfo.insert(
-                    file.file_stem()
-                        .context("Whoa, no filename")?
-                        .to_string_lossy()
-                        .trim_end_matches(".info")
-                        .into(),
-                    Byml::from_text(fs::read_to_string(&file)?)?,
-                );
-                Ok(actorinfo)
-            })?,
-        );
+        let mut stage = Stage::start("load-actorinfo", None, "Loading actor info");
+        let actorinfo = glob::glob(
+            self.source_content()
+                .join("Actor/ActorInfo/**/*.info.yml")
+                .as_os_str()
+                .to_str()
+                .unwrap(),
+        )?
+        .filter_map(Result::ok)
+        .try_fold(BTreeMap::new(), |mut actorinfo, file| -> Result<Hash> {
+            actorinfo.insert(
+                file.file_stem()
+                    .context("Whoa, no filename")?
+                    .to_string_lossy()
+                    .trim_end_matches(".info")
+                    .into(),
+                Byml::from_text(fs::read_to_string(&file)?)
+                    .map_err(|e| Diagnostic::syntax(&file, "BYML", e))?,
+            );
+            Ok(actorinfo)
+        })?;
+        stage.count(actorinfo.len());
+        self.actorinfo = Some(actorinfo);
+        stage.finish();
         Ok(())
     }
 
@@ -307,21 +452,26 @@ impl Builder {
     fn build_actors(&mut self) -> Result<()> {
         let actor_root = self.source_content().join("Actor");
         if self.modified_files.any(|p| p.starts_with(&actor_root)) {
-            println!("Checking actor packs");
+            message::status("Checking actor packs");
             let modded_actors: Vec<Actor> =
                 glob::glob(actor_root.join("ActorLink/*.bxml.yml").to_str().unwrap())?
                     .filter_map(Result::ok)
                     .collect::<Vec<_>>()
                     .into_par_iter()
-                    .map(|f| Actor::new(self, &f))
+                    .map(|f| self.attempt(&f, || Actor::new(self, &f)))
                     .collect::<Result<Vec<_>>>()?
                     .into_iter()
                     .flatten()
+                    .flatten()
                     .collect();
             if !modded_actors.is_empty() {
                 let output_pack_dir = self.out_content().join("Actor/Pack");
                 std::fs::create_dir_all(&output_pack_dir)?;
-                println!("Building {} actor packs", modded_actors.len());
+                let stage = Stage::start(
+                    "build-actors",
+                    Some(modded_actors.len()),
+                    &format!("Building {} actor packs", modded_actors.len()),
+                );
                 let (title_actors, free_actors): (Vec<_>, Vec<_>) = modded_actors
                     .into_par_iter()
                     .partition(|a| self.title_actors.contains(&a.name));
@@ -329,25 +479,33 @@ impl Builder {
                 free_actors
                     .into_par_iter()
                     .try_for_each(|a| -> Result<()> {
-                        std::fs::write(
-                            output_pack_dir.join(jstr!("{&a.name}.sbactorpack")),
-                            a.build()?,
-                        )?;
+                        let source = a.link.clone();
+                        self.attempt(&source, || -> Result<()> {
+                            let out = output_pack_dir.join(jstr!("{&a.name}.sbactorpack"));
+                            let name = a.name.clone();
+                            write_file(&out, self.timed(Span::ActorPack, || name, || a.build())?)?;
+                            Ok(())
+                        })?;
                         Ok(())
                     })?;
                 self.vprint(&format!("  {} TitleBG actor packs", title_actors.len()));
                 let built_title_actors = title_actors
                     .into_par_iter()
-                    .map(|a| -> Result<(PathBuf, Vec<u8>)> {
-                        Ok((
-                            jstr!("TitleBG.pack/Actor/Pack/{&a.name}.sbactorpack").into(),
-                            a.build()?,
-                        ))
+                    .map(|a| {
+                        let link = a.link.clone();
+                        self.attempt(&link, || -> Result<(PathBuf, Vec<u8>)> {
+                            let name = a.name.clone();
+                            Ok((
+                                jstr!("TitleBG.pack/Actor/Pack/{&name}.sbactorpack").into(),
+                                self.timed(Span::ActorPack, || name, || a.build())?,
+                            ))
+                        })
                     })
                     .collect::<Result<Vec<_>>>()?;
-                for (path, data) in built_title_actors {
-                    let _ = self.compiled.insert(path, data);
+                for (path, data) in built_title_actors.into_iter().flatten() {
+                    self.compiled.put(path, data.into());
                 }
+                stage.finish();
             }
         }
         Ok(())
@@ -355,7 +513,11 @@ impl Builder {
 
     fn build_actorinfo(&mut self) -> Result<()> {
         if let Some(actorinfo) = self.actorinfo.take() {
-            println!("Building actor info");
+            let stage = Stage::start(
+                "build-actorinfo",
+                Some(actorinfo.len()),
+                "Building actor info",
+            );
             let mut info = roead::byml::Map::default();
             info.insert(
                 "Hashes".into(),
@@ -384,10 +546,11 @@ impl Builder {
                     actors
                 }),
             );
-            fs::write(
-                self.out_content().join("Actor/ActorInfo.product.sbyml"),
-                compress(Byml::Map(info).to_binary(self.endian())),
+            write_file(
+                &self.out_content().join("Actor/ActorInfo.product.sbyml"),
+                self.compress(Byml::Map(info).to_binary(self.endian())),
             )?;
+            stage.finish();
         }
         Ok(())
     }
@@ -396,7 +559,7 @@ impl Builder {
         let event_root = self.source_content().join("Event");
         let event_info_root = event_root.join("EventInfo");
         if event_root.exists() {
-            println!("Checking events");
+            message::status("Checking events");
             let title_event_path = self.source_content().join("Pack/TitleBG.pack/EventFlow");
             if title_event_path.exists() {
                 for file in glob::glob(title_event_path.join("*.bfevfl").to_str().unwrap())?
@@ -411,40 +574,47 @@ impl Builder {
                     .filter_map(Result::ok)
                     .collect::<Vec<_>>()
                     .into_par_iter()
-                    .map(
-                        |file| -> Result<(
-                            std::collections::hash_map::IntoIter<
-                                smartstring::alias::String,
-                                roead::byml::Byml,
-                            >,
-                            Option<Event>,
-                        )> { Event::new(self, &file) },
-                    )
+                    .map(|file| self.attempt(&file, || Event::new(self, &file)))
                     .collect::<Result<Vec<_>>>()?
                     .into_par_iter()
+                    .flatten()
                     .map(|(i, e)| (Some(i), e)),
             );
             if self.modified_files.any(|p| p.starts_with(&event_info_root)) {
-                println!("Building event info");
+                let stage = Stage::start(
+                    "build-eventinfo",
+                    Some(event_info.len()),
+                    "Building event info",
+                );
                 let data = Byml::Map(event_info).to_binary(self.endian());
                 self.set_resource_size("Event/EventInfo.product.byml", &data);
-                let _ = self
-                    .compiled
-                    .insert("Event/EventInfo.product.sbyml".into(), compress(data));
+                self.compiled.put(
+                    "Event/EventInfo.product.sbyml".into(),
+                    self.compress(data).into(),
+                );
+                stage.finish();
             }
             if !event_packs.is_empty() {
                 let output_pack_dir = self.out_content().join("Event");
                 std::fs::create_dir_all(&output_pack_dir)?;
-                println!("Building {} event packs", event_packs.len());
+                let stage = Stage::start(
+                    "build-events",
+                    Some(event_packs.len()),
+                    &format!("Building {} event packs", event_packs.len()),
+                );
                 event_packs
                     .into_par_iter()
                     .try_for_each(|e| -> Result<()> {
-                        std::fs::write(
-                            output_pack_dir.join(jstr!("{&e.name}.sbeventpack")),
-                            e.build()?,
-                        )?;
+                        let source = e.info.clone();
+                        self.attempt(&source, || -> Result<()> {
+                            let out = output_pack_dir.join(jstr!("{&e.name}.sbeventpack"));
+                            let name = e.name.clone();
+                            write_file(&out, self.timed(Span::EventPack, || name, || e.build())?)?;
+                            Ok(())
+                        })?;
                         Ok(())
                     })?;
+                stage.finish();
             }
         }
         Ok(())
@@ -465,26 +635,30 @@ impl Builder {
                     .context("Weird")?
                     .to_str()
                     .context("Weird")?;
-                println!("Building {} texts", lang);
+                let files: Vec<PathBuf> = glob::glob(dir.join("**/*.msyt").to_str().unwrap())?
+                    .filter_map(Result::ok)
+                    .collect();
+                let stage = Stage::start(
+                    "build-texts",
+                    Some(files.len()),
+                    &format!("Building {} texts", lang),
+                );
                 let message_sarc = Arc::new(Mutex::new(SarcWriter::new(self.endian())));
                 let endian = if self.be {
                     msyt::Endianness::Big
                 } else {
                     msyt::Endianness::Little
                 };
-                glob::glob(dir.join("**/*.msyt").to_str().unwrap())?
-                    .filter_map(Result::ok)
-                    .collect::<Vec<_>>()
-                    .into_par_iter()
-                    .try_for_each(|f| -> Result<()> {
+                files.into_par_iter().try_for_each(|f| -> Result<()> {
+                    self.attempt(&f, || -> Result<()> {
                         let text = fs::read_to_string(&f)?;
                         let msyt: msyt::Msyt = serde_yml::from_str(&text)
-                            .with_context(|| f.to_slash_lossy().to_string())
                             .or_else(|e| {
                                 let deser = serde_yml::Deserializer::from_str(&text);
                                 serde_yml::with::singleton_map_recursive::deserialize(deser)
-                                    .context(e)
-                            })?;
+                                    .map_err(|_| e)
+                            })
+                            .map_err(|e| Diagnostic::yaml(&f, "MSYT", &e))?;
                         message_sarc.lock().unwrap().add_file(
                             f.strip_prefix(&dir)?
                                 .with_extension("msbt")
@@ -494,6 +668,8 @@ impl Builder {
                         );
                         Ok(())
                     })?;
+                    Ok(())
+                })?;
                 let message_bytes = Arc::try_unwrap(message_sarc)
                     .unwrap()
                     .into_inner()?
@@ -501,35 +677,43 @@ impl Builder {
                 let message_path = jstr!("Message/Msg_{lang}.product.ssarc");
                 self.set_resource_size(&message_path.replace(".ss", ".s"), &message_bytes);
                 let mut bootup_sarc = SarcWriter::new(self.endian());
-                bootup_sarc.add_file(&message_path, compress(message_bytes));
-                fs::write(
-                    pack_out.join(jstr!("Bootup_{lang}.pack")),
+                bootup_sarc.add_file(&message_path, self.compress(message_bytes));
+                write_file(
+                    &pack_out.join(jstr!("Bootup_{lang}.pack")),
                     bootup_sarc.to_binary(),
                 )?;
+                stage.finish();
             }
         }
         Ok(())
     }
 
     fn build_sarc(&self, sarc_path: &Path, sarc: &mut SarcWriter) -> Result<Vec<u8>> {
-        let prefix = if sarc_path.join(".slash").exists() {
+        let meta = SarcMeta::load(sarc_path)
+            .with_context(|| jstr!("Failed to read SARC info in {&sarc_path.to_slash_lossy()}"))?;
+        // Projects unbuilt before `.sarc.yml` existed use `.slash` and `.align` files instead
+        let prefix = if meta.as_ref().map(|m| m.leading_slash).unwrap_or(false)
+            || sarc_path.join(".slash").exists()
+        {
             "/"
         } else {
             ""
         };
         let align_path = sarc_path.join(".align");
-        if align_path.exists() {
+        if let Some(meta) = meta.as_ref() {
+            sarc.set_min_alignment(meta.min_alignment);
+        } else if align_path.exists() {
             sarc.set_min_alignment(fs::read_to_string(align_path)?.parse::<u8>()?.into());
         }
         if sarc_path.file_name() == Some(std::ffi::OsStr::new("TitleBG.pack")) {
-            self.compiled.scan(|path, data| {
+            for (path, data) in self.compiled.take_all(Path::new("TitleBG.pack")) {
                 if let Ok(path) = path.strip_prefix("TitleBG.pack") {
-                    sarc.add_file(path.to_str().unwrap(), data.clone());
+                    sarc.add_file(path.to_str().unwrap(), data.to_vec());
                 }
-            });
+            }
         } else if sarc_path.file_name() == Some(std::ffi::OsStr::new("Bootup.pack")) {
             if let Ok(data) = self.get_resource_data(Path::new("Event/EventInfo.product.sbyml")) {
-                sarc.add_file("Event/EventInfo.product.sbyml", data);
+                sarc.add_file("Event/EventInfo.product.sbyml", data.to_vec());
             } else if sarc.get_file("Event/EventInfo.product.sbyml").is_none() {
                 anyhow::bail!("No event info???")
             }
@@ -556,10 +740,12 @@ impl Builder {
                 .filter_map(Result::ok)
                 .filter(|f| f.is_dir()),
         );
-        files.into_iter()
-            .try_for_each(|f| -> Result<()> {
+        for f in files {
+            self.attempt(&f, || -> Result<()> {
                 let add_path = jstr!(r#"{prefix}{&f.strip_prefix(&sarc_path)?.to_slash_lossy().trim_end_matches(".yml")}"#);
-                let data = if f.is_dir() && SARC_EXTS.contains(&f.extension()) {
+                let shared;
+                // Shared data is only copied once it's known to go into the SARC as it is
+                let data: Cow<[u8]> = if f.is_dir() && SARC_EXTS.contains(&f.extension()) {
                     let mut sarc_writer = if let Some(data) = sarc.get_file(&*add_path) {
                         SarcWriter::from_sarc(&Sarc::new(
                             data
@@ -567,9 +753,10 @@ impl Builder {
                     } else {
                         SarcWriter::new(self.endian())
                     };
-                    self.build_sarc(&f, &mut sarc_writer)?
+                    Cow::Owned(self.build_sarc(&f, &mut sarc_writer)?)
                 } else if f.is_file() {
-                    self.get_resource_data(&f)?
+                    shared = self.get_resource_data(&f)?;
+                    Cow::Borrowed(&shared)
                 } else {
                     return Ok(());
                 };
@@ -581,18 +768,73 @@ impl Builder {
                         && ext.map(|e| e.starts_with('s')).unwrap_or(false)
                         && ext != Some("sarc")
                     {
-                        compress(data)
+                        self.compress(data)
                     } else {
-                        data
+                        data.into_owned()
                     },
                 );
                 Ok(())
             })?;
-        if !sarc.files.is_empty() {
-            Ok(sarc.to_binary())
-        } else {
-            Ok(vec![])
         }
+        if sarc.files.is_empty() {
+            return Ok(vec![]);
+        }
+        if let Some(meta) = meta {
+            if let Some(data) = meta.to_binary(
+                sarc_path,
+                sarc.files.iter().map(|(name, data)| (name.as_str(), data.as_slice())),
+            )? {
+                return Ok(data);
+            }
+            self.vprint(&jstr!(
+                "Entries of {&sarc_path.to_slash_lossy()} changed, original layout not kept"
+            ));
+            if meta.big_endian != self.be {
+                let mut writer = SarcWriter::new(if meta.big_endian {
+                    Endian::Big
+                } else {
+                    Endian::Little
+                });
+                writer.set_min_alignment(meta.min_alignment);
+                writer.files = std::mem::take(&mut sarc.files);
+                return Ok(writer.to_binary());
+            }
+        }
+        Ok(sarc.to_binary())
+    }
+
+    /// Packs unbuilt with `--modified-only` leave out their vanilla entries, listed in their
+    /// `.sarc.yml` files, so those need to be restored from the stock copy of the pack.
+    fn load_stock_pack(&self, root: &Path, pack: &Path) -> Result<Option<SarcWriter>> {
+        if !glob::glob(&pack.join("**/.sarc.yml").to_string_lossy())?
+            .filter_map(Result::ok)
+            .filter_map(|f| f.parent().and_then(|p| SarcMeta::load(p).ok().flatten()))
+            .any(|meta| !meta.vanilla.is_empty())
+        {
+            return Ok(None);
+        }
+        let rel = pack.strip_prefix(self.source.join(root))?;
+        let game_dirs = if root == &self.aoc {
+            if self.be {
+                vec![&self.dump.dlc_dir]
+            } else {
+                vec![&self.dump.dlc_dir_nx]
+            }
+        } else if self.be {
+            vec![&self.dump.update_dir, &self.dump.game_dir]
+        } else {
+            vec![&self.dump.game_dir_nx]
+        };
+        let stock = game_dirs
+            .into_iter()
+            .flatten()
+            .map(|dir| dir.join(rel))
+            .find(|p| p.exists())
+            .with_context(|| {
+                jstr!("Stock copy of {&rel.to_slash_lossy()} not found, check game folder settings")
+            })?;
+        self.vprint(&jstr!("Restoring vanilla files from {&stock.to_slash_lossy()}"));
+        Ok(Some(SarcWriter::from_sarc(&Sarc::new(fs::read(stock)?)?)))
     }
 
     fn build_packs(&self) -> Result<()> {
@@ -605,27 +847,37 @@ impl Builder {
                         && self.modified_files.any(|mf| mf.starts_with(f))
                 })
                 .collect::<Vec<_>>();
-            println!("Building {} packs", packs.len());
+            let stage = Stage::start(
+                "build-packs",
+                Some(packs.len()),
+                &format!("Building {} packs", packs.len()),
+            );
             packs.into_par_iter().try_for_each(|pack| -> Result<()> {
-                self.vprint(&format!(
-                    "Building {}",
-                    pack.file_name()
-                        .and_then(|n| n.to_str())
-                        .context("No pack name")?
-                ));
-                let out = self
-                    .output
-                    .join(root)
-                    .join(pack.strip_prefix(&source_root)?);
-                let mut sarc = if out.exists() {
-                    SarcWriter::from_sarc(&Sarc::new(fs::read(&out)?)?)
-                } else {
-                    SarcWriter::new(self.endian())
-                };
-                fs::create_dir_all(out.parent().context("No parent???")?)?;
-                fs::write(out, self.build_sarc(&pack, &mut sarc)?)?;
+                self.attempt(&pack, || -> Result<()> {
+                    self.vprint(&format!(
+                        "Building {}",
+                        pack.file_name()
+                            .and_then(|n| n.to_str())
+                            .context("No pack name")?
+                    ));
+                    let out = self
+                        .output
+                        .join(root)
+                        .join(pack.strip_prefix(&source_root)?);
+                    let mut sarc = if out.exists() {
+                        SarcWriter::from_sarc(&Sarc::new(fs::read(&out)?)?)
+                    } else if let Some(stock) = self.load_stock_pack(root, &pack)? {
+                        stock
+                    } else {
+                        SarcWriter::new(self.endian())
+                    };
+                    fs::create_dir_all(out.parent().context("No parent???")?)?;
+                    write_file(&out, self.build_sarc(&pack, &mut sarc)?)?;
+                    Ok(())
+                })?;
                 Ok(())
             })?;
+            stage.finish();
         }
         Ok(())
     }
@@ -634,10 +886,6 @@ impl Builder {
         for root in [&self.aoc, &self.content] {
             let map_dir = self.source.join(root).join("Map");
             if self.modified_files.any(|f| f.starts_with(&map_dir)) {
-                println!(
-                    "Building {} maps",
-                    if root == &self.aoc { "DLC" } else { "base" }
-                );
                 let yml_ext = Some(OsStr::new("yml"));
                 let mut files = Vec::with_capacity(self.modified_files.len());
                 self.modified_files.scan(|f| {
@@ -645,23 +893,45 @@ impl Builder {
                         files.push(f.clone())
                     }
                 });
+                let stage = Stage::start(
+                    "build-maps",
+                    Some(files.len()),
+                    &format!(
+                        "Building {} maps",
+                        if root == &self.aoc { "DLC" } else { "base" }
+                    ),
+                );
                 files.into_par_iter().try_for_each(|f| -> Result<()> {
-                    let out = self
-                        .output
-                        .join(root)
-                        .join("Map")
-                        .join(f.strip_prefix(&map_dir)?);
-                    fs::create_dir_all(out.parent().context("No parent??")?)?;
-                    fs::write(
-                        if out.extension() == yml_ext {
-                            out.with_extension("")
-                        } else {
-                            out
-                        },
-                        self.get_resource_data(&f)?,
-                    )?;
+                    self.attempt(&f, || -> Result<()> {
+                        let out = self
+                            .output
+                            .join(root)
+                            .join("Map")
+                            .join(f.strip_prefix(&map_dir)?);
+                        fs::create_dir_all(out.parent().context("No parent??")?)?;
+                        let data = self.timed(
+                            Span::MapUnit,
+                            || {
+                                f.strip_prefix(&map_dir)
+                                    .unwrap_or(&f)
+                                    .to_slash_lossy()
+                                    .into_owned()
+                            },
+                            || self.get_resource_data(&f),
+                        )?;
+                        write_file(
+                            &if out.extension() == yml_ext {
+                                out.with_extension("")
+                            } else {
+                                out
+                            },
+                            data,
+                        )?;
+                        Ok(())
+                    })?;
                     Ok(())
                 })?;
+                stage.finish();
             }
         }
         Ok(())
@@ -696,40 +966,49 @@ impl Builder {
             })
             .collect();
         if !misc_files.is_empty() {
-            println!("Building {} miscellaneous files", misc_files.len());
+            let stage = Stage::start(
+                "build-misc",
+                Some(misc_files.len()),
+                &format!("Building {} miscellaneous files", misc_files.len()),
+            );
             misc_files.into_par_iter().try_for_each(|f| -> Result<()> {
-                let out = self.output.join(f.strip_prefix(&self.source)?);
-                fs::create_dir_all(out.parent().context("No parent???")?)?;
-                if let Some(canon) = self.get_canon_name(&f) {
-                    let data = fs::read(f)?;
-                    self.set_resource_size(&canon, &data);
-                    fs::write(&out, data)?;
-                } else {
-                    fs::copy(f, &out)?;
-                }
+                self.attempt(&f, || -> Result<()> {
+                    let out = self.output.join(f.strip_prefix(&self.source)?);
+                    fs::create_dir_all(out.parent().context("No parent???")?)?;
+                    if let Some(canon) = self.get_canon_name(&f) {
+                        let data = fs::read(&f)?;
+                        self.set_resource_size(&canon, &data);
+                        write_file(&out, data)?;
+                    } else {
+                        replace_file(&out, |temp| fs::copy(&f, temp).map(|_| ()))?;
+                    }
+                    Ok(())
+                })?;
                 Ok(())
             })?;
+            stage.finish();
         }
         Ok(())
     }
 
     fn build_rstb(&self) -> Result<()> {
-        println!("Building RSTB");
+        let stage = Stage::start("build-rstb", None, "Building RSTB");
         let res_dir = self.output.join(&self.content).join("System/Resource");
         fs::create_dir_all(&res_dir)?;
-        fs::write(
-            res_dir.join("ResourceSizeTable.product.srsizetable"),
-            compress(self.size_table.lock().unwrap().to_binary(if self.be {
+        write_file(
+            &res_dir.join("ResourceSizeTable.product.srsizetable"),
+            self.compress(self.size_table.lock().unwrap().to_binary(if self.be {
                 rstb::Endian::Big
             } else {
                 rstb::Endian::Little
             })),
         )?;
+        stage.finish();
         Ok(())
     }
 
     fn update_db(&mut self) -> Result<()> {
-        println!("Saving state");
+        let stage = Stage::start("save", None, "Saving state");
         let moment = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)?
             .as_secs();
@@ -745,12 +1024,58 @@ impl Builder {
                 t
             )?;
         }
+        stage.finish();
+        Ok(())
+    }
+
+    fn remove_stale(&self) -> Result<()> {
+        let list = self.source.join(REMOVED_FILE);
+        if !list.exists() {
+            return Ok(());
+        }
+        let stage = Stage::start("remove-stale", None, "Removing outputs of removed files");
+        for line in fs::read_to_string(&list)?.lines().filter(|l| !l.is_empty()) {
+            let (file, nested) = match line.split_once("//") {
+                Some((file, nested)) => (file, Some(nested)),
+                None => (line, None),
+            };
+            let out = self.output.join(file);
+            if out.exists() {
+                if let Some(nested) = nested {
+                    let data = fs::read(&out)?;
+                    let mut pack = SarcWriter::from_sarc(&Sarc::new(&data[..])?);
+                    if pack.files.shift_remove(nested).is_some() {
+                        self.vprint(&jstr!("Removing {nested} from {file}"));
+                        let new_data = pack.to_binary();
+                        write_file(
+                            &out,
+                            if data.starts_with(b"Yaz0") {
+                                self.compress(new_data)
+                            } else {
+                                new_data
+                            },
+                        )?;
+                    }
+                } else {
+                    self.vprint(&jstr!("Removing {file}"));
+                    fs::remove_file(&out)?;
+                }
+            }
+            if nested.unwrap_or(file).contains("Event/") {
+                // Make sure the event info is rebuilt without the removed event
+                let _ = self
+                    .modified_files
+                    .insert(self.source_content().join("Event/EventInfo"));
+            }
+        }
+        fs::remove_file(list)?;
+        stage.finish();
         Ok(())
     }
 
     fn build_meta(&self) -> Result<()> {
         if self.be {
-            let mut file = fs::File::create(self.output.join("rules.txt"))?;
+            let mut file = vec![];
             writeln!(file, "[Definition]")?;
             writeln!(
                 file,
@@ -767,34 +1092,74 @@ impl Builder {
                 writeln!(file, "{} = {}", k, v)?;
             }
             writeln!(file, "version = 7")?;
+            write_file(&self.output.join("rules.txt"), file)?;
         }
         Ok(())
     }
 
-    pub fn build(&mut self) -> Result<()> {
-        if !validate_source(&self.source) {
-            return Err(anyhow!("Source folder is not a Hyrule Builder project"));
-        }
-        self.load_modified_files()?;
+    fn build_all(&mut self) -> Result<()> {
+        self.stage("load_modified_files", Self::load_modified_files)?;
         if self.modified_files.is_empty() {
-            println!("Nope, nothing to do");
+            message::status("Nope, nothing to do");
             return Ok(());
         }
+        self.stage("remove_stale", |b| b.remove_stale())?;
         if self.source_content().join("Actor/ActorInfo").exists() {
-            self.load_actorinfo()?;
-        }
-        self.build_actors()?;
-        self.build_actorinfo()?;
-        self.build_events()?;
-        self.build_texts()?;
-        self.build_packs()?;
-        self.build_maps()?;
-        self.build_misc()?;
-        self.build_rstb()?;
+            self.stage("load_actorinfo", Self::load_actorinfo)?;
+        }
+        self.stage("build_actors", Self::build_actors)?;
+        self.stage("build_actorinfo", Self::build_actorinfo)?;
+        self.stage("build_events", Self::build_events)?;
+        self.stage("build_texts", |b| b.build_texts())?;
+        self.stage("build_packs", |b| b.build_packs())?;
+        self.stage("build_maps", |b| b.build_maps())?;
+        self.stage("build_misc", |b| b.build_misc())?;
+        self.stage("build_rstb", |b| b.build_rstb())?;
         self.build_meta()?;
-        self.update_db()?;
+        if let Some(artifacts) = self.artifacts.as_ref() {
+            let (reused, made) = artifacts.stats();
+            self.vprint(&format!(
+                "Reused {} cached files, cached {} new ones",
+                reused, made
+            ));
+            let trimmed = artifacts.finish();
+            if trimmed > 0 {
+                self.vprint(&format!("Removed {} old files from the cache", trimmed));
+            }
+        }
+        // Files which failed have to be built again next time
+        if self.diagnostics.lock().unwrap().is_empty() {
+            self.stage("update_db", Self::update_db)?;
+        }
         Ok(())
     }
+
+    /// Builds the project. Errors are printed with their files and positions. With
+    /// `keep_going`, everything which can be built is, and all errors are printed at the end.
+    pub fn build(&mut self) -> Result<()> {
+        if !validate_source(&self.source) {
+            return Err(anyhow!("Source folder is not a Hyrule Builder project"));
+        }
+        let mut result = self.build_all();
+        if let Some(timings) = self.timings.as_ref() {
+            result = result.and(timings.finish());
+        }
+        let mut diagnostics = std::mem::take(&mut *self.diagnostics.lock().unwrap());
+        if let Err(e) = result {
+            diagnostics.push(Diagnostic::from_error(&e));
+        }
+        if diagnostics.is_empty() {
+            return Ok(());
+        }
+        diagnostics.sort_by(|a, b| (&a.file, a.line, a.column).cmp(&(&b.file, b.line, b.column)));
+        for diagnostic in &diagnostics {
+            diagnostic.print(&self.source);
+        }
+        Err(match diagnostics.len() {
+            1 => anyhow!("Build failed"),
+            count => anyhow!("Build failed with {} errors", count),
+        })
+    }
 }
 
 const CRC32: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
@@ -807,12 +1172,12 @@ fn hash_name(name: &str) -> u32 {
 #[cfg(test)]
 mod tests {
     use super::{Builder, WarnLevel};
-    use botw_utils::hashes::{Platform, StockHashTable};
+    use crate::game_dump::StockHashes;
     use rstb::ResourceSizeTable;
     use rustc_hash::FxBuildHasher;
-    use scc::{HashMap as SyncMap, HashSet};
+    use scc::HashSet;
     use std::{
-        collections::HashMap,
+        collections::{BTreeMap, HashMap},
         path::PathBuf,
         sync::{Arc, Mutex},
     };
@@ -852,10 +1217,10 @@ mod tests {
         Builder {
             be: true,
             file_times: HashMap::default(),
-            meta: HashMap::default(),
+            meta: BTreeMap::new(),
             modified_files: HashSet::with_hasher(FxBuildHasher),
             actorinfo: None,
-            hash_table: StockHashTable::new(&Platform::WiiU),
+            hash_table: StockHashes::bundled(true),
             size_table: Arc::new(Mutex::new(ResourceSizeTable::new_from_stock(
                 rstb::Endian::Big,
             ))),
@@ -881,9 +1246,17 @@ mod tests {
                 }
                 events
             },
-            compiled: SyncMap::with_hasher(FxBuildHasher),
+            compiled: Default::default(),
             verbose: false,
             warn: WarnLevel::Warn,
+            warnings: Default::default(),
+            compression: super::config::DEFAULT_COMPRESSION,
+            reproducible: false,
+            dump: Default::default(),
+            keep_going: false,
+            diagnostics: Mutex::default(),
+            timings: None,
+            artifacts: None,
         }
         .build()
         .unwrap()
@@ -0,0 +1,199 @@
+use crate::message::status;
+use anyhow::{Context, Result};
+use fs_err as fs;
+use serde::Serialize;
+use std::{
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How many of the slowest actor packs, event packs, and map units to list
+const SLOWEST_COUNT: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Span {
+    Stage,
+    ActorPack,
+    EventPack,
+    MapUnit,
+}
+
+impl Span {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Stage => "stage",
+            Self::ActorPack => "actor pack",
+            Self::EventPack => "event pack",
+            Self::MapUnit => "map unit",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Record {
+    span: Span,
+    name: String,
+    /// Since the build started
+    start: Duration,
+    duration: Duration,
+    /// 0 for the main thread, or 1 more than the rayon thread index
+    thread: usize,
+}
+
+/// One complete event in the Chrome trace event format, as read by `about:tracing` and
+/// Perfetto. Times are in microseconds.
+#[derive(Debug, Serialize)]
+struct TraceEvent<'a> {
+    name: &'a str,
+    cat: Span,
+    ph: &'static str,
+    ts: u128,
+    dur: u128,
+    pid: u32,
+    tid: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Trace<'a> {
+    trace_events: Vec<TraceEvent<'a>>,
+    display_time_unit: &'static str,
+}
+
+/// Wall time of each build stage and of each actor pack, event pack, and map unit, for
+/// `build --timings`
+#[derive(Debug)]
+pub struct Timings {
+    start: Instant,
+    records: Mutex<Vec<Record>>,
+    /// Where to write a Chrome trace, if anywhere
+    pub trace: Option<PathBuf>,
+}
+
+impl Timings {
+    pub fn new(trace: Option<PathBuf>) -> Self {
+        Self {
+            start: Instant::now(),
+            records: Mutex::default(),
+            trace,
+        }
+    }
+
+    /// Records something which started at `start` and has just finished
+    pub fn record(&self, span: Span, name: impl Into<String>, start: Instant) {
+        let record = Record {
+            span,
+            name: name.into(),
+            start: start.saturating_duration_since(self.start),
+            duration: start.elapsed(),
+            thread: rayon::current_thread_index().map_or(0, |i| i + 1),
+        };
+        self.records.lock().unwrap().push(record);
+    }
+
+    /// Prints the time taken by each stage and the slowest items, and writes the trace file
+    pub fn finish(&self) -> Result<()> {
+        let total = self.start.elapsed();
+        let records = self.records.lock().unwrap();
+        status!("Build timings:");
+        for record in records.iter().filter(|r| r.span == Span::Stage) {
+            status!(
+                "  {:<20} {:>8.2}s {:>5.1}%",
+                record.name,
+                record.duration.as_secs_f64(),
+                record.duration.as_secs_f64() / total.as_secs_f64().max(f64::EPSILON) * 100.0
+            );
+        }
+        status!("  {:<20} {:>8.2}s", "total", total.as_secs_f64());
+        let mut items: Vec<&Record> = records.iter().filter(|r| r.span != Span::Stage).collect();
+        if !items.is_empty() {
+            items.sort_by(|a, b| b.duration.cmp(&a.duration));
+            status!("Slowest items:");
+            for record in items.into_iter().take(SLOWEST_COUNT) {
+                status!(
+                    "  {:<10} {:>8.2}s  {}",
+                    record.span.label(),
+                    record.duration.as_secs_f64(),
+                    record.name
+                );
+            }
+        }
+        if let Some(path) = self.trace.as_ref() {
+            let trace = Trace {
+                trace_events: records
+                    .iter()
+                    .map(|r| TraceEvent {
+                        name: &r.name,
+                        cat: r.span,
+                        ph: "X",
+                        ts: r.start.as_micros(),
+                        dur: r.duration.as_micros(),
+                        pid: std::process::id(),
+                        tid: r.thread,
+                    })
+                    .collect(),
+                display_time_unit: "ms",
+            };
+            fs::write(path, serde_json::to_vec(&trace)?)
+                .context("Failed to write timings trace")?;
+            status!("Wrote timings trace to {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn trace_format() {
+        let timings = Timings::new(None);
+        timings.record(Span::ActorPack, "Enemy_Test", Instant::now());
+        let records = timings.records.lock().unwrap();
+        let event = TraceEvent {
+            name: &records[0].name,
+            cat: records[0].span,
+            ph: "X",
+            ts: 5,
+            dur: 10,
+            pid: 1,
+            tid: 0,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"name":"Enemy_Test","cat":"actor-pack","ph":"X","ts":5,"dur":10,"pid":1,"tid":0}"#
+        );
+    }
+
+    #[test]
+    fn finish_writes_trace() {
+        let dir = TempDir::new("timings");
+        fs::create_dir_all(&dir).unwrap();
+        let timings = Timings::new(Some(dir.join("trace.json")));
+        let start = Instant::now();
+        timings.record(Span::ActorPack, "Enemy_Test", start);
+        timings.record(Span::Stage, "build_actors", start);
+        timings.finish().unwrap();
+
+        let trace: serde_json::Value =
+            serde_json::from_slice(&fs::read(dir.join("trace.json")).unwrap()).unwrap();
+        assert_eq!(trace["displayTimeUnit"], "ms");
+        let events = trace["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        for (event, (name, cat)) in events
+            .iter()
+            .zip([("Enemy_Test", "actor-pack"), ("build_actors", "stage")])
+        {
+            assert_eq!(event["name"], name);
+            assert_eq!(event["cat"], cat);
+            assert_eq!(event["ph"], "X");
+            assert_eq!(event["pid"], std::process::id());
+            assert_eq!(event["tid"], 0);
+            assert!(event["ts"].is_u64() && event["dur"].is_u64());
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use crate::{
+    builder::{
+        actor::actor_files,
+        config::Warnings,
+        event::{event_files, NESTED_EVENTS, TITLE_EVENTS},
+        WarnLevel,
+    },
+    diagnostic::{Code, Diagnostic, InFile},
+    message::{self, Stage},
+};
+use anyhow::{format_err, Result};
+use fs_err as fs;
+use rayon::prelude::*;
+use roead::{aamp::ParameterIO, byml::Byml};
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+#[derive(Debug)]
+struct Problems {
+    found: Mutex<Vec<Diagnostic>>,
+    project: PathBuf,
+    /// The level of warnings the config doesn't set
+    warn: WarnLevel,
+    warnings: Warnings,
+}
+
+impl Problems {
+    #[inline]
+    fn add(&self, diagnostic: Diagnostic) {
+        self.found.lock().unwrap().push(diagnostic);
+    }
+
+    /// Adds a warning at the level the project's config and the warning flags set for it, as the
+    /// build would
+    fn warn(&self, code: Code, file: &Path, message: String) {
+        let rel = file.strip_prefix(&self.project).unwrap_or(file);
+        match self.warnings.level(code, rel, self.warn) {
+            WarnLevel::None => (),
+            WarnLevel::Warn => self.add(Diagnostic::warning(code, message).with_file(file)),
+            WarnLevel::Error => self.add(Diagnostic::error(code, message).with_file(file)),
+        }
+    }
+}
+
+fn check_source(file: &Path) -> Result<()> {
+    match file.extension().and_then(|e| e.to_str()) {
+        Some("yml") => {
+            let text = fs::read_to_string(file)?;
+            if text.starts_with("!io") {
+                ParameterIO::from_text(&text).map_err(|e| Diagnostic::syntax(file, "AAMP", e))?;
+            } else {
+                Byml::from_text(&text).map_err(|e| Diagnostic::syntax(file, "BYML", e))?;
+            }
+        }
+        Some("msyt") => {
+            let text = fs::read_to_string(file)?;
+            // Same fallback as the build, for MSYT files written by older tools
+            let _: msyt::Msyt = serde_yml::from_str(&text)
+                .or_else(|e| {
+                    let deser = serde_yml::Deserializer::from_str(&text);
+                    serde_yml::with::singleton_map_recursive::deserialize(deser)
+                        .map_err(|_| e)
+                })
+                .map_err(|e| Diagnostic::yaml(file, "MSYT", &e))?;
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+fn check_actors(root: &Path, problems: &Problems) -> Result<()> {
+    glob::glob(&root.join("Actor/ActorLink/*.bxml.yml").to_string_lossy())?
+        .filter_map(Result::ok)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .for_each(|link| {
+            let name = link
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default()
+                .trim_end_matches(".bxml.yml");
+            match actor_files(root, &link).in_file(&link) {
+                Ok(files) => {
+                    for file in files.into_iter().filter(|f| !f.exists()) {
+                        // The build only warns about missing Havok files
+                        if file.starts_with(root.join("Physics")) {
+                            problems.warn(
+                                Code::MissingHavok,
+                                &file,
+                                format!("Havok file not found, but actor {} uses it", name),
+                            );
+                        } else {
+                            problems.add(Diagnostic::missing_file(
+                                &file,
+                                format!("File not found, but actor {} uses it", name),
+                            ));
+                        }
+                    }
+                }
+                Err(e) => problems.add(Diagnostic::from_error(&e)),
+            }
+        });
+    Ok(())
+}
+
+fn check_events(root: &Path, problems: &Problems) -> Result<()> {
+    let main_exts = [Some(OsStr::new("bfevfl")), Some(OsStr::new("bfevtm"))];
+    glob::glob(&root.join("Event/EventInfo/*.info.yml").to_string_lossy())?
+        .filter_map(Result::ok)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .for_each(|file| {
+            let name = file
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default()
+                .trim_end_matches(".info.yml");
+            if TITLE_EVENTS.contains(&name) {
+                return;
+            }
+            let files = fs::read_to_string(&file)
+                .map_err(anyhow::Error::from)
+                .and_then(|text| {
+                    Ok(Byml::from_text(text)
+                        .map_err(|e| Diagnostic::syntax(&file, "BYML", e))?
+                        .into_map()?)
+                })
+                .and_then(|info| event_files(root, name, &info))
+                .in_file(&file);
+            let files = match files {
+                Ok(files) => files,
+                Err(e) => {
+                    problems.add(Diagnostic::from_error(&e));
+                    return;
+                }
+            };
+            let (main, subfiles): (Vec<PathBuf>, Vec<PathBuf>) =
+                files.into_iter().partition(|f| {
+                    main_exts.contains(&f.extension())
+                        || f.file_stem()
+                            .and_then(|s| s.to_str())
+                            .map(|s| s.ends_with(".bdemo"))
+                            .unwrap_or(false)
+                });
+            // Events without their own flow files aren't built, the same as in the build
+            if main.iter().any(|f| !f.exists()) {
+                return;
+            }
+            // Files shared with title events are found in TitleBG.pack, as in the build
+            for file in subfiles.into_iter().filter(|f| {
+                !f.exists()
+                    && !f
+                        .strip_prefix(root)
+                        .map(|rel| root.join("Pack/TitleBG.pack").join(rel).exists())
+                        .unwrap_or(false)
+                    && !f
+                        .file_name()
+                        .and_then(|n| n.to_str())
+                        .map(|n| {
+                            TITLE_EVENTS
+                                .iter()
+                                .chain(NESTED_EVENTS)
+                                .any(|e| n.contains(e))
+                        })
+                        .unwrap_or(false)
+            }) {
+                problems.warn(
+                    Code::MissingEventFile,
+                    &file,
+                    format!("File not found, but event {} uses it", name),
+                );
+            }
+        });
+    Ok(())
+}
+
+/// Parses every source file in a project and resolves every actor and event file reference,
+/// the same as a build, but without writing anything. Warnings are given the level the build
+/// would give them, from `warnings` or else `warn`. Returns every problem found, sorted, and how
+/// many files were parsed.
+fn find_problems(
+    project: &Path,
+    be: bool,
+    warn: WarnLevel,
+    warnings: Warnings,
+    verbose: bool,
+) -> Result<(Vec<Diagnostic>, usize)> {
+    let roots: Vec<PathBuf> = if be {
+        vec![project.join("content"), project.join("aoc/0010")]
+    } else {
+        vec![
+            project.join("01007EF00011E000/romfs"),
+            project.join("01007EF00011F001/romfs"),
+        ]
+    }
+    .into_iter()
+    .filter(|r| r.exists())
+    .collect();
+    let problems = Problems {
+        found: Mutex::default(),
+        project: project.to_owned(),
+        warn,
+        warnings,
+    };
+
+    let mut stage = Stage::start("check-sources", None, "Checking source files");
+    let sources: Vec<PathBuf> = roots
+        .iter()
+        .map(|root| glob::glob(&root.join("**/*").to_string_lossy()))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter(|f| {
+            f.is_file()
+                && !f
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .map(|n| n.starts_with('.'))
+                    .unwrap_or(true)
+        })
+        .collect();
+    stage.count(sources.len());
+    sources.par_iter().for_each(|file| {
+        if let Err(e) = check_source(file).in_file(file) {
+            problems.add(Diagnostic::from_error(&e));
+        }
+    });
+    if verbose {
+        message::detail(&format!("Parsed {} files", sources.len()));
+    }
+    stage.finish();
+
+    let stage = Stage::start("check-references", None, "Checking actors and events");
+    for root in &roots {
+        check_actors(root, &problems)?;
+        check_events(root, &problems)?;
+    }
+    stage.finish();
+
+    let mut problems = problems.found.into_inner().unwrap();
+    problems.sort_by(|a, b| {
+        (&a.file, a.line, a.column, &a.message).cmp(&(&b.file, b.line, b.column, &b.message))
+    });
+    Ok((problems, sources.len()))
+}
+
+/// Checks a project the same as a build, but without writing anything. Reports every problem
+/// found at once.
+pub fn check(
+    project: &Path,
+    be: bool,
+    warn: WarnLevel,
+    warnings: Warnings,
+    verbose: bool,
+) -> Result<()> {
+    let project = dunce::canonicalize(project)?;
+    let (problems, sources) = find_problems(&project, be, warn, warnings, verbose)?;
+    let errors = problems.iter().filter(|p| p.is_error()).count();
+    for problem in &problems {
+        problem.print(&project);
+    }
+    if errors > 0 {
+        Err(format_err!(
+            "{} errors and {} warnings found",
+            errors,
+            problems.len() - errors
+        ))
+    } else {
+        message::success(&format!(
+            "No errors found in {} files ({} warnings)",
+            sources,
+            problems.len()
+        ));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+    use path_slash::PathExt;
+
+    #[test]
+    fn reports_every_problem() {
+        let project = TempDir::new("check");
+        let content = project.join("content");
+        for dir in ["Actor/ActorLink", "Actor/ASList", "Actor/ModelList"] {
+            fs::create_dir_all(content.join(dir)).unwrap();
+        }
+        fs::write(
+            content.join("Actor/ActorLink/Obj_Test.bxml.yml"),
+            "!io\nversion: 0\ntype: xml\nparam_root: !list\n  objects:\n    LinkTarget: !obj\n      \
+             ASUser: !str64 Obj_Test\n      AttentionUser: !str64 Obj_Gone\n      ModelUser: \
+             !str64 Obj_Gone\n      PhysicsUser: !str64 Obj_Gone\n  lists: {}\n",
+        )
+        .unwrap();
+        fs::write(
+            content.join("Actor/ASList/Obj_Test.baslist.yml"),
+            "!io\nversion: 0\ntype: xml\nparam_root: !list\n  objects: {}\n  lists:\n    \
+             ASDefines: !list\n      objects:\n        ASDefine_0: !obj\n          Name: !str64 \
+             Wait\n          Filename: !str64 Obj_Test_Wait\n      lists: {}\n",
+        )
+        .unwrap();
+        fs::write(
+            content.join("Actor/ModelList/Broken.bmodellist.yml"),
+            "!io\nversion: 0\ntype: [xml\n",
+        )
+        .unwrap();
+
+        let (problems, sources) =
+            find_problems(&project, true, WarnLevel::Warn, Warnings::default(), false).unwrap();
+        assert_eq!(sources, 3);
+        let mut found: Vec<(Code, String)> = problems
+            .iter()
+            .map(|p| {
+                let file = p.file.as_ref().unwrap().strip_prefix(&content).unwrap();
+                (p.code, file.to_slash_lossy().into_owned())
+            })
+            .collect();
+        found.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(
+            found,
+            vec![
+                (
+                    Code::MissingFile,
+                    "Actor/AS/Obj_Test_Wait.bas.yml".to_owned()
+                ),
+                (
+                    Code::MissingFile,
+                    "Actor/AttClientList/Obj_Gone.batcllist.yml".to_owned()
+                ),
+                (
+                    Code::Syntax,
+                    "Actor/ModelList/Broken.bmodellist.yml".to_owned()
+                ),
+                (
+                    Code::MissingFile,
+                    "Actor/ModelList/Obj_Gone.bmodellist.yml".to_owned()
+                ),
+                (
+                    Code::MissingFile,
+                    "Actor/Physics/Obj_Gone.bphysics.yml".to_owned()
+                ),
+            ]
+        );
+        assert!(problems.iter().all(|p| p.is_error()));
+    }
+
+    #[test]
+    fn warning_levels_follow_config_and_flags() {
+        let warnings: Warnings = serde_yml::from_str(
+            "deny: [missing-event-file]\nsuppress:\n  - code: missing-havok\n    paths: \
+             content/Physics/Cloth/*\n",
+        )
+        .unwrap();
+        let project = Path::new("project");
+        let levels = |warn| {
+            let problems = Problems {
+                found: Mutex::default(),
+                project: project.to_owned(),
+                warn,
+                warnings: warnings.clone(),
+            };
+            for (code, file) in [
+                (Code::MissingHavok, "content/Physics/Cloth/Npc_Test.hkcl"),
+                (Code::MissingHavok, "content/Physics/Ragdoll/Npc_Test.hkrg"),
+                (Code::MissingEventFile, "content/Event/Test.bfevfl"),
+            ] {
+                problems.warn(code, &project.join(file), "Not found".to_owned());
+            }
+            problems
+                .found
+                .into_inner()
+                .unwrap()
+                .iter()
+                .map(|p| (p.code, p.is_error()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            levels(WarnLevel::Warn),
+            vec![(Code::MissingHavok, false), (Code::MissingEventFile, true)]
+        );
+        assert_eq!(
+            levels(WarnLevel::Error),
+            vec![(Code::MissingHavok, true), (Code::MissingEventFile, true)]
+        );
+        assert_eq!(
+            levels(WarnLevel::None),
+            vec![(Code::MissingEventFile, true)]
+        );
+    }
+}
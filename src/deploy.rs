@@ -0,0 +1,183 @@
+use crate::{
+    message::{self, status},
+    util::replace_file,
+};
+use anyhow::{anyhow, Context, Result};
+use fs_err as fs;
+use path_slash::PathExt;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+/// Lists the files put in a deploy folder by the last deploy, so only those are ever deleted
+pub static DEPLOY_LIST: &str = ".hyrule_builder_deploy";
+
+/// The first line of the deploy list. Older lists without it are from deploys made while builds
+/// still rewrote output files in place, so every file in them is deployed again.
+static LIST_HEADER: &str = "# hyrule_builder deploy 2";
+
+/// Whether a deployed file still matches the output, by its size and modified time, which a hard
+/// link shares and a copy is given
+fn up_to_date(src: &Path, dest: &Path) -> Result<bool> {
+    let (src, dest) = (fs::metadata(src)?, fs::metadata(dest)?);
+    Ok(src.len() == dest.len() && src.modified()? == dest.modified()?)
+}
+
+/// Hard links a file into the deploy folder, or copies it if it can't be linked, as when the
+/// folders are on different drives. Builds replace output files instead of writing over them,
+/// so a link keeps the deployed data until the next deploy. Returns whether it was linked.
+fn deploy_file(src: &Path, dest: &Path) -> Result<bool> {
+    if replace_file(dest, |temp| fs::hard_link(src, temp)).is_ok() {
+        return Ok(true);
+    }
+    replace_file(dest, |temp| {
+        fs::copy(src, temp)?;
+        std::fs::File::options()
+            .write(true)
+            .open(temp)?
+            .set_modified(fs::metadata(src)?.modified()?)
+    })?;
+    Ok(false)
+}
+
+/// Removes empty folders left in the deploy folder after deleting files
+fn remove_empty_dirs(root: &Path, file: &Path) -> Result<()> {
+    for dir in file
+        .ancestors()
+        .skip(1)
+        .take_while(|d| d.starts_with(root) && *d != root)
+    {
+        if fs::read_dir(dir)?.next().is_some() {
+            break;
+        }
+        fs::remove_dir(dir)?;
+    }
+    Ok(())
+}
+
+/// Mirrors a built mod into a deploy folder, such as a Cemu graphic pack or an emulator's mod
+/// folder. Changed files are hard linked or copied, and files from the last deploy which are no longer in the
+/// output are deleted. Files not put there by a deploy are left alone, and the deploy fails
+/// instead of overwriting them unless `force` is set.
+pub fn deploy(output: &Path, target: &Path, force: bool, verbose: bool) -> Result<()> {
+    let output = dunce::canonicalize(output)?;
+    fs::create_dir_all(target)?;
+    let target = dunce::canonicalize(target)?;
+    if target.starts_with(&output) || output.starts_with(&target) {
+        return Err(anyhow!(
+            "Deploy folder {} overlaps the build output",
+            target.display()
+        ));
+    }
+    status!("Deploying to {}", target.display());
+    let files: BTreeSet<PathBuf> = glob::glob(&output.join("**/*").to_string_lossy())?
+        .filter_map(Result::ok)
+        .filter(|f| f.is_file())
+        .map(|f| f.strip_prefix(&output).map(|f| f.to_path_buf()))
+        .collect::<Result<_, _>>()?;
+    let list_path = target.join(DEPLOY_LIST);
+    let list = if list_path.exists() {
+        fs::read_to_string(&list_path)?
+    } else {
+        String::new()
+    };
+    let copies = list.lines().next() == Some(LIST_HEADER);
+    let last: BTreeSet<PathBuf> = list
+        .lines()
+        .filter(|l| !l.is_empty() && *l != LIST_HEADER)
+        .map(PathBuf::from)
+        .collect();
+
+    let foreign: Vec<_> = files
+        .iter()
+        .filter(|rel| !last.contains(*rel) && target.join(rel).exists())
+        .map(|rel| rel.to_slash_lossy().into_owned())
+        .collect();
+    if !foreign.is_empty() && !force {
+        return Err(anyhow!(
+            "{} files in {} weren't put there by a deploy, pass --force to overwrite them: {}",
+            foreign.len(),
+            target.display(),
+            foreign.join(", ")
+        ));
+    }
+
+    let (mut linked, mut copied, mut removed) = (0, 0, 0);
+    for rel in files.iter() {
+        let (src, dest) = (output.join(rel), target.join(rel));
+        if dest.exists() {
+            if copies && last.contains(rel) && up_to_date(&src, &dest)? {
+                continue;
+            }
+        } else if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if deploy_file(&src, &dest)
+            .with_context(|| format!("Failed to deploy {}", rel.to_slash_lossy()))?
+        {
+            linked += 1;
+        } else {
+            copied += 1;
+        }
+        if verbose {
+            message::detail(&format!("Deployed {}", rel.to_slash_lossy()));
+        }
+    }
+    for rel in last.difference(&files) {
+        let dest = target.join(rel);
+        if dest.is_file() {
+            fs::remove_file(&dest)?;
+            remove_empty_dirs(&target, &dest)?;
+            removed += 1;
+            if verbose {
+                message::detail(&format!("Removed {}", rel.to_slash_lossy()));
+            }
+        }
+    }
+    fs::write(
+        &list_path,
+        std::iter::once(LIST_HEADER.to_owned())
+            .chain(files.iter().map(|f| f.to_slash_lossy().into_owned()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )?;
+    status!(
+        "Deployed {} files ({} linked, {} copied, {} removed)",
+        files.len(),
+        linked,
+        copied,
+        removed
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{write_file, TempDir};
+
+    #[test]
+    fn deploy_links_and_keeps_foreign_files() {
+        let root = TempDir::new("deploy");
+        let (output, target) = (root.join("build"), root.join("deploy"));
+        fs::create_dir_all(output.join("content/Pack")).unwrap();
+        fs::create_dir_all(&target).unwrap();
+        let pack = output.join("content/Pack/Test.pack");
+        write_file(&pack, b"first").unwrap();
+        fs::write(target.join("rules.txt"), b"mine").unwrap();
+        deploy(&output, &target, false, false).unwrap();
+
+        // A build replaces the output file, so the deployed one keeps its data until a deploy
+        write_file(&pack, b"second").unwrap();
+        let deployed = target.join("content/Pack/Test.pack");
+        assert_eq!(fs::read(&deployed).unwrap(), b"first");
+
+        fs::write(output.join("rules.txt"), b"built").unwrap();
+        assert!(deploy(&output, &target, false, false).is_err());
+        assert_eq!(fs::read(target.join("rules.txt")).unwrap(), b"mine");
+        deploy(&output, &target, true, false).unwrap();
+        assert_eq!(fs::read(target.join("rules.txt")).unwrap(), b"built");
+        assert_eq!(fs::read(&deployed).unwrap(), b"second");
+    }
+}
@@ -0,0 +1,287 @@
+use path_slash::PathExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    any::Any,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+/// The kind of problem a diagnostic reports, so problems can be told apart without parsing
+/// their messages. Warning codes can be allowed or denied in `config.yml`, so their names
+/// shouldn't change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Code {
+    /// A YAML, AAMP, BYML, or MSYT source file could not be parsed
+    Syntax,
+    /// A file needed by an actor or event doesn't exist
+    MissingFile,
+    /// A file parsed, but is missing data or has data of the wrong type
+    InvalidData,
+    /// The builder crashed, which is always a bug
+    Panic,
+    /// Anything else, like I/O errors
+    Other,
+    /// Warning: a Havok file used by an actor isn't in the project, which is fine if the game
+    /// has it outside the actor pack
+    MissingHavok,
+    /// Warning: a file used by an event isn't in the project
+    MissingEventFile,
+    /// Warning: the game dump settings have a problem, or the dump is an unexpected version
+    GameDump,
+    /// Warning: a mod being unbuilt looks like it's for the other platform
+    PlatformMismatch,
+    /// Warning: a file differs between packs being unbuilt
+    PackConflict,
+    /// Warning: a file changed in a round trip
+    RoundTrip,
+    /// Warning: a removed actor or event is still mentioned in a project file
+    StaleReference,
+    /// Warning: a command left part of its job to be done by hand
+    ManualStep,
+}
+
+impl Code {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Syntax => "syntax",
+            Self::MissingFile => "missing-file",
+            Self::InvalidData => "invalid-data",
+            Self::Panic => "panic",
+            Self::Other => "other",
+            Self::MissingHavok => "missing-havok",
+            Self::MissingEventFile => "missing-event-file",
+            Self::GameDump => "game-dump",
+            Self::PlatformMismatch => "platform-mismatch",
+            Self::PackConflict => "pack-conflict",
+            Self::RoundTrip => "round-trip",
+            Self::StaleReference => "stale-reference",
+            Self::ManualStep => "manual-step",
+        }
+    }
+
+    /// Warnings raised by a build, whose levels can be set in `config.yml`
+    #[inline]
+    pub fn is_build_warning(&self) -> bool {
+        matches!(self, Self::MissingHavok | Self::MissingEventFile)
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem in a project, with where it was found if known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    pub message: String,
+    pub file: Option<PathBuf>,
+    /// 1-based line and column, when the parser reports them
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+/// Finds a position in a parser error message. Handles both `at line 3 column 7` and
+/// `file.yml:3:7` styles, since serde_yml and the roead YAML parsers word them differently.
+fn find_position(message: &str) -> (Option<usize>, Option<usize>) {
+    fn number_after(text: &str, word: &str) -> Option<usize> {
+        let start = text.find(word)? + word.len();
+        text[start..]
+            .trim_start()
+            .split(|c: char| !c.is_ascii_digit())
+            .next()
+            .and_then(|n| n.parse().ok())
+    }
+    let line = number_after(message, "line ");
+    if line.is_some() {
+        return (
+            line,
+            number_after(message, "column ").or_else(|| number_after(message, "col ")),
+        );
+    }
+    let mut parts = message
+        .split(':')
+        .skip_while(|s| s.trim().parse::<usize>().is_err());
+    let line = parts.next().and_then(|s| s.trim().parse().ok());
+    let column = parts.next().and_then(|s| number_after(s, ""));
+    (line, column)
+}
+
+impl Diagnostic {
+    pub fn error(code: Code, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            file: None,
+            line: None,
+            column: None,
+        }
+    }
+
+    pub fn warning(code: Code, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(code, message)
+        }
+    }
+
+    pub fn with_file(mut self, file: impl AsRef<Path>) -> Self {
+        self.file = Some(file.as_ref().to_owned());
+        self
+    }
+
+    /// A parse error in a source file, positioned from the parser's message
+    pub fn syntax(file: &Path, kind: &str, err: impl fmt::Display) -> Self {
+        let err = format!("{:#}", err);
+        let (line, column) = find_position(&err);
+        Self {
+            line,
+            column,
+            ..Self::error(
+                Code::Syntax,
+                format!("Failed to parse {} file: {}", kind, err),
+            )
+            .with_file(file)
+        }
+    }
+
+    /// The same as [`Diagnostic::syntax`], for serde_yml errors which know their position
+    pub fn yaml(file: &Path, kind: &str, err: &serde_yml::Error) -> Self {
+        let mut diagnostic = Self::syntax(file, kind, err);
+        if let Some(location) = err.location() {
+            diagnostic.line = Some(location.line());
+            diagnostic.column = Some(location.column());
+        }
+        diagnostic
+    }
+
+    pub fn missing_file(file: &Path, message: impl Into<String>) -> Self {
+        Self::error(Code::MissingFile, message).with_file(file)
+    }
+
+    /// Turns a panic payload from [`std::panic::catch_unwind`] into an error
+    pub fn from_panic(payload: Box<dyn Any + Send>, context: &str) -> Self {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_owned());
+        Self::error(
+            Code::Panic,
+            format!("{} crashed, please report this: {}", context, message),
+        )
+    }
+
+    /// Finds the diagnostic behind an error, or wraps the error in a new one. Context added
+    /// to a diagnostic is kept in its message. Other errors are I/O problems if caused by one,
+    /// or else bad data.
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        match err.chain().find_map(|e| e.downcast_ref::<Diagnostic>()) {
+            Some(diagnostic) => {
+                let mut diagnostic = diagnostic.clone();
+                let context: Vec<String> = err
+                    .chain()
+                    .take_while(|e| e.downcast_ref::<Diagnostic>().is_none())
+                    .map(|e| e.to_string())
+                    .collect();
+                if !context.is_empty() {
+                    diagnostic.message = format!("{}: {}", context.join(": "), diagnostic.message);
+                }
+                diagnostic
+            }
+            None => {
+                let code = if err
+                    .chain()
+                    .any(|e| e.downcast_ref::<std::io::Error>().is_some())
+                {
+                    Code::Other
+                } else {
+                    Code::InvalidData
+                };
+                Self::error(code, format!("{:#}", err))
+            }
+        }
+    }
+
+    #[inline]
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Prints the diagnostic, with its file relative to `root` where possible
+    #[inline]
+    pub fn print(&self, root: &Path) {
+        crate::message::diagnostic(self, root)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}", file.to_slash_lossy())?;
+            if let Some(line) = self.line {
+                write!(f, ":{}", line)?;
+                if let Some(column) = self.column {
+                    write!(f, ":{}", column)?;
+                }
+            }
+            write!(f, ": ")?;
+        }
+        write!(f, "[{}] {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+pub trait InFile<T> {
+    /// Ties an error to the file it was found in, unless it already names one
+    fn in_file(self, file: &Path) -> anyhow::Result<T>;
+}
+
+impl<T> InFile<T> for anyhow::Result<T> {
+    fn in_file(self, file: &Path) -> anyhow::Result<T> {
+        self.map_err(|e| {
+            let diagnostic = Diagnostic::from_error(&e);
+            if diagnostic.file.is_some() {
+                diagnostic.into()
+            } else {
+                diagnostic.with_file(file).into()
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions() {
+        assert_eq!(
+            find_position("invalid type: string, expected u32 at line 12 column 7"),
+            (Some(12), Some(7))
+        );
+        assert_eq!(
+            find_position("(unnamed):4:15 (73B): ERROR: could not find ':' colon"),
+            (Some(4), Some(15))
+        );
+        assert_eq!(find_position("Missing ParamSet"), (None, None));
+
+        let err = anyhow::Error::new(Diagnostic::missing_file(Path::new("a.yml"), "Not found"))
+            .context("Failed to build actor X");
+        let diagnostic = Diagnostic::from_error(&err);
+        assert_eq!(diagnostic.code, Code::MissingFile);
+        assert_eq!(diagnostic.message, "Failed to build actor X: Not found");
+    }
+}
@@ -0,0 +1,382 @@
+use crate::{
+    message::status,
+    settings::{GameProfile, Settings},
+};
+use anyhow::{anyhow, Context, Result};
+use botw_utils::{extensions::SARC_EXTS, get_canon_name_without_root};
+use fs_err as fs;
+use path_slash::PathExt;
+use rayon::prelude::*;
+use roead::sarc::Sarc;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// Bumped whenever the index format changes, so old caches are rebuilt
+const INDEX_VERSION: u32 = 2;
+
+/// Folders in the game dump whose modified times are checked to tell if it has changed
+static STAMP_DIRS: &[&str] = &["", "Pack", "Actor/Pack"];
+
+/// Which part of the game dump a file comes from, in order of priority
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Source {
+    Base,
+    Update,
+    Dlc,
+}
+
+/// Where to find a file in the game dump: a file relative to one of the dump folders, and the
+/// names of the SARC entries to follow inside it, if it is nested.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    pub source: Source,
+    pub file: String,
+    pub nested: Vec<String>,
+}
+
+impl Location {
+    /// Loose files take priority over nested ones, and newer files over older ones
+    #[inline]
+    fn priority(&self) -> (usize, std::cmp::Reverse<Source>, &str) {
+        (
+            self.nested.len(),
+            std::cmp::Reverse(self.source),
+            self.file.as_str(),
+        )
+    }
+}
+
+/// A cached map of every canonical resource path in the game dump to its location, stored in the
+/// local data folder next to the settings.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DumpIndex {
+    version: u32,
+    be: bool,
+    #[serde(default)]
+    profile: Option<String>,
+    base: Option<PathBuf>,
+    update: Option<PathBuf>,
+    dlc: Option<PathBuf>,
+    /// The latest modified time of each game folder, its packs, and its actor packs
+    #[serde(default)]
+    stamps: BTreeMap<Source, u64>,
+    files: BTreeMap<String, Location>,
+}
+
+/// The latest modified time, in seconds, of a game folder's pack folders and the packs in them,
+/// which changes whenever files are added, removed, or replaced there
+fn stamp(dir: &Path) -> Option<u64> {
+    let mtime = |path: &Path| -> Option<u64> {
+        path.metadata()
+            .and_then(|m| m.modified())
+            .ok()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs())
+    };
+    STAMP_DIRS
+        .iter()
+        .map(|d| dir.join(d))
+        .filter(|d| d.is_dir())
+        .flat_map(|d| {
+            let files = std::fs::read_dir(&d)
+                .into_iter()
+                .flatten()
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|f| f.is_file());
+            std::iter::once(d).chain(files)
+        })
+        .filter_map(|f| mtime(&f))
+        .max()
+}
+
+#[inline]
+fn canon_name(source: Source, name: &str) -> String {
+    let canon = get_canon_name_without_root(name.trim_start_matches('/'));
+    if source == Source::Dlc && !canon.starts_with("Aoc/0010/") {
+        ["Aoc/0010/", &canon].concat()
+    } else {
+        canon
+    }
+}
+
+fn index_sarc(
+    source: Source,
+    file: &str,
+    nested: &[String],
+    data: &[u8],
+    files: &mut Vec<(String, Location)>,
+) -> Result<()> {
+    let sarc = Sarc::new(data)?;
+    for entry in sarc.files() {
+        let name = match entry.name() {
+            Some(name) => name,
+            None => continue,
+        };
+        let mut nested = nested.to_vec();
+        nested.push(name.to_owned());
+        if SARC_EXTS.contains(&Path::new(name).extension().and_then(|e| e.to_str()))
+            && entry.data().len() > 0x15
+        {
+            // Some nested files with SARC extensions are not actually SARCs, so skip those
+            let _ = index_sarc(source, file, &nested, entry.data(), files);
+        }
+        files.push((
+            canon_name(source, name),
+            Location {
+                source,
+                file: file.to_owned(),
+                nested,
+            },
+        ));
+    }
+    Ok(())
+}
+
+impl DumpIndex {
+    /// Each game profile has its own index, so switching profiles doesn't rebuild it
+    #[inline]
+    pub fn get_index_path(profile: Option<&str>, be: bool) -> PathBuf {
+        let platform = if be { "wiiu" } else { "switch" };
+        Settings::get_settings_path().with_file_name(match profile {
+            Some(profile) => format!("index_{}_{}.json", profile, platform),
+            None => format!("index_{}.json", platform),
+        })
+    }
+
+    /// Loads the cached index for the platform, building it if there is none, it can't be read,
+    /// or the game folders in the settings or their contents have changed since it was built.
+    pub fn load(settings: &GameProfile, be: bool) -> Result<Self> {
+        let path = Self::get_index_path(settings.name.as_deref(), be);
+        if path.exists() {
+            match std::fs::read_to_string(&path)
+                .ok()
+                .and_then(|text| serde_json::from_str::<Self>(&text).ok())
+            {
+                Some(index) if index.version == INDEX_VERSION && index.matches(settings) => {
+                    return Ok(index);
+                }
+                Some(_) => (),
+                None => status!("Game file index is damaged, rebuilding it"),
+            }
+        }
+        let index = Self::build(settings, be)?;
+        index.save()?;
+        Ok(index)
+    }
+
+    fn empty(settings: &GameProfile, be: bool) -> Self {
+        let (base, update, dlc) = if be {
+            (
+                settings.game_dir.clone(),
+                settings.update_dir.clone(),
+                settings.dlc_dir.clone(),
+            )
+        } else {
+            (settings.game_dir_nx.clone(), None, settings.dlc_dir_nx.clone())
+        };
+        let stamps = [
+            (Source::Base, &base),
+            (Source::Update, &update),
+            (Source::Dlc, &dlc),
+        ]
+        .into_iter()
+        .filter_map(|(source, dir)| Some((source, stamp(dir.as_deref()?)?)))
+        .collect();
+        Self {
+            version: INDEX_VERSION,
+            be,
+            profile: settings.name.clone(),
+            base,
+            update,
+            dlc,
+            stamps,
+            files: BTreeMap::new(),
+        }
+    }
+
+    fn matches(&self, settings: &GameProfile) -> bool {
+        let current = Self::empty(settings, self.be);
+        self.base == current.base
+            && self.update == current.update
+            && self.dlc == current.dlc
+            && self.stamps == current.stamps
+    }
+
+    #[inline]
+    pub fn dir(&self, source: Source) -> Option<&Path> {
+        match source {
+            Source::Base => self.base.as_deref(),
+            Source::Update => self.update.as_deref(),
+            Source::Dlc => self.dlc.as_deref(),
+        }
+    }
+
+    /// Indexes every file in the game dump, including the contents of packs and other SARCs
+    pub fn build(settings: &GameProfile, be: bool) -> Result<Self> {
+        let mut index = Self::empty(settings, be);
+        if index.base.is_none() && index.update.is_none() && index.dlc.is_none() {
+            return Err(anyhow!("Game directories not set"));
+        }
+        status!("Indexing game files, this will take a while the first time...");
+        let mut sources: Vec<(Source, PathBuf)> = vec![];
+        for source in [Source::Base, Source::Update, Source::Dlc] {
+            if let Some(dir) = index.dir(source) {
+                for file in glob::glob(&dir.join("**/*.*").to_string_lossy())?
+                    .filter_map(Result::ok)
+                    .filter(|f| f.is_file())
+                {
+                    sources.push((source, file));
+                }
+            }
+        }
+        let found = sources
+            .into_par_iter()
+            .map(|(source, path)| -> Result<Vec<(String, Location)>> {
+                let file = path
+                    .strip_prefix(index.dir(source).unwrap())?
+                    .to_slash_lossy()
+                    .into_owned();
+                let mut files = vec![];
+                if SARC_EXTS.contains(&path.extension().and_then(|e| e.to_str())) {
+                    let data = fs::read(&path)?;
+                    if data.len() > 0x15 {
+                        let _ = index_sarc(source, &file, &[], &data, &mut files);
+                    }
+                }
+                files.push((
+                    canon_name(source, &file),
+                    Location {
+                        source,
+                        file,
+                        nested: vec![],
+                    },
+                ));
+                Ok(files)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (canon, location) in found.into_iter().flatten() {
+            match index.files.get(&canon) {
+                Some(existing) if existing.priority() <= location.priority() => (),
+                _ => {
+                    index.files.insert(canon, location);
+                }
+            }
+        }
+        status!("Indexed {} game files", index.files.len());
+        Ok(index)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::get_index_path(self.profile.as_deref(), self.be);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn get(&self, canon: &str) -> Option<&Location> {
+        self.files.get(canon)
+    }
+
+    /// Iterates all canonical paths and their locations
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Location)> {
+        self.files.iter()
+    }
+
+    /// Reads the raw data of an indexed file, following it into any SARCs it is nested in
+    pub fn read(&self, location: &Location) -> Result<Vec<u8>> {
+        let path = self
+            .dir(location.source)
+            .context("Game directory not set")?
+            .join(&location.file);
+        let mut data =
+            fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        for name in &location.nested {
+            let sarc = Sarc::new(&data[..])?;
+            data = sarc
+                .get(name)
+                .with_context(|| format!("{} missing from {}", name, location.file))?
+                .data
+                .to_vec();
+        }
+        Ok(data)
+    }
+
+    /// Looks up and reads a file by its canonical path
+    pub fn read_canon(&self, canon: &str) -> Result<(Vec<u8>, &Location)> {
+        let location = self
+            .get(canon)
+            .with_context(|| format!("{} not found in game files", canon))?;
+        Ok((self.read(location)?, location))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn canon_names() {
+        assert_eq!(
+            canon_name(Source::Update, "Actor/Pack/Enemy_Lynel_Senior.sbactorpack"),
+            "Actor/Pack/Enemy_Lynel_Senior.bactorpack"
+        );
+        assert_eq!(
+            canon_name(Source::Dlc, "Pack/AocMainField.pack"),
+            "Aoc/0010/Pack/AocMainField.pack"
+        );
+        let loose = Location {
+            source: Source::Base,
+            file: "Actor/Pack/Obj_Test.sbactorpack".into(),
+            nested: vec![],
+        };
+        let nested = Location {
+            source: Source::Update,
+            file: "Pack/TitleBG.pack".into(),
+            nested: vec!["Actor/Pack/Obj_Test.sbactorpack".into()],
+        };
+        let update = Location {
+            source: Source::Update,
+            ..loose.clone()
+        };
+        assert!(loose.priority() < nested.priority());
+        assert!(update.priority() < loose.priority());
+    }
+
+    #[test]
+    fn stale_when_packs_change() {
+        let dir = TempDir::new("dump");
+        fs::create_dir_all(dir.join("Pack")).unwrap();
+        let pack = dir.join("Pack/Bootup.pack");
+        fs::write(&pack, b"SARC").unwrap();
+        let set_mtime = |secs: u64| {
+            std::fs::File::options()
+                .write(true)
+                .open(&pack)
+                .unwrap()
+                .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+                .unwrap();
+        };
+        let future = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 1000;
+        set_mtime(future);
+        let settings = GameProfile {
+            game_dir: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+        let index = DumpIndex::empty(&settings, true);
+        assert_eq!(index.stamps.get(&Source::Base), Some(&future));
+        assert!(index.matches(&settings));
+        set_mtime(future + 1);
+        assert!(!index.matches(&settings));
+    }
+}
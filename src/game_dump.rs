@@ -0,0 +1,310 @@
+use crate::{
+    diagnostic::Code,
+    dump_index::{DumpIndex, Source},
+    message,
+    settings::GameProfile,
+};
+use anyhow::{anyhow, Context, Result};
+use botw_utils::hashes::{Platform, StockHashTable};
+use fs_err as fs;
+use rstb::ResourceSizeTable;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+/// The game versions the bundled stock hash and size tables were made from
+pub static STOCK_VERSION: &str = "1.5.0";
+pub static STOCK_VERSION_NX: &str = "1.6.0";
+
+static RSTB_PATH: &str = "System/Resource/ResourceSizeTable.product.srsizetable";
+
+/// What was detected about a game dump when its folders were set
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DumpInfo {
+    pub version: String,
+    /// The region code, e.g. `US` or `EU`, or none if the dump has every region's languages
+    pub region: Option<String>,
+}
+
+impl DumpInfo {
+    #[inline]
+    pub fn is_stock(&self, be: bool) -> bool {
+        self.version == if be { STOCK_VERSION } else { STOCK_VERSION_NX }
+    }
+}
+
+/// Files every folder of each kind should have, used to catch folders set to the wrong place
+fn key_files(source: Source, be: bool) -> &'static [&'static str] {
+    match (source, be) {
+        (Source::Base, true) => &["Pack/Bootup.pack", "Pack/Dungeon000.pack", "Actor/Pack"],
+        (Source::Base, false) => &[
+            "Pack/Bootup.pack",
+            "Pack/Dungeon000.pack",
+            "Actor/Pack",
+            RSTB_PATH,
+        ],
+        (Source::Update, _) => &[
+            "Pack/Bootup.pack",
+            "Actor/Pack/Enemy_Lynel_Senior.sbactorpack",
+            RSTB_PATH,
+        ],
+        (Source::Dlc, _) => &["Pack/AocMainField.pack"],
+    }
+}
+
+/// The folder and platform a game folder setting is for
+pub fn setting_source(setting: &str) -> Option<(Source, bool)> {
+    match setting {
+        "game_dir" => Some((Source::Base, true)),
+        "update_dir" => Some((Source::Update, true)),
+        "dlc_dir" => Some((Source::Dlc, true)),
+        "game_dir_nx" => Some((Source::Base, false)),
+        "dlc_dir_nx" => Some((Source::Dlc, false)),
+        _ => None,
+    }
+}
+
+/// Reads the byte order mark of a SARC, returning whether it is big endian
+fn sarc_is_big_endian(file: &Path) -> Result<bool> {
+    let mut header = [0u8; 8];
+    fs::File::open(file)?.read_exact(&mut header)?;
+    if &header[0..4] != b"SARC" {
+        return Err(anyhow!("{} is not a SARC file", file.display()));
+    }
+    Ok(header[6..8] == [0xFE, 0xFF])
+}
+
+/// Checks that a folder has the files expected of its kind and platform
+pub fn check_folder(dir: &Path, source: Source, be: bool) -> Result<()> {
+    let missing: Vec<&str> = key_files(source, be)
+        .iter()
+        .copied()
+        .filter(|f| !dir.join(f).exists())
+        .collect();
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "{} does not look like a {} folder, missing {}",
+            dir.display(),
+            describe(source, be),
+            missing.join(", ")
+        ));
+    }
+    let pack = dir.join(key_files(source, be)[0]);
+    if sarc_is_big_endian(&pack)? != be {
+        return Err(anyhow!(
+            "{} is a {} folder, not {}",
+            dir.display(),
+            if be { "Switch" } else { "Wii U" },
+            if be { "Wii U" } else { "Switch" }
+        ));
+    }
+    Ok(())
+}
+
+fn describe(source: Source, be: bool) -> String {
+    format!(
+        "{} {}",
+        if be { "Wii U" } else { "Switch" },
+        match source {
+            Source::Base => "base game",
+            Source::Update => "update",
+            Source::Dlc => "DLC",
+        }
+    )
+}
+
+fn read_version(dir: &Path) -> Option<String> {
+    fs::read_to_string(dir.join("System/Version.txt"))
+        .ok()
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty())
+}
+
+/// Finds the region from the language packs, e.g. `Pack/Bootup_USen.pack`
+fn detect_region(dir: &Path) -> Option<String> {
+    let pattern = dir.join("Pack/Bootup_????.pack");
+    let regions: BTreeSet<String> = glob::glob(&pattern.to_string_lossy())
+        .ok()?
+        .filter_map(Result::ok)
+        .filter_map(|f| {
+            f.file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.trim_start_matches("Bootup_").get(..2))
+                .map(|r| r.to_owned())
+        })
+        .collect();
+    match regions.len() {
+        1 => regions.into_iter().next(),
+        _ => None,
+    }
+}
+
+/// The game folders for one platform from a profile
+fn folders(profile: &GameProfile, be: bool) -> Vec<(Source, &PathBuf)> {
+    let folders = if be {
+        [
+            (Source::Base, profile.game_dir.as_ref()),
+            (Source::Update, profile.update_dir.as_ref()),
+            (Source::Dlc, profile.dlc_dir.as_ref()),
+        ]
+    } else {
+        [
+            (Source::Base, profile.game_dir_nx.as_ref()),
+            (Source::Update, None),
+            (Source::Dlc, profile.dlc_dir_nx.as_ref()),
+        ]
+    };
+    folders
+        .into_iter()
+        .filter_map(|(source, dir)| dir.map(|d| (source, d)))
+        .collect()
+}
+
+/// Checks every folder set for a platform and that they belong to the same dump, returning
+/// what was detected and a list of problems found. Nothing is detected if no folders are set.
+pub fn validate(profile: &GameProfile, be: bool) -> (Option<DumpInfo>, Vec<String>) {
+    let folders = folders(profile, be);
+    let mut problems = vec![];
+    for (source, dir) in &folders {
+        if let Err(e) = check_folder(dir, *source, be) {
+            problems.push(e.to_string());
+        }
+    }
+    let get = |source| folders.iter().find(|(s, _)| *s == source).map(|(_, d)| *d);
+    let (base, update, dlc) = (get(Source::Base), get(Source::Update), get(Source::Dlc));
+    if let (Some(base), Some(update)) = (base, update) {
+        if base == update {
+            problems.push("Base game and update folders are the same".into());
+        } else if read_version(update).is_some() && read_version(update) == read_version(base) {
+            problems.push(format!(
+                "Update folder {} has the same version as the base game, it may be a base game folder",
+                update.display()
+            ));
+        }
+        if let (Some(a), Some(b)) = (detect_region(base), detect_region(update)) {
+            if a != b {
+                problems.push(format!(
+                    "Base game is region {} but update is region {}",
+                    a, b
+                ));
+            }
+        }
+    }
+    if be && dlc.is_some() && update.is_none() {
+        problems.push("DLC folder is set without an update folder".into());
+    }
+    // The Wii U version is the update's, since the base game is always 1.0.0
+    let main = if be { update.or(base) } else { base };
+    let info = main.and_then(read_version).map(|version| DumpInfo {
+        version,
+        region: base.and_then(detect_region).or_else(|| main.and_then(detect_region)),
+    });
+    (info, problems)
+}
+
+/// Prints validation problems as warnings
+pub fn print_problems(problems: &[String]) {
+    for problem in problems {
+        message::warning(Code::GameDump, problem);
+    }
+}
+
+/// Loads the stock RSTB from the game dump when it is a different version from the bundled
+/// one, so size estimates are compared against the right values.
+pub fn dump_rstb(profile: &GameProfile, be: bool) -> Result<Option<ResourceSizeTable>> {
+    let info = if be {
+        profile.dump_info.as_ref()
+    } else {
+        profile.dump_info_nx.as_ref()
+    };
+    match info {
+        Some(info) if !info.is_stock(be) => {
+            let dir = if be {
+                profile.update_dir.as_ref()
+            } else {
+                profile.game_dir_nx.as_ref()
+            };
+            match dir.map(|d| d.join(RSTB_PATH)).filter(|p| p.exists()) {
+                Some(path) => Ok(Some(
+                    ResourceSizeTable::from_binary(roead::yaz0::decompress(fs::read(&path)?)?)
+                        .with_context(|| format!("Failed to read stock RSTB from {}", path.display()))?,
+                )),
+                None => Ok(None),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Tells stock files from modified ones. The bundled hash tables are from the stock versions, so
+/// when the game dump is another version, files the bundled table doesn't know are compared with
+/// the dump's own copies instead.
+#[derive(Debug)]
+pub struct StockHashes {
+    table: StockHashTable,
+    dump: Option<DumpIndex>,
+}
+
+impl StockHashes {
+    /// The bundled hash table for a platform alone
+    pub fn bundled(be: bool) -> Self {
+        Self {
+            table: StockHashTable::new(&if be { Platform::WiiU } else { Platform::Switch }),
+            dump: None,
+        }
+    }
+
+    /// The bundled hash table, plus the game dump when it is not the version the table is from.
+    /// If the dump can't be indexed, only the bundled table is used.
+    pub fn new(profile: &GameProfile, be: bool) -> Self {
+        let mut hashes = Self::bundled(be);
+        let info = if be {
+            profile.dump_info.as_ref()
+        } else {
+            profile.dump_info_nx.as_ref()
+        };
+        if let Some(info) = info.filter(|info| !info.is_stock(be)) {
+            match DumpIndex::load(profile, be) {
+                Ok(index) => {
+                    message::status(&format!(
+                        "Game dump is version {}, comparing files with it instead of the stock \
+                         hashes from version {}",
+                        info.version,
+                        if be { STOCK_VERSION } else { STOCK_VERSION_NX }
+                    ));
+                    hashes.dump = Some(index);
+                }
+                Err(e) => message::warning(
+                    Code::GameDump,
+                    &format!(
+                        "Game dump is version {}, but it could not be indexed, so some unmodified \
+                         files may be treated as modified: {:#}",
+                        info.version, e
+                    ),
+                ),
+            }
+        }
+        hashes
+    }
+
+    /// Whether a (decompressed) file differs from its stock copy. New files count as modified if
+    /// `flag_new` is set.
+    pub fn is_file_modded(&self, canon: &str, data: &[u8], flag_new: bool) -> bool {
+        if !self.table.is_file_modded(canon, data, flag_new) {
+            return false;
+        }
+        match self
+            .dump
+            .as_ref()
+            .and_then(|index| index.read_canon(canon).ok())
+        {
+            Some((stock, _)) => {
+                roead::yaz0::decompress_if(&stock) != roead::yaz0::decompress_if(data)
+            }
+            None => true,
+        }
+    }
+}
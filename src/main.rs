@@ -1,17 +1,16 @@
 use crate::{
     builder::BuildConfig,
-    settings::{ConfigCommand, Settings},
+    game_dump::StockHashes,
+    settings::{ConfigCommand, GameProfile, Settings},
 };
 use add::AddCommand;
-use anyhow::{anyhow, Result};
-use botw_utils::hashes::{Platform, StockHashTable};
-use builder::WarnLevel;
-use colored::*;
+use anyhow::{anyhow, Context, Result};
+use builder::{artifacts::ArtifactCache, config::Flag};
 use fs_err as fs;
 use roead::yaz0::decompress;
 use rstb::ResourceSizeTable;
 use rustc_hash::{FxBuildHasher, FxHashMap as HashMap};
-use scc::{HashMap as SyncMap, HashSet};
+use scc::HashSet;
 use std::{
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
@@ -19,11 +18,23 @@ use std::{
 use structopt::{clap::AppSettings::ColoredHelp, StructOpt};
 
 mod add;
+mod archive;
 mod builder;
+mod check;
+mod deploy;
+mod diagnostic;
+mod dump_index;
+mod game_dump;
+mod message;
+mod prune;
+mod remove;
+mod rename;
+mod sarc_meta;
 mod settings;
 mod unbuilder;
 mod unzip_some;
 mod util;
+mod verify;
 
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -36,6 +47,13 @@ mod util;
 struct Opt {
     #[structopt(long, short, help = "Show detailed output")]
     verbose: bool,
+    #[structopt(
+        long,
+        default_value = "human",
+        possible_values = &["human", "json"],
+        help = "Print messages as text, or as JSON lines for editors and CI"
+    )]
+    message_format: message::MessageFormat,
     #[structopt(subcommand)]
     command: Command,
 }
@@ -46,25 +64,7 @@ pub enum Command {
     /// Build a mod from a source-like structure into binary game files
     /// {n}Note: Flags can be set using a config.yml file. See readme for details.
     #[structopt(setting = ColoredHelp)]
-    Build {
-        #[structopt(long, short, help = "Use big endian/Wii U mode")]
-        be: bool,
-        #[structopt(long, short, help = "Suppress warnings, show only errors")]
-        ignore_warnings: bool,
-        #[structopt(long, short, help = "Treat warnings as errors and abort")]
-        hard_warnings: bool,
-        #[structopt(
-            long,
-            short,
-            use_delimiter = true,
-            help = "Comma separated list of custom actors to add to TitleBG.pack, e.g.\n`--title-actors=Weapon_Bow_001,Enemy_Golem_Senior`"
-        )]
-        title_actors: Vec<String>,
-        #[structopt(help = "Source mod folder to build")]
-        source: Option<PathBuf>,
-        #[structopt(long, short, help = "Output folder for built mod")]
-        output: Option<PathBuf>,
-    },
+    Build(BuildOptions),
     /// Create a new source-like mod project
     #[structopt(setting = ColoredHelp, alias = "unbuild")]
     Init {
@@ -72,14 +72,55 @@ pub enum Command {
         be: bool,
         #[structopt(help = "Target folder to create project in [default: .]")]
         directory: Option<PathBuf>,
-        #[structopt(long, short, help = "Source mod folder to unbuild")]
+        #[structopt(
+            long,
+            short,
+            help = "Source mod folder or archive (.zip, .7z, or .bnp) to unbuild"
+        )]
         source: Option<PathBuf>,
         #[structopt(long, short, help = "Create default config.yml")]
         config: bool,
+        #[structopt(
+            long,
+            short,
+            requires = "source",
+            help = "Only unbuild files which differ from the stock game"
+        )]
+        modified_only: bool,
+        #[structopt(
+            long,
+            short,
+            requires = "source",
+            help = "Keep copies of files which differ between actor or event packs, so each pack is rebuilt as it was"
+        )]
+        keep_variants: bool,
     },
     /// Get or set Hyrule Builder configuration parameters
     #[structopt(setting = ColoredHelp, alias = "conf")]
     Config(ConfigCommand),
+    /// Unbuild a mod to a temporary project and build it again, reporting every difference
+    /// between the original and rebuilt files
+    #[structopt(setting = ColoredHelp)]
+    VerifyRoundtrip {
+        #[structopt(long, short, help = "Use big endian/Wii U mode")]
+        be: bool,
+        #[structopt(help = "Mod folder or archive (.zip, .7z, or .bnp) to check")]
+        source: PathBuf,
+        #[structopt(long, short, help = "Keep the temporary project and build for inspection")]
+        keep: bool,
+    },
+    /// Check a project for errors without building it
+    #[structopt(setting = ColoredHelp)]
+    Check {
+        #[structopt(long, short, default_value = ".", help = "Project folder to check")]
+        project: PathBuf,
+        #[structopt(long, short, help = "Suppress warnings, show only errors")]
+        ignore_warnings: bool,
+        #[structopt(long, short, help = "Treat warnings as errors")]
+        hard_warnings: bool,
+        #[structopt(long, help = "Build profile from config.yml to check with")]
+        profile: Option<String>,
+    },
     /// Add new content to the active mod project
     #[structopt(setting = ColoredHelp)]
     Add {
@@ -88,6 +129,40 @@ pub enum Command {
         #[structopt(subcommand)]
         command: AddCommand,
     },
+    /// Remove content from the active mod project
+    #[structopt(setting = ColoredHelp)]
+    Remove {
+        #[structopt(long, short, default_value = ".", help = "Project folder to remove from")]
+        project: PathBuf,
+        #[structopt(subcommand)]
+        command: remove::RemoveCommand,
+    },
+    /// Clean up unused files in the active mod project
+    #[structopt(setting = ColoredHelp)]
+    Prune {
+        #[structopt(long, short, default_value = ".", help = "Project folder to prune")]
+        project: PathBuf,
+        #[structopt(
+            long,
+            required = true,
+            help = "Remove files which no actor link or event info refers to"
+        )]
+        orphans: bool,
+        #[structopt(
+            long,
+            short,
+            help = "Remove the files found, instead of only listing them"
+        )]
+        yes: bool,
+    },
+    /// Rename content in the active mod project
+    #[structopt(setting = ColoredHelp)]
+    Rename {
+        #[structopt(long, short, default_value = ".", help = "Project folder to rename in")]
+        project: PathBuf,
+        #[structopt(subcommand)]
+        command: rename::RenameCommand,
+    },
 }
 
 fn check_project(project: &Path) -> Result<bool> {
@@ -107,8 +182,257 @@ fn check_project(project: &Path) -> Result<bool> {
     }
 }
 
+#[derive(Debug, Default, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub struct BuildOptions {
+    #[structopt(long, short, help = "Use big endian/Wii U mode")]
+    be: bool,
+    #[structopt(long, short, help = "Suppress warnings, show only errors")]
+    ignore_warnings: bool,
+    #[structopt(long, short, help = "Treat warnings as errors and abort")]
+    hard_warnings: bool,
+    #[structopt(
+        long,
+        short,
+        use_delimiter = true,
+        help = "Comma separated list of custom actors to add to TitleBG.pack, e.g.\n`--title-actors=Weapon_Bow_001,Enemy_Golem_Senior`"
+    )]
+    title_actors: Vec<String>,
+    #[structopt(help = "Source mod folder to build")]
+    source: Option<PathBuf>,
+    #[structopt(long, short, help = "Output folder for built mod")]
+    output: Option<PathBuf>,
+    #[structopt(long, short, help = "Build profile from config.yml to use, e.g. `release`")]
+    profile: Option<String>,
+    #[structopt(long, help = "Don't deploy the build, even if a deploy folder is configured")]
+    no_deploy: bool,
+    #[structopt(
+        long,
+        help = "Let the deploy overwrite files in the deploy folder which it didn't put there"
+    )]
+    force: bool,
+    #[structopt(
+        long,
+        short,
+        help = "Build everything possible after an error, then list every error at the end"
+    )]
+    keep_going: bool,
+    #[structopt(
+        long,
+        help = "Show how long each stage of the build and the slowest packs took"
+    )]
+    timings: bool,
+    #[structopt(
+        long,
+        help = "Write the build timings to a Chrome trace file, for about:tracing or Perfetto"
+    )]
+    trace: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Compile and compress every file instead of using the artifact cache"
+    )]
+    no_cache: bool,
+    /// Game folders to use instead of those in the settings
+    #[structopt(skip)]
+    game_dump: Option<GameProfile>,
+}
+
+fn build(options: BuildOptions, verbose: bool) -> Result<()> {
+    let BuildOptions {
+        be,
+        ignore_warnings,
+        hard_warnings,
+        title_actors,
+        source,
+        output,
+        profile,
+        no_deploy,
+        force,
+        keep_going,
+        timings,
+        trace,
+        no_cache,
+        game_dump,
+    } = options;
+    let source = source.map_or(
+        std::env::current_dir().expect("There's no current working directory"),
+        |path| dunce::canonicalize(path).expect("Provided path was invalid"),
+    );
+    let config = BuildConfig::load(&source)?
+        .unwrap_or_default()
+        .with_profile(profile.as_deref())?;
+    let be = config.has(Flag::Be) || be;
+    let verbose = config.has(Flag::Verbose) || verbose;
+    let reproducible = config.has(Flag::Reproducible);
+    let keep_going = config.has(Flag::KeepGoing) || keep_going;
+    let no_cache = config.has(Flag::NoCache) || no_cache;
+    let compression = config
+        .options
+        .compression
+        .unwrap_or(builder::config::DEFAULT_COMPRESSION);
+    let output = config
+        .options
+        .output
+        .clone()
+        .or(output)
+        .unwrap_or_else(|| source.join("build"));
+    let meta = config.meta.clone();
+    let title_actors = if config.options.title_actors.is_empty() {
+        title_actors
+    } else {
+        config.options.title_actors.clone()
+    };
+    let content = PathBuf::from(if be {
+        "content"
+    } else {
+        "01007EF00011E000/romfs"
+    });
+    let dump = match game_dump {
+        Some(dump) => dump,
+        None => Settings::load_profile(config.options.profile.as_deref())?,
+    };
+    let hash_table = StockHashes::new(&dump, be);
+    let deploy = config.options.deploy.clone().filter(|_| !no_deploy);
+    let mut builder = builder::Builder {
+        be,
+        file_times: HashMap::default(),
+        meta,
+        modified_files: HashSet::with_hasher(FxBuildHasher),
+        actorinfo: None,
+        hash_table,
+        size_table: Arc::new(Mutex::new({
+            let try_table = output
+                .join(&content)
+                .join("System/Resource/ResourceSizeTable.product.srsizetable");
+            if try_table.exists() && !reproducible {
+                if verbose {
+                    message::detail("Loading last built RSTB");
+                }
+                ResourceSizeTable::from_binary(decompress(fs::read(try_table)?)?)?
+            } else {
+                let try_table = source
+                    .join(&content)
+                    .join("System/Resource/ResourceSizeTable.product.json");
+                if try_table.exists() {
+                    if verbose {
+                        message::detail("Loading JSON RSTB");
+                    }
+                    ResourceSizeTable::from_text(fs::read_to_string(try_table)?)?
+                } else {
+                    match game_dump::dump_rstb(&dump, be)? {
+                        Some(table) => {
+                            if verbose {
+                                message::detail("Loading stock RSTB from game dump");
+                            }
+                            table
+                        }
+                        None => {
+                            if verbose {
+                                message::detail("Loading fresh RSTB");
+                            }
+                            ResourceSizeTable::new_from_stock(if be {
+                                rstb::Endian::Big
+                            } else {
+                                rstb::Endian::Little
+                            })
+                        }
+                    }
+                }
+            }
+        })),
+        content,
+        aoc: PathBuf::from(if be {
+            "aoc/0010"
+        } else {
+            "01007EF00011F001/romfs"
+        }),
+        output,
+        source,
+        title_actors: {
+            let actors = HashSet::with_capacity_and_hasher(
+                title_actors.len() + builder::actor::TITLE_ACTORS.len(),
+                FxBuildHasher,
+            );
+            for actor in title_actors
+                .into_iter()
+                .chain(builder::actor::TITLE_ACTORS.iter().map(|t| t.to_string()))
+            {
+                let _ = actors.insert(actor);
+            }
+            actors
+        },
+        title_events: {
+            let events = HashSet::with_capacity_and_hasher(
+                builder::event::TITLE_EVENTS.len() + builder::event::NESTED_EVENTS.len(),
+                FxBuildHasher,
+            );
+            for event in builder::event::TITLE_EVENTS
+                .iter()
+                .chain(builder::event::NESTED_EVENTS.iter())
+                .map(|t| t.to_string())
+            {
+                let _ = events.insert(event);
+            }
+            events
+        },
+        compiled: Default::default(),
+        verbose,
+        warn: config.warn_level(hard_warnings, ignore_warnings),
+        warnings: config.warnings.clone(),
+        compression,
+        reproducible,
+        dump,
+        keep_going,
+        diagnostics: Mutex::default(),
+        timings: (timings || trace.is_some()).then(|| builder::timings::Timings::new(trace)),
+        artifacts: if no_cache {
+            None
+        } else {
+            config
+                .options
+                .cache
+                .clone()
+                .or_else(ArtifactCache::default_dir)
+                .map(|root| {
+                    ArtifactCache::new(
+                        root,
+                        be,
+                        compression,
+                        config
+                            .options
+                            .cache_size
+                            .map_or(builder::artifacts::DEFAULT_MAX_SIZE, |mb| {
+                                mb.saturating_mul(1 << 20)
+                            }),
+                    )
+                })
+        },
+    };
+    builder.build()?;
+    if let Some(target) = deploy {
+        deploy::deploy(&builder.output, &target, force, verbose)
+            .context("Failed to deploy build")?;
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let opt = Opt::from_args();
+    message::set_format(opt.message_format);
+    let result = run(opt);
+    if let Err(e) = &result {
+        if message::is_json() {
+            message::diagnostic(
+                &diagnostic::Diagnostic::from_error(e),
+                &std::env::current_dir().unwrap_or_default(),
+            );
+            std::process::exit(1);
+        }
+    }
+    result
+}
+
+fn run(opt: Opt) -> Result<()> {
     match opt.command {
         Command::Config(conf) => {
             let mut config = Settings::get_settings()?;
@@ -116,26 +440,54 @@ fn main() -> Result<()> {
                 ConfigCommand::List { values } => {
                     config.list(values);
                 }
-                ConfigCommand::Get { setting } => {
-                    config.get(&setting)?;
+                ConfigCommand::Get { setting, profile } => {
+                    config.get(&setting, profile.as_deref())?;
+                }
+                ConfigCommand::Set {
+                    setting,
+                    value,
+                    profile,
+                } => {
+                    config.set(&setting, &value, profile.as_deref())?;
                 }
-                ConfigCommand::Set { setting, value } => {
-                    config.set(&setting, &value)?;
+                ConfigCommand::Validate { profile } => {
+                    config.validate(profile.as_deref())?;
+                }
+                ConfigCommand::Use { profile } => {
+                    config.use_profile(profile)?;
                 }
                 ConfigCommand::Import {
                     from_bcml,
                     from_cemu,
+                    from_ukmm,
+                    from_dump,
                     cemu_dir,
                 } => {
-                    if from_cemu && from_bcml {
-                        return Err(anyhow!("Cannot combine `--from-cemu` and `--from-bcml`"));
+                    if [from_bcml, from_cemu, from_ukmm, from_dump.is_some()]
+                        .iter()
+                        .filter(|f| **f)
+                        .count()
+                        > 1
+                    {
+                        return Err(anyhow!(
+                            "Only one of `--from-bcml`, `--from-cemu`, `--from-ukmm`, or \
+                             `--from-dump` can be used at a time"
+                        ));
                     }
                     if from_cemu {
                         config.set_from_cemu(&cemu_dir.unwrap())?;
                     } else if from_bcml {
                         config.set_from_bcml()?;
+                    } else if from_ukmm {
+                        config.set_from_ukmm()?;
+                    } else if let Some(dump) = from_dump {
+                        config.set_from_dump(&dump)?;
                     }
                 }
+                ConfigCommand::Reindex { be, profile } => {
+                    let profile = config.game_profile(profile.as_deref())?;
+                    dump_index::DumpIndex::build(&profile, be)?.save()?;
+                }
             }
             Ok(())
         }
@@ -144,160 +496,74 @@ fn main() -> Result<()> {
             directory,
             source,
             config,
-        } => unbuilder::unbuild(be, source, directory, config),
-        Command::Build {
+            modified_only,
+            keep_variants,
+        } => unbuilder::unbuild(
             be,
-            hard_warnings,
-            ignore_warnings,
-            output,
-            title_actors,
             source,
+            directory,
+            config,
+            modified_only,
+            keep_variants,
+        ),
+        Command::Build(options) => build(options, opt.verbose),
+        Command::VerifyRoundtrip { be, source, keep } => {
+            verify::verify_roundtrip(be, source, keep, opt.verbose)
+        }
+        Command::Check {
+            project,
+            ignore_warnings,
+            hard_warnings,
+            profile,
         } => {
-            let source = source.map_or(
-                std::env::current_dir().expect("There's no current working directory"),
-                |path| dunce::canonicalize(path).expect("Provided path was invalid"),
-            );
-            let config: Option<BuildConfig> = if source.join("config.yml").exists() {
-                Some(serde_yml::from_reader(&std::fs::File::open(
-                    source.join("config.yml"),
-                )?)?)
-            } else {
-                None
-            };
-            let be = config
-                .as_ref()
-                .map(|c| c.flags.contains(&"be".to_string()) || be)
-                .unwrap_or(be);
-            let hard_warnings = config
-                .as_ref()
-                .map(|c| c.flags.contains(&"hard_warnings".to_owned()) || hard_warnings)
-                .unwrap_or(hard_warnings);
-            let ignore_warnings = config
-                .as_ref()
-                .map(|c| c.flags.contains(&"ignore_warnings".to_owned()) || ignore_warnings)
-                .unwrap_or(ignore_warnings);
-            let verbose = config
-                .as_ref()
-                .map(|c| c.flags.contains(&"verbose".to_owned()) || opt.verbose)
-                .unwrap_or(opt.verbose);
-            let output = config
-                .as_ref()
-                .and_then(|c| c.options.get("output"))
-                .map(PathBuf::from)
-                .or(output)
-                .unwrap_or_else(|| source.join("build"));
-            let meta = config.as_ref().map(|c| c.meta.clone()).unwrap_or_default();
-            let title_actors = config
-                .as_ref()
-                .and_then(|c| c.options.get("title_actors"))
-                .map(|t| t.split(',').map(|s| s.to_owned()).collect())
-                .unwrap_or(title_actors);
-            let content = PathBuf::from(if be {
-                "content"
-            } else {
-                "01007EF00011E000/romfs"
-            });
-            builder::Builder {
+            let be = check_project(&project)?;
+            let config =
+                BuildConfig::load_or_default(&project)?.with_profile(profile.as_deref())?;
+            check::check(
+                &project,
                 be,
-                file_times: HashMap::default(),
-                meta,
-                modified_files: HashSet::with_hasher(FxBuildHasher),
-                actorinfo: None,
-                hash_table: StockHashTable::new(&if be {
-                    Platform::WiiU
-                } else {
-                    Platform::Switch
-                }),
-                size_table: Arc::new(Mutex::new({
-                    let try_table = output
-                        .join(&content)
-                        .join("System/Resource/ResourceSizeTable.product.srsizetable");
-                    if try_table.exists() {
-                        if verbose {
-                            println!("{}", "Loading last built RSTB".bright_black());
-                        }
-                        ResourceSizeTable::from_binary(decompress(fs::read(try_table)?)?)?
-                    } else {
-                        let try_table = source
-                            .join(&content)
-                            .join("System/Resource/ResourceSizeTable.product.json");
-                        if try_table.exists() {
-                            if verbose {
-                                println!("{}", "Loading JSON RSTB".bright_black());
-                            }
-                            ResourceSizeTable::from_text(fs::read_to_string(try_table)?)?
-                        } else {
-                            if verbose {
-                                println!("{}", "Loading fresh RSTB".bright_black());
-                            }
-                            ResourceSizeTable::new_from_stock(if be {
-                                rstb::Endian::Big
-                            } else {
-                                rstb::Endian::Little
-                            })
-                        }
-                    }
-                })),
-                content,
-                aoc: PathBuf::from(if be {
-                    "aoc/0010"
-                } else {
-                    "01007EF00011F001/romfs"
-                }),
-                output,
-                source,
-                title_actors: {
-                    let actors = HashSet::with_capacity_and_hasher(
-                        title_actors.len() + builder::actor::TITLE_ACTORS.len(),
-                        FxBuildHasher,
-                    );
-                    for actor in title_actors
-                        .into_iter()
-                        .chain(builder::actor::TITLE_ACTORS.iter().map(|t| t.to_string()))
-                    {
-                        let _ = actors.insert(actor);
-                    }
-                    actors
-                },
-                title_events: {
-                    let events = HashSet::with_capacity_and_hasher(
-                        builder::event::TITLE_EVENTS.len() + builder::event::NESTED_EVENTS.len(),
-                        FxBuildHasher,
-                    );
-                    for event in builder::event::TITLE_EVENTS
-                        .iter()
-                        .chain(builder::event::NESTED_EVENTS.iter())
-                        .map(|t| t.to_string())
-                    {
-                        let _ = events.insert(event);
-                    }
-                    events
-                },
-                compiled: SyncMap::with_hasher(FxBuildHasher),
-                verbose,
-                warn: if hard_warnings {
-                    WarnLevel::Error
-                } else if config
-                    .as_ref()
-                    .map(|c| c.flags.contains(&"ignore_warnings".to_owned()))
-                    .unwrap_or(ignore_warnings)
-                {
-                    WarnLevel::None
-                } else {
-                    WarnLevel::Warn
-                },
-            }
-            .build()
+                config.warn_level(hard_warnings, ignore_warnings),
+                config.warnings,
+                opt.verbose,
+            )
         }
         Command::Add { project, command } => {
             let be = check_project(&project)?;
-            let config = Settings::get_settings()?;
+            let config = Settings::load(Some(&project))?;
             match command {
                 AddCommand::Actor { .. } => command.add_actor(project, config, be)?,
                 AddCommand::Actorinfo => command.add_actorinfo(project, config, be)?,
                 AddCommand::Map { .. } => command.add_map(project, config, be)?,
                 AddCommand::Event { .. } => command.add_event(project, config, be)?,
                 AddCommand::Pack { .. } => command.add_pack(project, config, be)?,
+                AddCommand::Shrine { .. } => command.add_shrine(project, config, be)?,
+                AddCommand::File { .. } => command.add_file(project, config, be)?,
+            };
+            Ok(())
+        }
+        Command::Remove { project, command } => {
+            let be = check_project(&project)?;
+            match command {
+                remove::RemoveCommand::Actor { .. } => command.remove_actor(project, be)?,
+                remove::RemoveCommand::Event { .. } => command.remove_event(project, be)?,
+            };
+            Ok(())
+        }
+        Command::Prune {
+            project,
+            orphans,
+            yes,
+        } => {
+            let be = check_project(&project)?;
+            if orphans {
+                prune::prune_orphans(&project, be, yes)?;
+            }
+            Ok(())
+        }
+        Command::Rename { project, command } => {
+            let be = check_project(&project)?;
+            match command {
+                rename::RenameCommand::Actor { .. } => command.rename_actor(project, be)?,
             };
             Ok(())
         }
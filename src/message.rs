@@ -0,0 +1,261 @@
+//! All output meant for the user goes through here, so it can be printed as text or as JSON
+//! lines with `--message-format json`. Every JSON line has the same fields: `type`, `severity`,
+//! `code`, `message`, and `file`, plus `line`, `column`, and `count` where known.
+
+use crate::diagnostic::{Code, Diagnostic, Severity};
+use colored::*;
+use path_slash::PathExt;
+use serde::Serialize;
+use std::{path::Path, str::FromStr, sync::OnceLock};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MessageFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+impl FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "Unknown message format {}, expected human or json",
+                s
+            )),
+        }
+    }
+}
+
+static FORMAT: OnceLock<MessageFormat> = OnceLock::new();
+
+/// Sets the output format for the rest of the run. JSON output is never coloured.
+pub fn set_format(format: MessageFormat) {
+    if format == MessageFormat::Json {
+        colored::control::set_override(false);
+    }
+    let _ = FORMAT.set(format);
+}
+
+#[inline]
+pub fn is_json() -> bool {
+    FORMAT.get() == Some(&MessageFormat::Json)
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Kind {
+    Status,
+    StageStarted,
+    StageFinished,
+    Diagnostic,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Level {
+    Verbose,
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+struct Message<'a> {
+    #[serde(rename = "type")]
+    kind: Kind,
+    severity: Level,
+    code: Option<&'a str>,
+    message: &'a str,
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    count: Option<usize>,
+}
+
+impl<'a> Message<'a> {
+    fn new(kind: Kind, severity: Level, message: &'a str) -> Self {
+        Self {
+            kind,
+            severity,
+            code: None,
+            message,
+            file: None,
+            line: None,
+            column: None,
+            count: None,
+        }
+    }
+
+    fn emit(&self) {
+        println!(
+            "{}",
+            serde_json::to_string(self).expect("Messages always serialize")
+        );
+    }
+}
+
+/// Prints a progress or result message
+pub fn status(message: &str) {
+    if is_json() {
+        Message::new(Kind::Status, Level::Info, message).emit();
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// Prints a message that something finished successfully, in green
+pub fn success(message: &str) {
+    if is_json() {
+        status(message);
+    } else {
+        println!("{}", message.green());
+    }
+}
+
+/// Prints a message only meant for verbose output, in grey
+pub fn detail(message: &str) {
+    if is_json() {
+        Message::new(Kind::Status, Level::Verbose, message).emit();
+    } else {
+        println!("{}", message.bright_black());
+    }
+}
+
+/// Prints a warning which isn't about a project file, like a problem with the game dump
+pub fn warning(code: Code, message: &str) {
+    if is_json() {
+        Message {
+            code: Some(code.as_str()),
+            ..Message::new(Kind::Diagnostic, Level::Warning, message)
+        }
+        .emit();
+    } else {
+        println!("{}", format!("Warning: {}", message).yellow());
+    }
+}
+
+/// Prints a diagnostic, with its file relative to `root` where possible
+pub fn diagnostic(diagnostic: &Diagnostic, root: &Path) {
+    let file = diagnostic.file.as_ref().map(|f| {
+        f.strip_prefix(root)
+            .unwrap_or(f)
+            .to_slash_lossy()
+            .into_owned()
+    });
+    let severity = match diagnostic.severity {
+        Severity::Error => Level::Error,
+        Severity::Warning => Level::Warning,
+    };
+    if is_json() {
+        Message {
+            code: Some(diagnostic.code.as_str()),
+            file,
+            line: diagnostic.line,
+            column: diagnostic.column,
+            ..Message::new(Kind::Diagnostic, severity, &diagnostic.message)
+        }
+        .emit();
+        return;
+    }
+    let location = file.map(|mut location| {
+        if let Some(line) = diagnostic.line {
+            location += &format!(":{}", line);
+            if let Some(column) = diagnostic.column {
+                location += &format!(":{}", column);
+            }
+        }
+        location
+    });
+    let label = match severity {
+        Level::Error => format!("Error[{}]", diagnostic.code).red(),
+        _ => format!("Warning[{}]", diagnostic.code).yellow(),
+    };
+    match location {
+        Some(location) => println!("{} {}: {}", label, location.bold(), diagnostic.message),
+        None => println!("{} {}", label, diagnostic.message),
+    }
+}
+
+/// A step of a command, like building actor packs. Starting one prints its message, and in
+/// JSON it is reported as started and finished with the number of items it handles.
+#[derive(Debug)]
+#[must_use = "stages should be finished"]
+pub struct Stage {
+    code: &'static str,
+    count: Option<usize>,
+}
+
+impl Stage {
+    pub fn start(code: &'static str, count: Option<usize>, message: &str) -> Self {
+        if is_json() {
+            Message {
+                code: Some(code),
+                count,
+                ..Message::new(Kind::StageStarted, Level::Info, message)
+            }
+            .emit();
+        } else {
+            println!("{}", message);
+        }
+        Self { code, count }
+    }
+
+    /// Sets the number of items, for stages which only know it at the end
+    #[inline]
+    pub fn count(&mut self, count: usize) {
+        self.count = Some(count);
+    }
+
+    pub fn finish(self) {
+        if is_json() {
+            Message {
+                code: Some(self.code),
+                count: self.count,
+                ..Message::new(Kind::StageFinished, Level::Info, "")
+            }
+            .emit();
+        }
+    }
+}
+
+/// Prints a progress or result message, formatted like `println!`
+macro_rules! status {
+    ($($arg:tt)*) => {
+        $crate::message::status(&format!($($arg)*))
+    };
+}
+
+pub(crate) use status;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_fields() {
+        let message = Message {
+            code: Some(Code::MissingHavok.as_str()),
+            file: Some("content/Physics/Test.hkrb".into()),
+            ..Message::new(Kind::Diagnostic, Level::Warning, "Havok file not found")
+        };
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"type":"diagnostic","severity":"warning","code":"missing-havok","message":"Havok file not found","file":"content/Physics/Test.hkrb"}"#
+        );
+        let stage = Message {
+            count: Some(3),
+            ..Message::new(Kind::StageStarted, Level::Info, "Building 3 packs")
+        };
+        assert_eq!(
+            serde_json::to_string(&stage).unwrap(),
+            r#"{"type":"stage-started","severity":"info","code":null,"message":"Building 3 packs","file":null,"count":3}"#
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use crate::{
+    builder::{actor::actor_files, event::event_files},
+    message::{self, status},
+    remove::delete_files,
+};
+use anyhow::{Context, Result};
+use fs_err as fs;
+use join_str::jstr;
+use path_slash::PathExt;
+use roead::byml::Byml;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+/// Folders whose files only reach the build through actor or event packs
+static PACKED_DIRS: &[&str] = &["Actor", "Physics", "EventFlow", "Camera", "Demo"];
+
+/// Folders inside those which are built some other way
+static UNPACKED_DIRS: &[&str] = &[
+    "Actor/ActorLink",
+    "Actor/ActorInfo",
+    "Actor/Pack",
+    "Physics/StaticCompound",
+    "Physics/TeraMeshRigidBody",
+];
+
+fn names(root: &Path, pattern: &str, ext: &str) -> Result<HashSet<String>> {
+    Ok(glob::glob(&root.join(pattern).to_string_lossy())?
+        .filter_map(Result::ok)
+        .filter_map(|f| {
+            f.file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| n.trim_end_matches(ext).to_owned())
+        })
+        .collect())
+}
+
+/// An actor or event whose own references are left out of [`referenced_files`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Owner<'a> {
+    Actor(&'a str),
+    Event(&'a str),
+}
+
+/// Every file in a content folder which an actor link, one of its variants, or an event info
+/// refers to, apart from those only `except` refers to
+pub fn referenced_files(root: &Path, except: Option<Owner>) -> Result<HashSet<PathBuf>> {
+    let mut reachable: HashSet<PathBuf> = HashSet::new();
+    for link in glob::glob(&root.join("Actor/ActorLink/*.bxml.yml").to_string_lossy())?
+        .filter_map(Result::ok)
+    {
+        let name = link
+            .file_name()
+            .and_then(|n| n.to_str())
+            .context("Missing filename")?
+            .trim_end_matches(".bxml.yml");
+        if except == Some(Owner::Actor(name)) {
+            continue;
+        }
+        reachable.extend(actor_files(root, &link).with_context(|| {
+            jstr!("Could not tell which files are used, failed to read {&link.to_slash_lossy()}")
+        })?);
+        // A variant's own lists can use files which the main ones don't
+        let variant = root.join(jstr!("Variants/{name}.sbactorpack"));
+        if variant.exists() {
+            for file in actor_files(&variant, &link).with_context(|| {
+                jstr!("Could not tell which files are used, failed to read variant {name}")
+            })? {
+                if let Ok(rel) = file.strip_prefix(&variant) {
+                    reachable.insert(root.join(rel));
+                }
+            }
+        }
+    }
+    for info_path in glob::glob(&root.join("Event/EventInfo/*.info.yml").to_string_lossy())?
+        .filter_map(Result::ok)
+    {
+        let name = info_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .context("Missing filename")?
+            .trim_end_matches(".info.yml")
+            .to_owned();
+        if except == Some(Owner::Event(&name)) {
+            continue;
+        }
+        let info = Byml::from_text(fs::read_to_string(&info_path)?)
+            .with_context(|| {
+                jstr!("Could not tell which files are used, failed to read {&info_path.to_slash_lossy()}")
+            })?
+            .into_map()?;
+        reachable.extend(event_files(root, &name, &info)?);
+    }
+    Ok(reachable)
+}
+
+/// Finds every file in a content folder which no actor link or event info reaches
+fn find_orphans(root: &Path) -> Result<Vec<PathBuf>> {
+    let reachable = referenced_files(root, None)?;
+    let actors = names(root, "Actor/ActorLink/*.bxml.yml", ".bxml.yml")?;
+    let events = names(root, "Event/EventInfo/*.info.yml", ".info.yml")?;
+    let unpacked: Vec<PathBuf> = UNPACKED_DIRS.iter().map(|d| root.join(d)).collect();
+    let mut orphans: Vec<PathBuf> = PACKED_DIRS
+        .iter()
+        .map(|dir| glob::glob(&root.join(dir).join("**/*").to_string_lossy()))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter(|f| {
+            f.is_file()
+                && !reachable.contains(f)
+                && !unpacked.iter().any(|d| f.starts_with(d))
+                && !f
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .map(|n| n.starts_with('.'))
+                    .unwrap_or(true)
+        })
+        .collect();
+    // Variants of packs which no longer exist
+    let variant_root = root.join("Variants");
+    if variant_root.exists() {
+        for dir in fs::read_dir(&variant_root)?
+            .filter_map(Result::ok)
+            .map(|e| e.path())
+        {
+            let name = dir.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            let orphaned = match (
+                name.strip_suffix(".sbactorpack"),
+                name.strip_suffix(".sbeventpack"),
+            ) {
+                (Some(actor), _) => !actors.contains(actor),
+                (_, Some(event)) => !events.contains(event),
+                _ => false,
+            };
+            if orphaned {
+                orphans.extend(
+                    glob::glob(&dir.join("**/*").to_string_lossy())?
+                        .filter_map(Result::ok)
+                        .filter(|f| f.is_file()),
+                );
+            }
+        }
+    }
+    orphans.sort();
+    Ok(orphans)
+}
+
+/// Lists every file in a project which only actor or event packs could ship, but which no
+/// actor link or event info refers to, and deletes them if `delete` is set
+pub fn prune_orphans(project: &Path, be: bool, delete: bool) -> Result<()> {
+    let roots = if be {
+        ["content", "aoc/0010"]
+    } else {
+        ["01007EF00011E000/romfs", "01007EF00011F001/romfs"]
+    };
+    status!("Finding orphaned files...");
+    let mut total = 0;
+    for root in roots.iter().map(|r| project.join(r)).filter(|r| r.exists()) {
+        let orphans = find_orphans(&root)?;
+        if orphans.is_empty() {
+            continue;
+        }
+        for file in &orphans {
+            status!("  {}", file.strip_prefix(project)?.to_slash_lossy());
+        }
+        total += orphans.len();
+        if delete {
+            delete_files(&root, &orphans.into_iter().collect())?;
+        }
+    }
+    if total == 0 {
+        message::success("No orphaned files found");
+    } else if !delete {
+        status!("Found {} orphaned files, pass --yes to remove them", total);
+    } else {
+        status!("Removed {} orphaned files", total);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    static LINK: &str = "!io\nversion: 0\ntype: xml\nparam_root: !list\n  objects:\n    LinkTarget: \
+                         !obj\n      ASUser: !str64 Obj_Test\n      PhysicsUser: !str64 Obj_Test\n  \
+                         lists: {}\n";
+
+    fn aslist(file: &str) -> String {
+        format!(
+            "!io\nversion: 0\ntype: xml\nparam_root: !list\n  objects: {{}}\n  lists:\n    \
+             ASDefines: !list\n      objects:\n        ASDefine_0: !obj\n          Name: !str64 \
+             Wait\n          Filename: !str64 {}\n      lists: {{}}\n",
+            file
+        )
+    }
+
+    static PHYSICS: &str = "!io\nversion: 0\ntype: xml\nparam_root: !list\n  objects: {}\n  lists:\n    \
+                            ParamSet: !list\n      objects:\n        1258832850: !obj\n          \
+                            use_ragdoll: true\n          use_support_bone: false\n          use_cloth: \
+                            false\n          use_rigid_body_set_num: 0\n        Ragdoll: !obj\n          \
+                            ragdoll_setup_file_path: !str256 Obj_Test.hkrg\n      lists: {}\n";
+
+    static EVENT_INFO: &str =
+        "Demo001_0:\n  subfile:\n    - {file: Demo001_Sub.bfevfl}\n  as:\n    - {file: Demo001_Talk}\n";
+
+    #[test]
+    fn keeps_referenced_files() {
+        let project = TempDir::new("prune");
+        let content = project.join("content");
+        let write = |rel: &str, text: &str| {
+            let file = content.join(rel);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, text).unwrap();
+        };
+        write("Actor/ActorLink/Obj_Test.bxml.yml", LINK);
+        write(
+            "Actor/ASList/Obj_Test.baslist.yml",
+            &aslist("Obj_Test_Wait"),
+        );
+        write("Actor/Physics/Obj_Test.bphysics.yml", PHYSICS);
+        write("Event/EventInfo/Demo001.info.yml", EVENT_INFO);
+        let used = [
+            "Actor/AS/Obj_Test_Wait.bas.yml",
+            "Physics/Ragdoll/Obj_Test.hkrg",
+            "EventFlow/Demo001.bfevfl",
+            "EventFlow/Demo001_Sub.bfevfl",
+            "Actor/AS/Demo001/Demo001_Talk.bas.yml",
+            // Only the variant's AS list uses this
+            "Actor/AS/Obj_Test_Variant.bas.yml",
+            "Variants/Obj_Test.sbactorpack/Actor/ASList/Obj_Test.baslist.yml",
+            "Pack/TitleBG.pack/EventFlow/Title.bfevfl",
+            "Pack/TitleBG.pack/Actor/AS/Title_Wait.bas.yml",
+        ];
+        for file in used {
+            write(file, "");
+        }
+        write(
+            "Variants/Obj_Test.sbactorpack/Actor/ASList/Obj_Test.baslist.yml",
+            &aslist("Obj_Test_Variant"),
+        );
+        let orphans = [
+            "Actor/AS/Obj_Unused.bas.yml",
+            "EventFlow/Old.bfevfl",
+            "Variants/Obj_Gone.sbactorpack/Actor/AS/Obj_Gone_Wait.bas.yml",
+        ];
+        for file in orphans {
+            write(file, "");
+        }
+
+        assert_eq!(
+            find_orphans(&content).unwrap(),
+            orphans.iter().map(|f| content.join(f)).collect::<Vec<_>>()
+        );
+        // Leaving out the actor, as removing it does, leaves only the event's files
+        let shared = referenced_files(&content, Some(Owner::Actor("Obj_Test"))).unwrap();
+        assert!(!shared.contains(&content.join("Actor/AS/Obj_Test_Wait.bas.yml")));
+        assert!(!shared.contains(&content.join("Actor/AS/Obj_Test_Variant.bas.yml")));
+        assert!(shared.contains(&content.join("EventFlow/Demo001.bfevfl")));
+        prune_orphans(&project, true, false).unwrap();
+        assert!(orphans.iter().all(|f| content.join(f).exists()));
+        prune_orphans(&project, true, true).unwrap();
+        assert!(orphans.iter().all(|f| !content.join(f).exists()));
+        assert!(used.iter().all(|f| content.join(f).exists()));
+    }
+}
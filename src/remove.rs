@@ -0,0 +1,180 @@
+use crate::{
+    builder::{actor::actor_files, event::event_files, BuildConfig, REMOVED_FILE},
+    diagnostic::{Code, Diagnostic},
+    message::status,
+    prune::{referenced_files, Owner},
+    util::*,
+};
+use anyhow::{anyhow, Context, Result};
+use fs_err as fs;
+use join_str::jstr;
+use path_slash::PathExt;
+use roead::byml::Byml;
+use std::{
+    collections::HashSet,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub enum RemoveCommand {
+    /// Remove an actor from the current project, along with any files only it uses
+    Actor {
+        #[structopt(help = "Actor name")]
+        name: String,
+    },
+    /// Remove an event from the current project, along with any files only it uses
+    Event {
+        #[structopt(help = "Event name")]
+        name: String,
+    },
+}
+
+/// Deletes files, reporting how many were removed, and cleans up any folders left empty
+pub fn delete_files(content: &Path, files: &HashSet<PathBuf>) -> Result<usize> {
+    let mut count = 0;
+    for file in files.iter().filter(|f| f.is_file()) {
+        fs::remove_file(file)?;
+        count += 1;
+        for dir in file
+            .ancestors()
+            .skip(1)
+            .take_while(|d| d.starts_with(content) && d != &content)
+        {
+            if fs::read_dir(dir)?.next().is_some() {
+                break;
+            }
+            fs::remove_dir(dir)?;
+        }
+    }
+    Ok(count)
+}
+
+/// Adds outputs to be deleted on the next build
+pub fn mark_removed(project: &Path, outputs: &[String]) -> Result<()> {
+    let mut list = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(project.join(REMOVED_FILE))?;
+    for output in outputs {
+        writeln!(list, "{}", output)?;
+    }
+    Ok(())
+}
+
+/// Warns about project files which still mention a removed name
+fn warn_references(project: &Path, name: &str) -> Result<()> {
+    let references: Vec<PathBuf> = text_sources(project)?
+        .into_iter()
+        .filter(|f| {
+            fs::read_to_string(f)
+                .map(|text| contains_name(&text, name))
+                .unwrap_or(false)
+        })
+        .collect();
+    for file in references {
+        Diagnostic::warning(
+            Code::StaleReference,
+            jstr!("Still refers to removed {name}"),
+        )
+        .with_file(file)
+        .print(project);
+    }
+    Ok(())
+}
+
+impl RemoveCommand {
+    pub fn remove_actor(&self, project: PathBuf, be: bool) -> Result<()> {
+        if let Self::Actor { name } = self {
+            let content_dir = if be {
+                "content"
+            } else {
+                "01007EF00011E000/romfs"
+            };
+            let content = project.join(content_dir);
+            let link_root = content.join("Actor/ActorLink");
+            let link = link_root.join(jstr!("{name}.bxml.yml"));
+            if !link.exists() {
+                return Err(anyhow!("Actor {} not found in project", name));
+            }
+            status!("Finding files used by {}...", name);
+            // Files of an unreadable actor or event can't be told apart, so nothing is removed
+            let shared = referenced_files(&content, Some(Owner::Actor(name)))?;
+            let mut owned: HashSet<PathBuf> = actor_files(&content, &link)?
+                .into_iter()
+                .filter(|f| !shared.contains(f))
+                .collect();
+            owned.insert(content.join(jstr!("Actor/ActorInfo/{name}.info.yml")));
+            let removed = delete_files(&content, &owned)?;
+            let variants = content.join(jstr!("Variants/{name}.sbactorpack"));
+            if variants.exists() {
+                fs::remove_dir_all(variants)?;
+            }
+
+            if let Some(mut config) = BuildConfig::load(&project)? {
+                let count = config.options.title_actors.len();
+                config.options.title_actors.retain(|a| a != name);
+                if config.options.title_actors.len() != count {
+                    config.save(&project)?;
+                    status!("Removed {} from title actors in config.yml", name);
+                }
+            }
+
+            mark_removed(
+                &project,
+                &[
+                    jstr!("{content_dir}/Actor/Pack/{name}.sbactorpack"),
+                    jstr!("{content_dir}/Pack/TitleBG.pack//Actor/Pack/{name}.sbactorpack"),
+                ],
+            )?;
+            status!("Removed {} ({} files)", name, removed);
+            warn_references(&project, name)?;
+        };
+        Ok(())
+    }
+
+    pub fn remove_event(&self, project: PathBuf, be: bool) -> Result<()> {
+        if let Self::Event { name } = self {
+            let content_dir = if be {
+                "content"
+            } else {
+                "01007EF00011E000/romfs"
+            };
+            let content = project.join(content_dir);
+            let info_root = content.join("Event/EventInfo");
+            let info_path = info_root.join(jstr!("{name}.info.yml"));
+            if !info_path.exists() {
+                return Err(anyhow!("Event {} not found in project", name));
+            }
+            let load_files = |file: &Path, event: &str| -> Result<HashSet<PathBuf>> {
+                let info = Byml::from_text(fs::read_to_string(file)?)
+                    .with_context(|| jstr!("Failed to parse YAML at {&file.to_slash_lossy()}"))?
+                    .into_map()?;
+                event_files(&content, event, &info)
+            };
+            status!("Finding files used by {}...", name);
+            // Files of an unreadable actor or event can't be told apart, so nothing is removed
+            let shared = referenced_files(&content, Some(Owner::Event(name)))?;
+            let mut owned = load_files(&info_path, name)?;
+            owned.retain(|f| !shared.contains(f));
+            owned.insert(info_path);
+            let removed = delete_files(&content, &owned)?;
+            let variants = content.join(jstr!("Variants/{name}.sbeventpack"));
+            if variants.exists() {
+                fs::remove_dir_all(variants)?;
+            }
+            mark_removed(
+                &project,
+                &[
+                    jstr!("{content_dir}/Event/{name}.sbeventpack"),
+                    jstr!("{content_dir}/Pack/TitleBG.pack//Event/{name}.sbeventpack"),
+                ],
+            )?;
+            status!("Removed {} ({} files)", name, removed);
+            warn_references(&project, name)?;
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,278 @@
+use crate::{
+    builder::{actor::ACTOR_LINKS, BuildConfig},
+    message::status,
+    remove::mark_removed,
+    util::*,
+};
+use anyhow::{anyhow, Result};
+use fs_err as fs;
+use join_str::jstr;
+use path_slash::PathExt;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+/// Message labels which belong to an actor, as suffixes of its name
+static MESSAGE_SUFFIXES: &[&str] = &["_Name", "_Desc", "_PictureBook"];
+
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab-case")]
+pub enum RenameCommand {
+    /// Rename an actor and update every reference to it in the project
+    Actor {
+        #[structopt(help = "Current actor name")]
+        old_name: String,
+        #[structopt(help = "New actor name")]
+        new_name: String,
+        #[structopt(
+            long,
+            short,
+            help = "Also rename actor link files named after the actor, e.g. `{name}.bgparamlist.yml`"
+        )]
+        sub_files: bool,
+    },
+}
+
+/// Files to rename, which are all checked before anything in the project is changed
+#[derive(Debug, Default)]
+struct Renames(Vec<(PathBuf, PathBuf)>);
+
+impl Renames {
+    /// Adds a rename if the file exists
+    fn add(&mut self, from: PathBuf, to: PathBuf) {
+        if from.exists() {
+            self.0.push((from, to));
+        }
+    }
+
+    /// Fails if any of the new names is already taken
+    fn check(&self, project: &Path) -> Result<()> {
+        let taken = self
+            .0
+            .iter()
+            .filter(|(_, to)| to.exists())
+            .map(|(_, to)| Ok(to.strip_prefix(project)?.to_slash_lossy().into_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        if taken.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("{} already exist", taken.join(", ")))
+        }
+    }
+
+    fn apply(&self, project: &Path, changes: &mut Vec<String>) -> Result<()> {
+        for (from, to) in &self.0 {
+            fs::rename(from, to)?;
+            changes.push(jstr!(
+                "renamed {&from.strip_prefix(project)?.to_slash_lossy()} to {&to.file_name().unwrap().to_string_lossy()}"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Where an edited file is written before it replaces the original. Dot files are never read as
+/// project sources, so a leftover one is harmless.
+fn staged_path(file: &Path) -> PathBuf {
+    file.with_file_name(format!(
+        ".{}.rename",
+        file.file_name().unwrap_or_default().to_string_lossy()
+    ))
+}
+
+/// Writes every edit next to the file it replaces, removing them all again if one fails, so
+/// the project is unchanged unless every edit is ready
+fn stage_edits(edits: &[(PathBuf, String, usize)]) -> Result<()> {
+    for (i, (file, text, _)) in edits.iter().enumerate() {
+        if let Err(e) = fs::write(staged_path(file), text) {
+            for (file, ..) in &edits[..=i] {
+                let _ = fs::remove_file(staged_path(file));
+            }
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+impl RenameCommand {
+    pub fn rename_actor(&self, project: PathBuf, be: bool) -> Result<()> {
+        if let Self::Actor {
+            old_name,
+            new_name,
+            sub_files,
+        } = self
+        {
+            let content_dir = if be {
+                "content"
+            } else {
+                "01007EF00011E000/romfs"
+            };
+            let content = project.join(content_dir);
+            let link_root = content.join("Actor/ActorLink");
+            let link = link_root.join(jstr!("{&old_name}.bxml.yml"));
+            if !link.exists() {
+                return Err(anyhow!("Actor {} not found in project", old_name));
+            }
+            if link_root.join(jstr!("{&new_name}.bxml.yml")).exists() {
+                return Err(anyhow!("Actor {} already exists in project", new_name));
+            }
+            let mut changes = vec![];
+
+            let mut renames = Renames::default();
+            renames.add(link, link_root.join(jstr!("{&new_name}.bxml.yml")));
+            let info_root = content.join("Actor/ActorInfo");
+            renames.add(
+                info_root.join(jstr!("{&old_name}.info.yml")),
+                info_root.join(jstr!("{&new_name}.info.yml")),
+            );
+            let variant_root = content.join("Variants");
+            renames.add(
+                variant_root.join(jstr!("{&old_name}.sbactorpack")),
+                variant_root.join(jstr!("{&new_name}.sbactorpack")),
+            );
+            if *sub_files {
+                for link in ACTOR_LINKS.values() {
+                    renames.add(
+                        content.join(link.yaml_path(old_name)),
+                        content.join(link.yaml_path(new_name)),
+                    );
+                }
+            }
+            renames.check(&project)?;
+
+            status!("Updating references to {}...", old_name);
+            let mut edits = vec![];
+            for file in text_sources(&project)? {
+                // Actor links name the users of their sub-files, not actors, so they only
+                // change when the sub-files are renamed too
+                if !sub_files && file.starts_with(&link_root) {
+                    continue;
+                }
+                let text = fs::read_to_string(&file)?;
+                let (mut text, mut count) = replace_name(&text, old_name, new_name);
+                if file.extension().and_then(|e| e.to_str()) == Some("msyt") {
+                    for suffix in MESSAGE_SUFFIXES {
+                        let (new_text, labels) = replace_name(
+                            &text,
+                            &jstr!("{&old_name}{suffix}"),
+                            &jstr!("{&new_name}{suffix}"),
+                        );
+                        text = new_text;
+                        count += labels;
+                    }
+                }
+                if count > 0 {
+                    edits.push((file, text, count));
+                }
+            }
+            // Nothing is changed until every file has been read, every rename checked, and
+            // every edit written out. Moving the edits and files into place can still fail
+            // partway, so then the changes already made are listed.
+            stage_edits(&edits)?;
+            let mut replace = || -> Result<()> {
+                for (file, _, count) in &edits {
+                    fs::rename(staged_path(file), file)?;
+                    changes.push(format!(
+                        "{}: {} references",
+                        file.strip_prefix(&project)?.to_slash_lossy(),
+                        count
+                    ));
+                }
+                renames.apply(&project, &mut changes)
+            };
+            if let Err(e) = replace() {
+                for (file, ..) in &edits {
+                    let _ = fs::remove_file(staged_path(file));
+                }
+                return Err(e.context(if changes.is_empty() {
+                    "Rename failed, nothing was changed".to_owned()
+                } else {
+                    format!(
+                        "Rename failed partway, after these changes: {}",
+                        changes.join("; ")
+                    )
+                }));
+            }
+
+            if let Some(mut config) = BuildConfig::load(&project)? {
+                if let Some(actor) = config
+                    .options
+                    .title_actors
+                    .iter_mut()
+                    .find(|a| **a == *old_name)
+                {
+                    *actor = new_name.clone();
+                    config.save(&project)?;
+                    changes.push("config.yml: title actors".to_owned());
+                }
+            }
+
+            mark_removed(
+                &project,
+                &[
+                    jstr!("{content_dir}/Actor/Pack/{&old_name}.sbactorpack"),
+                    jstr!("{content_dir}/Pack/TitleBG.pack//Actor/Pack/{&old_name}.sbactorpack"),
+                ],
+            )?;
+            status!(
+                "Renamed {} to {}, {} changes:",
+                old_name,
+                new_name,
+                changes.len()
+            );
+            for change in changes {
+                status!("  {}", change);
+            }
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn conflict_changes_nothing() {
+        let project = TempDir::new("rename");
+        let content = project.join("content");
+        fs::create_dir_all(content.join("Actor/ActorLink")).unwrap();
+        fs::create_dir_all(content.join("Actor/ActorInfo")).unwrap();
+        fs::create_dir_all(content.join("Map/MainField/A-1")).unwrap();
+        let link = content.join("Actor/ActorLink/Obj_Old.bxml.yml");
+        let user = content.join("Map/MainField/A-1/A-1_Static.smubin.yml");
+        fs::write(&link, "ActorNameJpn: Obj_Old").unwrap();
+        fs::write(&user, "UnitConfigName: Obj_Old").unwrap();
+        fs::write(
+            content.join("Actor/ActorInfo/Obj_Old.info.yml"),
+            "name: Obj_Old",
+        )
+        .unwrap();
+        // Only the actor info is taken, so the conflict is found after the actor link check
+        fs::write(
+            content.join("Actor/ActorInfo/Obj_New.info.yml"),
+            "name: Obj_New",
+        )
+        .unwrap();
+        let command = RenameCommand::Actor {
+            old_name: "Obj_Old".into(),
+            new_name: "Obj_New".into(),
+            sub_files: false,
+        };
+        assert!(command.rename_actor(project.to_path_buf(), true).is_err());
+        assert!(link.exists());
+        assert_eq!(
+            fs::read_to_string(&user).unwrap(),
+            "UnitConfigName: Obj_Old"
+        );
+
+        fs::remove_file(content.join("Actor/ActorInfo/Obj_New.info.yml")).unwrap();
+        command.rename_actor(project.to_path_buf(), true).unwrap();
+        assert!(!link.exists());
+        assert_eq!(
+            fs::read_to_string(&user).unwrap(),
+            "UnitConfigName: Obj_New"
+        );
+        assert!(!staged_path(&user).exists());
+    }
+}
@@ -0,0 +1,553 @@
+use crate::diagnostic::{Code, Diagnostic};
+use anyhow::{ensure, Context, Result};
+use fs_err as fs;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path};
+
+pub static META_FILE: &str = ".sarc.yml";
+
+/// Larger alignments than this only ever happen by chance, so they are not kept
+const MAX_ALIGNMENT: u32 = 0x2000;
+
+/// A file in a SARC, in the order its data appears in the original
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SarcEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Only stored for entries without a name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<u32>,
+    pub alignment: u32,
+    /// Where the data begins in the data section. Only stored when the alignments alone don't
+    /// reproduce the original, e.g. when it has padding larger than any alignment needs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u32>,
+}
+
+/// Everything about an unbuilt SARC which is not kept by its files, stored in a `.sarc.yml` file
+/// in the SARC folder so it can be rebuilt exactly as it was.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SarcMeta {
+    pub big_endian: bool,
+    pub hash_multiplier: u32,
+    pub min_alignment: usize,
+    pub data_alignment: u32,
+    pub leading_slash: bool,
+    /// Start of the data section, only stored along with the entry offsets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_offset: Option<u32>,
+    pub entries: Vec<SarcEntry>,
+    /// Entries left out when unbuilding with `--modified-only`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vanilla: Vec<String>,
+}
+
+/// Rounds up to a multiple of the alignment, or `None` if it doesn't fit in the SARC's offsets
+#[inline]
+fn align(value: u32, alignment: u32) -> Option<u32> {
+    value.checked_next_multiple_of(alignment)
+}
+
+/// The end of a file's data in the SARC, if its offsets can hold it
+#[inline]
+fn data_end(begin: u32, data: &[u8]) -> Option<u32> {
+    begin.checked_add(u32::try_from(data.len()).ok()?)
+}
+
+#[inline]
+fn too_large() -> Diagnostic {
+    Diagnostic::error(
+        Code::InvalidData,
+        "SARC is too large for its offsets, which are 32-bit",
+    )
+}
+
+#[inline]
+fn max_alignment(offset: u32) -> u32 {
+    if offset == 0 {
+        MAX_ALIGNMENT
+    } else {
+        (1 << offset.trailing_zeros()).min(MAX_ALIGNMENT)
+    }
+}
+
+#[inline]
+pub fn name_hash(name: &str, multiplier: u32) -> u32 {
+    name.bytes().fold(0, |hash, c| {
+        hash.wrapping_mul(multiplier).wrapping_add(c as i8 as u32)
+    })
+}
+
+impl SarcMeta {
+    /// Data for an entry without a name is kept in the SARC folder under this file name
+    #[inline]
+    pub fn unnamed_file(hash: u32) -> String {
+        format!(".unnamed.{:08x}", hash)
+    }
+
+    /// Reads the layout of a (decompressed) SARC, along with the hashes and data of any entries
+    /// which have no name.
+    pub fn from_binary(data: &[u8]) -> Result<(Self, Vec<(u32, &[u8])>)> {
+        ensure!(
+            data.len() >= 0x28 && &data[0..4] == b"SARC",
+            "Invalid SARC header"
+        );
+        let big_endian = data[6..8] == [0xFE, 0xFF];
+        let read_u16 = |offset: usize| -> Result<u16> {
+            let bytes: [u8; 2] = data
+                .get(offset..offset + 2)
+                .context("SARC ended early")?
+                .try_into()?;
+            Ok(if big_endian {
+                u16::from_be_bytes(bytes)
+            } else {
+                u16::from_le_bytes(bytes)
+            })
+        };
+        let read_u32 = |offset: usize| -> Result<u32> {
+            let bytes: [u8; 4] = data
+                .get(offset..offset + 4)
+                .context("SARC ended early")?
+                .try_into()?;
+            Ok(if big_endian {
+                u32::from_be_bytes(bytes)
+            } else {
+                u32::from_le_bytes(bytes)
+            })
+        };
+        let data_offset = read_u32(0xC)?;
+        let node_count = read_u16(0x1A)? as usize;
+        let hash_multiplier = read_u32(0x1C)?;
+        let names_offset = 0x20 + node_count * 0x10 + 8;
+        let mut nodes = Vec::with_capacity(node_count);
+        for i in 0..node_count {
+            let node = 0x20 + i * 0x10;
+            let hash = read_u32(node)?;
+            let attributes = read_u32(node + 4)?;
+            let begin = read_u32(node + 8)?;
+            let end = read_u32(node + 12)?;
+            let name = if attributes >> 24 != 0 {
+                let start = names_offset + (attributes & 0xFFFF) as usize * 4;
+                let len = data
+                    .get(start..)
+                    .and_then(|names| names.iter().position(|c| *c == 0))
+                    .context("SARC name table ended early")?;
+                Some(std::str::from_utf8(&data[start..start + len])?.to_owned())
+            } else {
+                None
+            };
+            nodes.push((hash, name, begin, end));
+        }
+        nodes.sort_by_key(|(_, _, begin, _)| *begin);
+        let mut files = Vec::with_capacity(nodes.len());
+        let entries = nodes
+            .into_iter()
+            .map(|(hash, name, begin, end)| -> Result<SarcEntry> {
+                let start = data_offset
+                    .checked_add(begin)
+                    .context("Invalid SARC offsets")?;
+                let stop = data_offset
+                    .checked_add(end)
+                    .context("Invalid SARC offsets")?;
+                files.push((
+                    hash,
+                    data.get(start as usize..stop as usize)
+                        .context("SARC data ended early")?,
+                ));
+                Ok(SarcEntry {
+                    hash: name.is_none().then_some(hash),
+                    name,
+                    alignment: max_alignment(start),
+                    offset: Some(begin),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let min_alignment = entries
+            .iter()
+            .map(|e| e.alignment)
+            .min()
+            .unwrap_or(4)
+            .max(4) as usize;
+        let mut meta = Self {
+            big_endian,
+            hash_multiplier,
+            min_alignment,
+            data_alignment: max_alignment(data_offset),
+            leading_slash: entries
+                .iter()
+                .any(|e| e.name.as_deref().unwrap_or("").starts_with('/')),
+            data_offset: None,
+            entries,
+            vanilla: vec![],
+        };
+        // Keep the offsets only if the alignments can't reproduce the original layout
+        let offsets: Vec<Option<u32>> = meta.entries.iter_mut().map(|e| e.offset.take()).collect();
+        let layout: Vec<(u32, Option<&str>, &[u8])> = meta
+            .entries
+            .iter()
+            .zip(files.iter())
+            .map(|(e, (hash, data))| (*hash, e.name.as_deref(), *data))
+            .collect();
+        if meta.write(&layout).ok().as_deref() != Some(data) {
+            meta.data_offset = Some(data_offset);
+            for (entry, offset) in meta.entries.iter_mut().zip(offsets) {
+                entry.offset = offset;
+            }
+        }
+        let unnamed = meta
+            .entries
+            .iter()
+            .zip(files)
+            .filter(|(e, _)| e.name.is_none())
+            .map(|(_, file)| file)
+            .collect();
+        Ok((meta, unnamed))
+    }
+
+    /// Loads the layout of an unbuilt SARC, if it has one. Alignments must be powers of two,
+    /// since they may have been edited by hand.
+    pub fn load(folder: &Path) -> Result<Option<Self>> {
+        let path = folder.join(META_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(&path)?;
+        let meta: Self =
+            serde_yml::from_str(&text).map_err(|e| Diagnostic::yaml(&path, "SARC layout", &e))?;
+        let invalid = std::iter::once(("data_alignment", meta.data_alignment as usize))
+            .chain(std::iter::once(("min_alignment", meta.min_alignment)))
+            .chain(meta.entries.iter().map(|e| {
+                (
+                    e.name.as_deref().unwrap_or("unnamed entry"),
+                    e.alignment as usize,
+                )
+            }))
+            .find(|(_, alignment)| !alignment.is_power_of_two());
+        if let Some((name, alignment)) = invalid {
+            return Err(Diagnostic::error(
+                Code::InvalidData,
+                format!(
+                    "Invalid alignment {} for {}, alignments must be powers of two",
+                    alignment, name
+                ),
+            )
+            .with_file(&path)
+            .into());
+        }
+        Ok(Some(meta))
+    }
+
+    pub fn save(&self, folder: &Path) -> Result<()> {
+        fs::write(folder.join(META_FILE), serde_yml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Writes a SARC with the original layout. If the files no longer match the original
+    /// entries, the layout cannot be kept, and nothing is written.
+    pub fn to_binary<'a>(
+        &self,
+        folder: &Path,
+        files: impl Iterator<Item = (&'a str, &'a [u8])>,
+    ) -> Result<Option<Vec<u8>>> {
+        let mut files: HashMap<&str, &[u8]> = files.collect();
+        let mut unnamed: Vec<Option<Vec<u8>>> = vec![None; self.entries.len()];
+        for (i, entry) in self.entries.iter().enumerate() {
+            match (&entry.name, entry.hash) {
+                (Some(_), _) => (),
+                (None, Some(hash)) => {
+                    let path = folder.join(Self::unnamed_file(hash));
+                    if !path.exists() {
+                        return Ok(None);
+                    }
+                    unnamed[i] = Some(fs::read(path)?);
+                }
+                (None, None) => return Ok(None),
+            }
+        }
+        let mut entries = Vec::with_capacity(self.entries.len());
+        for (entry, unnamed) in self.entries.iter().zip(unnamed.iter()) {
+            match (&entry.name, unnamed) {
+                (Some(name), _) => match files.remove(name.as_str()) {
+                    Some(data) => entries.push((
+                        name_hash(name, self.hash_multiplier),
+                        Some(name.as_str()),
+                        data,
+                    )),
+                    None => return Ok(None),
+                },
+                (None, Some(data)) => {
+                    entries.push((entry.hash.unwrap_or_default(), None, data.as_slice()))
+                }
+                (None, None) => return Ok(None),
+            }
+        }
+        if !files.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(self.write(&entries)?))
+    }
+
+    /// Writes a SARC from the hash, name, and data of each entry, in the same order as
+    /// [`SarcMeta::entries`]. Fails if the data doesn't fit in 32-bit offsets.
+    fn write(&self, entries: &[(u32, Option<&str>, &[u8])]) -> Result<Vec<u8>> {
+        let mut nodes: Vec<usize> = (0..entries.len()).collect();
+        nodes.sort_by_key(|i| entries[*i].0);
+        let mut names: Vec<u8> = vec![];
+        let mut name_offsets = vec![0; entries.len()];
+        for &i in &nodes {
+            if let Some(name) = entries[i].1 {
+                name_offsets[i] = names.len() as u32 / 4;
+                names.extend_from_slice(name.as_bytes());
+                names.push(0);
+                names.resize(names.len().next_multiple_of(4), 0);
+            }
+        }
+        let names_end =
+            u32::try_from(0x20 + nodes.len() * 0x10 + 8 + names.len()).map_err(|_| too_large())?;
+        let data_offset = align(names_end, self.data_alignment).ok_or_else(too_large)?;
+        let mut ranges = vec![(0, 0); entries.len()];
+        let mut end = 0;
+        for (i, ((_, _, data), entry)) in entries.iter().zip(&self.entries).enumerate() {
+            let begin = data_offset
+                .checked_add(end)
+                .and_then(|offset| align(offset, entry.alignment))
+                .ok_or_else(too_large)?
+                - data_offset;
+            end = data_end(begin, data).ok_or_else(too_large)?;
+            ranges[i] = (begin, end);
+        }
+        // The recorded offsets are used as long as the files still fit in them
+        let (data_offset, end) = match self.data_offset.filter(|offset| *offset >= names_end) {
+            Some(recorded) => {
+                let mut recorded_end = 0;
+                let recorded_ranges: Option<Vec<(u32, u32)>> = entries
+                    .iter()
+                    .zip(&self.entries)
+                    .map(|((_, _, data), entry)| {
+                        let begin = entry.offset.filter(|begin| *begin >= recorded_end)?;
+                        recorded_end = data_end(begin, data)?;
+                        recorded.checked_add(recorded_end)?;
+                        Some((begin, recorded_end))
+                    })
+                    .collect();
+                match recorded_ranges {
+                    Some(recorded_ranges) => {
+                        ranges = recorded_ranges;
+                        (recorded, recorded_end)
+                    }
+                    None => (data_offset, end),
+                }
+            }
+            None => (data_offset, end),
+        };
+        let size = data_offset.checked_add(end).ok_or_else(too_large)?;
+
+        let mut out = Vec::with_capacity(size as usize);
+        let be = self.big_endian;
+        let put_u16 = |out: &mut Vec<u8>, value: u16| {
+            out.extend_from_slice(&if be {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            })
+        };
+        let put_u32 = |out: &mut Vec<u8>, value: u32| {
+            out.extend_from_slice(&if be {
+                value.to_be_bytes()
+            } else {
+                value.to_le_bytes()
+            })
+        };
+        out.extend_from_slice(b"SARC");
+        put_u16(&mut out, 0x14);
+        put_u16(&mut out, 0xFEFF);
+        put_u32(&mut out, size);
+        put_u32(&mut out, data_offset);
+        put_u16(&mut out, 0x100);
+        put_u16(&mut out, 0);
+        out.extend_from_slice(b"SFAT");
+        put_u16(&mut out, 0xC);
+        put_u16(&mut out, nodes.len() as u16);
+        put_u32(&mut out, self.hash_multiplier);
+        for &i in &nodes {
+            put_u32(&mut out, entries[i].0);
+            put_u32(
+                &mut out,
+                if entries[i].1.is_some() {
+                    0x0100_0000 | name_offsets[i]
+                } else {
+                    0
+                },
+            );
+            put_u32(&mut out, ranges[i].0);
+            put_u32(&mut out, ranges[i].1);
+        }
+        out.extend_from_slice(b"SFNT");
+        put_u16(&mut out, 8);
+        put_u16(&mut out, 0);
+        out.extend_from_slice(&names);
+        for (i, (_, _, data)) in entries.iter().enumerate() {
+            out.resize((data_offset + ranges[i].0) as usize, 0);
+            out.extend_from_slice(data);
+        }
+        out.resize(size as usize, 0);
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn hash_names() {
+        assert_eq!(name_hash("", 0x65), 0);
+        assert_eq!(name_hash("A", 0x65), 0x41);
+        assert_eq!(name_hash("AB", 0x65), 0x41 * 0x65 + 0x42);
+    }
+
+    #[test]
+    fn roundtrip_layout() {
+        let meta = SarcMeta {
+            big_endian: true,
+            hash_multiplier: 0x65,
+            min_alignment: 4,
+            data_alignment: 0x100,
+            leading_slash: false,
+            data_offset: None,
+            entries: vec![
+                SarcEntry {
+                    name: Some("b.bin".into()),
+                    hash: None,
+                    alignment: 4,
+                    offset: None,
+                },
+                SarcEntry {
+                    name: Some("a.bin".into()),
+                    hash: None,
+                    alignment: 0x80,
+                    offset: None,
+                },
+            ],
+            vanilla: vec![],
+        };
+        let files = [
+            ("a.bin", b"AAAA".as_slice()),
+            ("b.bin", b"BBBBBB".as_slice()),
+        ];
+        let data = meta
+            .to_binary(Path::new("."), files.iter().copied())
+            .unwrap()
+            .unwrap();
+        let (parsed, unnamed) = SarcMeta::from_binary(&data).unwrap();
+        assert!(unnamed.is_empty());
+        assert!(parsed.big_endian);
+        assert_eq!(parsed.data_alignment, 0x100);
+        assert_eq!(
+            parsed
+                .entries
+                .iter()
+                .map(|e| e.name.as_deref().unwrap())
+                .collect::<Vec<_>>(),
+            ["b.bin", "a.bin"]
+        );
+        let rebuilt = parsed
+            .to_binary(Path::new("."), files.iter().copied())
+            .unwrap()
+            .unwrap();
+        assert_eq!(data, rebuilt);
+        assert!(meta
+            .to_binary(Path::new("."), files[..1].iter().copied())
+            .unwrap()
+            .is_none());
+        assert!(parsed.data_offset.is_none());
+        assert!(parsed.entries.iter().all(|e| e.offset.is_none()));
+    }
+
+    #[test]
+    fn roundtrip_offsets() {
+        // Padding after b.bin which no alignment explains, and an unnamed entry
+        let mut meta = SarcMeta {
+            big_endian: false,
+            hash_multiplier: 0x65,
+            min_alignment: 4,
+            data_alignment: 0x100,
+            leading_slash: false,
+            data_offset: Some(0x100),
+            entries: vec![
+                SarcEntry {
+                    name: Some("b.bin".into()),
+                    hash: None,
+                    alignment: 4,
+                    offset: Some(0),
+                },
+                SarcEntry {
+                    name: None,
+                    hash: Some(0x1234),
+                    alignment: 4,
+                    offset: Some(0x24),
+                },
+                SarcEntry {
+                    name: Some("a.bin".into()),
+                    hash: None,
+                    alignment: 4,
+                    offset: Some(0x30),
+                },
+            ],
+            vanilla: vec![],
+        };
+        let entries = [
+            (
+                name_hash("b.bin", 0x65),
+                Some("b.bin"),
+                b"BBBBBB".as_slice(),
+            ),
+            (0x1234, None, b"UU".as_slice()),
+            (name_hash("a.bin", 0x65), Some("a.bin"), b"AAAA".as_slice()),
+        ];
+        let data = meta.write(&entries).unwrap();
+        let (parsed, unnamed) = SarcMeta::from_binary(&data).unwrap();
+        assert_eq!(unnamed, vec![(0x1234, b"UU".as_slice())]);
+        assert_eq!(parsed.data_offset, Some(0x100));
+        assert_eq!(
+            parsed.entries.iter().map(|e| e.offset).collect::<Vec<_>>(),
+            [Some(0), Some(0x24), Some(0x30)]
+        );
+        assert_eq!(parsed.write(&entries).unwrap(), data);
+
+        // Files which no longer fit fall back to the alignments
+        meta.entries[0].offset = Some(0x28);
+        let rebuilt = meta.write(&entries).unwrap();
+        let (parsed, _) = SarcMeta::from_binary(&rebuilt).unwrap();
+        assert!(parsed.data_offset.is_none());
+
+        // Offsets past the end of a 32-bit SARC are an error, not an overflow
+        meta.data_offset = None;
+        meta.entries[2].alignment = 0x8000_0000;
+        meta.entries[1].offset = None;
+        assert!(meta.write(&entries).is_err());
+    }
+
+    #[test]
+    fn reject_bad_alignments() {
+        let dir = TempDir::new("sarc_meta");
+        fs::create_dir_all(&dir).unwrap();
+        let text = "big_endian: true\nhash_multiplier: 101\nmin_alignment: 4\ndata_alignment: \
+                    {}\nleading_slash: false\nentries:\n- name: a.bin\n  alignment: {}\n";
+        for (data, entry, ok) in [
+            (0x100, 4, true),
+            (0, 4, false),
+            (0x100, 0, false),
+            (0x100, 3, false),
+        ] {
+            fs::write(
+                dir.join(META_FILE),
+                text.replacen("{}", &data.to_string(), 1)
+                    .replacen("{}", &entry.to_string(), 1),
+            )
+            .unwrap();
+            assert_eq!(SarcMeta::load(&dir).is_ok(), ok, "{} {}", data, entry);
+        }
+    }
+}
@@ -1,8 +1,17 @@
 #![allow(dead_code)]
-use crate::Result;
+use crate::{
+    builder::BuildConfig,
+    diagnostic::Code,
+    game_dump::{self, DumpInfo},
+    message::{self, status},
+    Result,
+};
 use anyhow::{anyhow, Context};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
@@ -14,46 +23,116 @@ pub enum ConfigCommand {
         setting: String,
         #[structopt(help = "Setting value")]
         value: String,
+        #[structopt(long, short, help = "Game profile to set, created if it does not exist")]
+        profile: Option<String>,
     },
     /// Get a config value
     Get {
         #[structopt(help = "Setting to get")]
         setting: String,
+        #[structopt(long, short, help = "Game profile to get from")]
+        profile: Option<String>,
     },
     /// List all Hyrule Builder settings
     List {
         #[structopt(long, short, help = "Show setting values")]
         values: bool,
     },
+    /// Check the game folders and detect the game version and region
+    Validate {
+        #[structopt(long, short, help = "Game profile to check")]
+        profile: Option<String>,
+    },
+    /// Choose the game profile used by projects which don't set their own
+    Use {
+        #[structopt(help = "Game profile name, or none to use the default game folders")]
+        profile: Option<String>,
+    },
     /// Import settings from other programs
     Import {
         #[structopt(
             long,
             short = "b",
-            required_unless = "from-cemu",
+            required_unless_one = &["from-cemu", "from-ukmm", "from-dump"],
             help = "Set game folders from BCML settings"
         )]
         from_bcml: bool,
         #[structopt(
             long,
             short = "c",
-            required_unless = "from-bcml",
+            required_unless_one = &["from-bcml", "from-ukmm", "from-dump"],
             requires = "cemu-dir",
             help = "(Wii U) Set game folders from Cemu folder"
         )]
         from_cemu: bool,
+        #[structopt(
+            long,
+            short = "u",
+            required_unless_one = &["from-bcml", "from-cemu", "from-dump"],
+            help = "Set game folders from UKMM settings"
+        )]
+        from_ukmm: bool,
+        #[structopt(
+            long,
+            short = "d",
+            required_unless_one = &["from-bcml", "from-cemu", "from-ukmm"],
+            help = "Set game folders by searching a folder of game dumps, e.g. extracted romfs from an emulator"
+        )]
+        from_dump: Option<PathBuf>,
         #[structopt(help = "Cemu directory (or MLC folder if separate)")]
         cemu_dir: Option<String>,
     },
+    /// Rebuild the cached index of game files used by the `add` command
+    Reindex {
+        #[structopt(long, short, help = "Index the Wii U game files")]
+        be: bool,
+        #[structopt(long, short, help = "Game profile to index, instead of the active one")]
+        profile: Option<String>,
+    },
+}
+
+/// A named set of game folders, e.g. for a different game version or region
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+pub struct GameProfile {
+    #[serde(default)]
+    pub game_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub game_dir_nx: Option<PathBuf>,
+    #[serde(default)]
+    pub update_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub dlc_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub dlc_dir_nx: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dump_info: Option<DumpInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dump_info_nx: Option<DumpInfo>,
+    /// The name of the profile, set when it is loaded, so each profile has its own file index
+    #[serde(skip)]
+    pub name: Option<String>,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+/// The global settings. The game folders at the top level are the default profile, and
+/// [`Settings::load`] gives the folders of the active one.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct Settings {
     pub game_dir: Option<PathBuf>,
     pub game_dir_nx: Option<PathBuf>,
     pub update_dir: Option<PathBuf>,
     pub dlc_dir: Option<PathBuf>,
     pub dlc_dir_nx: Option<PathBuf>,
+    /// The version and region detected from the Wii U game folders
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dump_info: Option<DumpInfo>,
+    /// The version and region detected from the Switch game folders
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dump_info_nx: Option<DumpInfo>,
+    /// The active profile, unless a project picks its own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, GameProfile>,
 }
 
 fn print_setting(setting: &Option<PathBuf>) -> &str {
@@ -63,18 +142,9 @@ fn print_setting(setting: &Option<PathBuf>) -> &str {
         .unwrap_or("-Not set-")
 }
 
-impl Settings {
-    #[inline]
-    pub fn get_settings_path() -> PathBuf {
-        dirs2::data_local_dir()
-            .context("Failed to get local data dir")
-            .unwrap()
-            .join("hyrule_builder/settings.yml")
-    }
-
-    pub fn list(&self, values: bool) {
-        println!("Hyrule Builder configuration settings:");
-        println!(
+impl GameProfile {
+    fn list(&self, values: bool) {
+        status!(
             "  game_dir:     {}",
             if values {
                 print_setting(&self.game_dir)
@@ -82,7 +152,7 @@ impl Settings {
                 "Wii U base game folder"
             }
         );
-        println!(
+        status!(
             "  update_dir:   {}",
             if values {
                 print_setting(&self.update_dir)
@@ -90,7 +160,7 @@ impl Settings {
                 "Wii U update folder"
             }
         );
-        println!(
+        status!(
             "  dlc_dir:      {}",
             if values {
                 print_setting(&self.dlc_dir)
@@ -98,7 +168,7 @@ impl Settings {
                 "Wii U DLC folder"
             }
         );
-        println!(
+        status!(
             "  game_dir_nx:  {}",
             if values {
                 print_setting(&self.game_dir_nx)
@@ -106,7 +176,7 @@ impl Settings {
                 "Switch base game folder"
             }
         );
-        println!(
+        status!(
             "  dlc_dir_nx:   {}",
             if values {
                 print_setting(&self.dlc_dir_nx)
@@ -114,10 +184,17 @@ impl Settings {
                 "Switch DLC folder"
             }
         );
+        if values {
+            for (platform, info) in [("Wii U", &self.dump_info), ("Switch", &self.dump_info_nx)] {
+                if let Some(info) = info {
+                    status!("  {} dump:   {}", platform, describe_info(info));
+                }
+            }
+        }
     }
 
-    pub fn get(&self, setting: &str) -> Result<()> {
-        println!(
+    fn get(&self, setting: &str) -> Result<()> {
+        status!(
             "{}",
             match setting {
                 "game_dir" => print_setting(&self.game_dir),
@@ -131,24 +208,195 @@ impl Settings {
         Ok(())
     }
 
-    pub fn set(&mut self, setting: &str, value: &str) -> Result<()> {
+    fn set(&mut self, setting: &str, value: &str) -> Result<()> {
         let path = PathBuf::from(value);
         if !path.exists() {
-            Err(anyhow!("{} does not exist", path.display()))
+            return Err(anyhow!("{} does not exist", path.display()));
+        }
+        let (source, be) =
+            game_dump::setting_source(setting).ok_or_else(|| anyhow!("Invalid setting"))?;
+        game_dump::check_folder(&path, source, be)?;
+        match setting {
+            "game_dir" => self.game_dir = Some(path),
+            "update_dir" => self.update_dir = Some(path),
+            "dlc_dir" => self.dlc_dir = Some(path),
+            "game_dir_nx" => self.game_dir_nx = Some(path),
+            "dlc_dir_nx" => self.dlc_dir_nx = Some(path),
+            _ => {
+                return Err(anyhow!("Invalid setting"));
+            }
+        };
+        self.detect(be);
+        Ok(())
+    }
+
+    /// Detects the version and region of the dump for a platform, warning about any folders
+    /// which don't match each other
+    pub fn detect(&mut self, be: bool) -> Vec<String> {
+        let (info, problems) = game_dump::validate(self, be);
+        game_dump::print_problems(&problems);
+        if let Some(info) = info.as_ref() {
+            status!(
+                "Detected {} dump: {}",
+                if be { "Wii U" } else { "Switch" },
+                describe_info(info)
+            );
+        }
+        if be {
+            self.dump_info = info;
         } else {
-            match setting {
-                "game_dir" => self.game_dir = Some(path),
-                "update_dir" => self.update_dir = Some(path),
-                "dlc_dir" => self.dlc_dir = Some(path),
-                "game_dir_nx" => self.game_dir_nx = Some(path),
-                "dlc_dir_nx" => self.dlc_dir_nx = Some(path),
-                _ => {
-                    return Err(anyhow!("Invalid setting"));
+            self.dump_info_nx = info;
+        }
+        problems
+    }
+}
+
+fn describe_info(info: &DumpInfo) -> String {
+    format!(
+        "version {}, {}",
+        info.version,
+        info.region.as_deref().unwrap_or("all regions")
+    )
+}
+
+impl Settings {
+    #[inline]
+    pub fn get_settings_path() -> PathBuf {
+        dirs2::data_local_dir()
+            .context("Failed to get local data dir")
+            .unwrap()
+            .join("hyrule_builder/settings.yml")
+    }
+
+    /// The game folders of the default profile
+    #[inline]
+    pub fn default_profile(&self) -> GameProfile {
+        GameProfile {
+            game_dir: self.game_dir.clone(),
+            game_dir_nx: self.game_dir_nx.clone(),
+            update_dir: self.update_dir.clone(),
+            dlc_dir: self.dlc_dir.clone(),
+            dlc_dir_nx: self.dlc_dir_nx.clone(),
+            dump_info: self.dump_info.clone(),
+            dump_info_nx: self.dump_info_nx.clone(),
+            name: None,
+        }
+    }
+
+    fn get_profile(&self, profile: Option<&str>) -> Result<GameProfile> {
+        match profile {
+            Some(name) => self
+                .profiles
+                .get(name)
+                .cloned()
+                .with_context(|| format!("Game profile {} not found", name)),
+            None => Ok(self.default_profile()),
+        }
+    }
+
+    fn set_profile(&mut self, profile: Option<&str>, paths: GameProfile) {
+        match profile {
+            Some(name) => {
+                self.profiles.insert(name.to_owned(), paths);
+            }
+            None => {
+                self.game_dir = paths.game_dir;
+                self.game_dir_nx = paths.game_dir_nx;
+                self.update_dir = paths.update_dir;
+                self.dlc_dir = paths.dlc_dir;
+                self.dlc_dir_nx = paths.dlc_dir_nx;
+                self.dump_info = paths.dump_info;
+                self.dump_info_nx = paths.dump_info_nx;
+            }
+        }
+    }
+
+    pub fn list(&self, values: bool) {
+        status!("Hyrule Builder configuration settings:");
+        self.default_profile().list(values);
+        if !values {
+            return;
+        }
+        for (name, profile) in &self.profiles {
+            status!(
+                "Game profile {}{}:",
+                name,
+                if self.profile.as_deref() == Some(name.as_str()) {
+                    " (active)"
+                } else {
+                    ""
                 }
-            };
-            self.save()?;
+            );
+            profile.list(values);
+        }
+    }
+
+    pub fn get(&self, setting: &str, profile: Option<&str>) -> Result<()> {
+        self.get_profile(profile)?.get(setting)
+    }
+
+    pub fn set(&mut self, setting: &str, value: &str, profile: Option<&str>) -> Result<()> {
+        let mut paths = match profile {
+            Some(name) => self.profiles.get(name).cloned().unwrap_or_default(),
+            None => self.default_profile(),
+        };
+        paths.set(setting, value)?;
+        self.set_profile(profile, paths);
+        self.save()?;
+        Ok(())
+    }
+
+    /// Checks the game folders of a profile for both platforms, failing if any are wrong
+    pub fn validate(&mut self, profile: Option<&str>) -> Result<()> {
+        let mut paths = self.get_profile(profile)?;
+        let problems = [paths.detect(true), paths.detect(false)].concat();
+        self.set_profile(profile, paths);
+        self.save()?;
+        if problems.is_empty() {
+            status!("Game folders are valid");
             Ok(())
+        } else {
+            Err(anyhow!("{} problems found with game folders", problems.len()))
+        }
+    }
+
+    /// Sets the profile used when a project doesn't choose one
+    pub fn use_profile(&mut self, profile: Option<String>) -> Result<()> {
+        if let Some(name) = profile.as_ref() {
+            if !self.profiles.contains_key(name) {
+                return Err(anyhow!("Game profile {} not found", name));
+            }
+            status!("Using game profile {}", name);
+        } else {
+            status!("Using default game folders");
         }
+        self.profile = profile;
+        self.save()
+    }
+
+    /// The game folders of a profile, or of the active one if none is chosen. These are kept
+    /// apart from the settings, so the defaults are never saved over with another profile.
+    pub fn game_profile(&self, profile: Option<&str>) -> Result<GameProfile> {
+        let name = profile.or(self.profile.as_deref());
+        Ok(GameProfile {
+            name: name.map(|n| n.to_owned()),
+            ..self.get_profile(name)?
+        })
+    }
+
+    /// Loads the game folders of the active profile, which is the one named in the project's
+    /// `config.yml` `profile` option if there is one, or else the global one.
+    pub fn load(project: Option<&Path>) -> Result<GameProfile> {
+        let project_profile = match project {
+            Some(project) => BuildConfig::load(project)?.and_then(|c| c.options.profile),
+            None => None,
+        };
+        Self::load_profile(project_profile.as_deref())
+    }
+
+    /// Loads the game folders of a chosen profile, or the global one if none
+    pub fn load_profile(profile: Option<&str>) -> Result<GameProfile> {
+        Self::get_settings()?.game_profile(profile)
     }
 
     pub fn set_from_cemu(&mut self, cemu_dir: &str) -> Result<()> {
@@ -156,7 +404,7 @@ impl Settings {
         if !cemu_dir.exists() {
             Err(anyhow::anyhow!("Specified Cemu directory does not exist"))
         } else {
-            println!("Detecting base game folder...");
+            status!("Detecting base game folder...");
             let needle = glob::glob(
                 cemu_dir
                     .join("mlc01")
@@ -174,8 +422,8 @@ impl Settings {
                     .context("No content folder")?
                     .to_path_buf(),
             );
-            println!("Found: {}", self.game_dir.as_ref().unwrap().display());
-            println!("Detecting update folder...");
+            status!("Found: {}", self.game_dir.as_ref().unwrap().display());
+            status!("Detecting update folder...");
             let needle = glob::glob(
                 cemu_dir
                     .join("mlc01")
@@ -195,8 +443,8 @@ impl Settings {
                     .context("No content folder")?
                     .to_path_buf(),
             );
-            println!("Found: {}", self.update_dir.as_ref().unwrap().display());
-            println!("Detecting DLC folder...");
+            status!("Found: {}", self.update_dir.as_ref().unwrap().display());
+            status!("Detecting DLC folder...");
             let needle = glob::glob(
                 cemu_dir
                     .join("mlc01")
@@ -214,15 +462,18 @@ impl Settings {
                     .context("No content folder")?
                     .to_path_buf(),
             );
-            println!("Found: {}", self.dlc_dir.as_ref().unwrap().display());
-            println!("Game folders set successfully");
+            status!("Found: {}", self.dlc_dir.as_ref().unwrap().display());
+            let mut paths = self.default_profile();
+            paths.detect(true);
+            self.set_profile(None, paths);
+            status!("Game folders set successfully");
             self.save()?;
             Ok(())
         }
     }
 
     pub fn set_from_bcml(&mut self) -> Result<()> {
-        println!("Loading BCML settings...");
+        status!("Loading BCML settings...");
         let bcml_path = dirs2::data_local_dir().unwrap().join("bcml/settings.json");
         let settings: serde_json::Value = serde_json::from_reader(
             std::fs::File::open(bcml_path).context("Missing BCML settings file")?,
@@ -248,11 +499,126 @@ impl Settings {
             .get("dlc_dir_nx")
             .and_then(|d| d.as_str())
             .map(PathBuf::from);
-        println!("BCML settings imported successfully.");
+        let mut paths = self.default_profile();
+        paths.detect(true);
+        paths.detect(false);
+        self.set_profile(None, paths);
+        self.save()?;
+        status!("BCML settings imported successfully.");
         self.list(true);
         Ok(())
     }
 
+    pub fn set_from_ukmm(&mut self) -> Result<()> {
+        status!("Loading UKMM settings...");
+        let ukmm_path = dirs2::config_dir()
+            .context("Failed to get config dir")?
+            .join("ukmm/settings.yml");
+        let settings: serde_yml::Value = serde_yml::from_reader(
+            std::fs::File::open(ukmm_path).context("Missing UKMM settings file")?,
+        )?;
+        // UKMM keeps the dump for each platform under `{platform}_config.dump`, in a shape which
+        // depends on how the dump is stored, so the folder keys are searched for anywhere in it
+        fn find_dir(value: &serde_yml::Value, key: &str) -> Option<PathBuf> {
+            match value {
+                serde_yml::Value::Mapping(map) => map
+                    .get(key)
+                    .and_then(|v| v.as_str())
+                    .map(PathBuf::from)
+                    .or_else(|| map.values().find_map(|v| find_dir(v, key))),
+                serde_yml::Value::Tagged(tagged) => find_dir(&tagged.value, key),
+                _ => None,
+            }
+        }
+        let wiiu = settings.get("wiiu_config").and_then(|c| c.get("dump"));
+        let switch = settings.get("switch_config").and_then(|c| c.get("dump"));
+        if wiiu.is_none() && switch.is_none() {
+            return Err(anyhow!("No game dumps configured in UKMM"));
+        }
+        if let Some(dump) = wiiu {
+            self.game_dir = find_dir(dump, "content_dir");
+            self.update_dir = find_dir(dump, "update_dir");
+            self.dlc_dir = find_dir(dump, "aoc_dir");
+        }
+        if let Some(dump) = switch {
+            self.game_dir_nx = find_dir(dump, "content_dir");
+            self.dlc_dir_nx = find_dir(dump, "aoc_dir");
+        }
+        let mut paths = self.default_profile();
+        paths.detect(true);
+        paths.detect(false);
+        self.set_profile(None, paths);
+        self.save()?;
+        status!("UKMM settings imported successfully.");
+        self.list(true);
+        Ok(())
+    }
+
+    /// Searches a folder for game dumps, finding each kind of game folder by its marker files
+    /// and platform, for example the extracted romfs folders from a Switch emulator.
+    pub fn set_from_dump(&mut self, dump_dir: &Path) -> Result<()> {
+        if !dump_dir.exists() {
+            return Err(anyhow!("Specified dump directory does not exist"));
+        }
+        status!("Searching {} for game folders...", dump_dir.display());
+        let mut roots: Vec<PathBuf> = ["**/Pack/Bootup.pack", "**/Pack/AocMainField.pack"]
+            .iter()
+            .map(|pattern| glob::glob(&dump_dir.join(pattern).to_string_lossy()))
+            .collect::<std::result::Result<Vec<_>, _>>()?
+            .into_iter()
+            .flatten()
+            .filter_map(std::result::Result::ok)
+            .filter_map(|needle| needle.parent()?.parent().map(|p| p.to_path_buf()))
+            .collect();
+        roots.sort();
+        roots.dedup();
+        let mut found = GameProfile::default();
+        for setting in ["game_dir", "update_dir", "dlc_dir", "game_dir_nx", "dlc_dir_nx"] {
+            let (source, be) = game_dump::setting_source(setting).unwrap();
+            let matches: Vec<&PathBuf> = roots
+                .iter()
+                .filter(|root| game_dump::check_folder(root, source, be).is_ok())
+                .collect();
+            if let Some(dir) = matches.first() {
+                status!("Found {}: {}", setting, dir.display());
+                for other in &matches[1..] {
+                    message::warning(
+                        Code::GameDump,
+                        &format!("Also found {} at {}, ignoring", setting, other.display()),
+                    );
+                }
+                match setting {
+                    "game_dir" => found.game_dir = Some(dir.to_path_buf()),
+                    "update_dir" => found.update_dir = Some(dir.to_path_buf()),
+                    "dlc_dir" => found.dlc_dir = Some(dir.to_path_buf()),
+                    "game_dir_nx" => found.game_dir_nx = Some(dir.to_path_buf()),
+                    _ => found.dlc_dir_nx = Some(dir.to_path_buf()),
+                }
+            }
+        }
+        if found == GameProfile::default() {
+            return Err(anyhow!("No game folders found in {}", dump_dir.display()));
+        }
+        // Only the platforms found are replaced, so Wii U and Switch dumps can be imported
+        // from separate folders
+        let mut paths = self.default_profile();
+        if found.game_dir.is_some() || found.update_dir.is_some() || found.dlc_dir.is_some() {
+            paths.game_dir = found.game_dir;
+            paths.update_dir = found.update_dir;
+            paths.dlc_dir = found.dlc_dir;
+            paths.detect(true);
+        }
+        if found.game_dir_nx.is_some() || found.dlc_dir_nx.is_some() {
+            paths.game_dir_nx = found.game_dir_nx;
+            paths.dlc_dir_nx = found.dlc_dir_nx;
+            paths.detect(false);
+        }
+        self.set_profile(None, paths);
+        self.save()?;
+        status!("Game folders set successfully");
+        Ok(())
+    }
+
     pub fn get_settings() -> Result<Self> {
         let path = Self::get_settings_path();
         if !path.exists() {
@@ -1,13 +1,25 @@
-use super::util::*;
+use super::{
+    archive,
+    builder::BuildConfig,
+    diagnostic::Code,
+    game_dump::StockHashes,
+    message::{self, status},
+    sarc_meta::SarcMeta,
+    settings::Settings,
+    util::*,
+};
 use anyhow::{anyhow, format_err, Context, Result};
-use colored::*;
+use botw_utils::{get_canon_name, get_canon_name_without_root};
 use fs_err as fs;
 use join_str::jstr;
 use rayon::prelude::*;
 use roead::{sarc::Sarc, *};
+use scc::{hash_map::Entry, HashMap as SyncMap};
+use serde::Serialize;
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 static BLANK_META: &[u8] = b"\
@@ -18,7 +30,7 @@ Flags: []
 Options: {}
 ";
 
-static HANDLED: &[&str] = &[
+pub static HANDLED: &[&str] = &[
     "ResourceSizeTable.product.srsizetable",
     "ActorInfo.product.sbyml",
     "EventInfo.product.sbyml",
@@ -27,7 +39,7 @@ static HANDLED: &[&str] = &[
     "info.json",
 ];
 
-static ROOT_PACKS: &[&str] = &["sbactorpack", "sbeventpack"];
+pub static ROOT_PACKS: &[&str] = &["sbactorpack", "sbeventpack"];
 
 static EXCLUDE_UNPACK: &[&str] = &[
     "tera_resource.Cafe_Cafe_GX2.release.ssarc",
@@ -41,6 +53,24 @@ pub struct Unbuilder<'a> {
     pub be: bool,
     pub output: &'a Path,
     pub source: PathBuf,
+    /// Stock hash table used to skip vanilla files when unbuilding only modified files
+    pub hash_table: Option<StockHashes>,
+    pub skipped_files: AtomicUsize,
+    pub skipped_entries: AtomicUsize,
+    /// Store conflicting copies of root-merged files under `Variants/{pack}`
+    pub keep_variants: bool,
+    merged: SyncMap<PathBuf, Merged>,
+}
+
+type UnbuildFn = fn(&[u8], &Path) -> Result<()>;
+
+/// Every copy of a file found in root-merged packs. The first copy found is unbuilt right
+/// away, and only copies which differ from it are held until every pack has been read.
+#[derive(Debug)]
+struct Merged {
+    written: blake3::Hash,
+    packs: BTreeMap<String, blake3::Hash>,
+    copies: HashMap<blake3::Hash, (Vec<u8>, UnbuildFn)>,
 }
 
 #[inline]
@@ -61,11 +91,45 @@ fn unbuild_byml(data: &[u8], out: &Path) -> Result<()> {
     Ok(())
 }
 
-const PLATFORM_WARNING: &str = "\
-WARNING: A `content` folder exists but no `--be` flag was set.
-         If nothing is unbuilt, check your platform setting.";
+#[inline]
+fn write_raw(data: &[u8], out: &Path) -> Result<()> {
+    fs::write(out, data)?;
+    Ok(())
+}
+
+/// Where the contents of a SARC are unbuilt to
+#[derive(Debug, Clone, Copy)]
+pub enum SarcOutput<'a> {
+    /// A folder named after the SARC
+    Folder(&'a Path),
+    /// The content root, as for actor and event packs, with the name of the pack
+    Root(&'a str),
+}
+
+/// A file found with different contents in more than one root-merged pack
+#[derive(Debug, Serialize)]
+struct Conflict {
+    kept: String,
+    differing: Vec<String>,
+}
+
+const PLATFORM_WARNING: &str = "A `content` folder exists but no `--be` flag was set. If nothing \
+                                is unbuilt, check your platform setting.";
+
+impl<'a> Unbuilder<'a> {
+    pub fn new(be: bool, output: &'a Path, source: PathBuf) -> Self {
+        Self {
+            be,
+            output,
+            source,
+            hash_table: None,
+            skipped_files: AtomicUsize::new(0),
+            skipped_entries: AtomicUsize::new(0),
+            keep_variants: false,
+            merged: SyncMap::default(),
+        }
+    }
 
-impl Unbuilder<'_> {
     #[inline]
     fn content(&self) -> &str {
         if self.be {
@@ -89,14 +153,43 @@ impl Unbuilder<'_> {
         self.output.join(self.content())
     }
 
+    #[inline]
+    fn is_vanilla(&self, canon: &str, data: &[u8]) -> bool {
+        self.hash_table
+            .as_ref()
+            .map(|table| !table.is_file_modded(canon, &yaz0::decompress_if(data), true))
+            .unwrap_or(false)
+    }
+
     fn unbuild(self) -> Result<()> {
+        if archive::is_archive(&self.source) {
+            self.unbuild_archive()?;
+        } else {
+            self.unbuild_folder()?;
+        }
+        let actor_pack_dir = self.out_content().join("Actor/Pack");
+        if actor_pack_dir.exists() {
+            fs::remove_dir_all(actor_pack_dir)?;
+        }
+        self.write_conflicts()?;
+        if self.hash_table.is_some() {
+            status!(
+                "Skipped {} unmodified files and {} unmodified pack entries",
+                self.skipped_files.load(Ordering::Relaxed),
+                self.skipped_entries.load(Ordering::Relaxed)
+            );
+        }
+        Ok(())
+    }
+
+    fn unbuild_folder(&self) -> Result<()> {
         if !validate_source(&self.source) {
             return Err(anyhow!("Source folder is not in a supported mod format"));
         }
         if !self.be && self.source.join("content").exists() {
-            println!("{}", PLATFORM_WARNING.yellow());
+            message::warning(Code::PlatformMismatch, PLATFORM_WARNING);
         }
-        println!("Unbuilding processed files...");
+        status!("Unbuilding processed files...");
         for dir in PROCESSED_DIRS {
             glob::glob(
                 &self
@@ -113,11 +206,11 @@ impl Unbuilder<'_> {
             .filter_map(Result::ok)
             .par_bridge()
             .try_for_each(|f| -> Result<()> {
-                self.unbuild_file(&f)?;
+                self.unbuild_file(f.strip_prefix(&self.source)?, &fs::read(&f)?)?;
                 Ok(())
             })?;
         }
-        println!("Unbuilding general files...");
+        status!("Unbuilding general files...");
         for dir in UNPROCESSED_DIRS {
             glob::glob(
                 &self
@@ -134,68 +227,156 @@ impl Unbuilder<'_> {
             .filter_map(Result::ok)
             .par_bridge()
             .try_for_each(|f| -> Result<()> {
-                let out = self.output.join(f.strip_prefix(&self.source)?);
-                fs::create_dir_all(out.parent().context("No parent???")?)?;
-                fs::copy(&f, &out)?;
-                Ok(())
+                self.copy_file(f.strip_prefix(&self.source)?, &fs::read(&f)?)
             })?;
         }
         let actorinfo = self
             .source
-            .join(if self.be {
-                "content"
-            } else {
-                "01007EF00011E000/romfs"
-            })
+            .join(self.content())
             .join("Actor/ActorInfo.product.sbyml");
         if actorinfo.exists() {
             self.unbuild_actorinfo(&actorinfo)?;
         }
-        let actor_pack_dir = self.out_content().join("Actor/Pack");
-        if actor_pack_dir.exists() {
-            fs::remove_dir_all(actor_pack_dir)?;
+        Ok(())
+    }
+
+    fn unbuild_archive(&self) -> Result<()> {
+        status!("Unbuilding files from {}...", self.source.display());
+        let actorinfo = Path::new(self.content()).join("Actor/ActorInfo.product.sbyml");
+        let platform_warning = std::sync::Once::new();
+        let (tx, rx) = std::sync::mpsc::sync_channel::<(String, Vec<u8>)>(16);
+        std::thread::scope(|scope| -> Result<()> {
+            let source = &self.source;
+            let reader = scope.spawn(move || archive::read_files(source, tx));
+            let result = rx
+                .into_iter()
+                .par_bridge()
+                .try_for_each(|(name, data)| -> Result<()> {
+                    let rel = match archive::mod_path(&name) {
+                        Some(rel) => rel,
+                        None => {
+                            let path = Path::new(&name);
+                            if path.file_name() == Some(std::ffi::OsStr::new("info.json"))
+                                && path.components().count() <= 2
+                            {
+                                self.unbuild_bnp_info(&data)?;
+                            }
+                            return Ok(());
+                        }
+                    };
+                    if !self.be && rel.starts_with("content") {
+                        platform_warning.call_once(|| {
+                            message::warning(Code::PlatformMismatch, PLATFORM_WARNING)
+                        });
+                    }
+                    let dir = rel
+                        .strip_prefix(self.content())
+                        .or_else(|_| rel.strip_prefix(self.aoc()))
+                        .ok()
+                        .and_then(|r| r.components().next())
+                        .and_then(|c| c.as_os_str().to_str());
+                    if rel == actorinfo {
+                        self.unbuild_actorinfo_data(&data)
+                    } else if dir.map(|d| PROCESSED_DIRS.contains(&d)).unwrap_or(false) {
+                        self.unbuild_file(&rel, &data)
+                    } else if dir.map(|d| UNPROCESSED_DIRS.contains(&d)).unwrap_or(false) {
+                        self.copy_file(&rel, &data)
+                    } else {
+                        Ok(())
+                    }
+                });
+            let read_result = reader
+                .join()
+                .map_err(|_| anyhow!("Failed to read archive {}", self.source.display()))?;
+            result?;
+            read_result
+        })
+    }
+
+    fn unbuild_bnp_info(&self, data: &[u8]) -> Result<()> {
+        status!("Loading mod info...");
+        let info: serde_json::Value = serde_json::from_slice(data).context("Invalid info.json")?;
+        if !BuildConfig::path(&self.output).exists() {
+            fs::write(BuildConfig::path(&self.output), BLANK_META)?;
         }
+        let mut config = BuildConfig::load_or_default(&self.output)?;
+        for (key, field) in [
+            ("name", "name"),
+            ("description", "desc"),
+            ("version", "version"),
+            ("url", "url"),
+        ] {
+            match info.get(field) {
+                Some(serde_json::Value::String(value)) if !value.is_empty() => {
+                    config.meta.insert(key.into(), value.clone());
+                }
+                Some(value @ serde_json::Value::Number(_)) => {
+                    config.meta.insert(key.into(), value.to_string());
+                }
+                _ => (),
+            }
+        }
+        config.save(&self.output)?;
         Ok(())
     }
 
-    fn unbuild_file(&self, file: &Path) -> Result<()> {
-        let file_name = file.file_name().unwrap().to_str().unwrap();
-        let rel = file.strip_prefix(&self.source)?.to_owned();
+    fn copy_file(&self, rel: &Path, data: &[u8]) -> Result<()> {
+        if self.hash_table.is_some() {
+            if let Some(canon) = get_canon_name(rel) {
+                if self.is_vanilla(&canon, data) {
+                    self.skipped_files.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+            }
+        }
+        let out = self.output.join(rel);
+        fs::create_dir_all(out.parent().context("No parent???")?)?;
+        fs::write(&out, data)?;
+        Ok(())
+    }
+
+    pub fn unbuild_file(&self, rel: &Path, data: &[u8]) -> Result<()> {
+        let file_name = rel.file_name().unwrap().to_str().unwrap();
         if HANDLED.contains(&file_name) {
             return Ok(());
         }
-        let out = self.output.join(&rel);
+        if self.hash_table.is_some() {
+            if let Some(canon) = get_canon_name(rel) {
+                if self.is_vanilla(&canon, data) {
+                    self.skipped_files.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+            }
+        }
+        let out = self.output.join(rel);
         if !out.parent().unwrap().exists() {
             fs::create_dir_all(out.parent().unwrap())?;
         }
-        let ext = match get_ext(&rel) {
+        let ext = match get_ext(rel) {
             Ok(e) => e,
             Err(_) => {
-                fs::copy(file, &out)?;
+                fs::write(&out, data)?;
                 return Ok(());
             }
         };
-        let data = fs::read(file)?;
         if AAMP_EXTS.contains(&ext) {
-            unbuild_aamp(&data, &out.with_extension(jstr!("{ext}.yml")))?;
+            unbuild_aamp(data, &out.with_extension(jstr!("{ext}.yml")))?;
         } else if BYML_EXTS.contains(&ext) {
-            unbuild_byml(&data, &out.with_extension(jstr!("{ext}.yml")))?;
+            unbuild_byml(data, &out.with_extension(jstr!("{ext}.yml")))?;
         } else if botw_utils::extensions::SARC_EXTS.contains(&ext) && !data.is_empty() {
-            let sarc = Sarc::new(&data)?;
             if file_name.starts_with("Bootup_") && file_name.len() == 16 {
                 // if self.no_msyt {
-                //     drop(sarc);
                 //     fs::write(out, data)?;
                 // } else {
-                self.unbuild_text(sarc)?;
+                self.unbuild_text(Sarc::new(data)?)?;
                 // }
             } else {
                 self.unbuild_sarc(
-                    sarc,
+                    data,
                     if ROOT_PACKS.contains(&ext) {
-                        None
+                        SarcOutput::Root(file_name)
                     } else {
-                        Some(&out)
+                        SarcOutput::Folder(&out)
                     },
                 )?;
             }
@@ -206,8 +387,12 @@ impl Unbuilder<'_> {
     }
 
     pub fn unbuild_actorinfo(&self, file: &Path) -> Result<()> {
-        println!("Unbuilding actor info...");
-        let actorinfo = byml::Byml::from_binary(fs::read(file)?)?;
+        self.unbuild_actorinfo_data(&fs::read(file)?)
+    }
+
+    pub fn unbuild_actorinfo_data(&self, data: &[u8]) -> Result<()> {
+        status!("Unbuilding actor info...");
+        let actorinfo = byml::Byml::from_binary(data)?;
         fs::create_dir_all(self.out_content().join("Actor/ActorInfo"))?;
         actorinfo
             .as_map()?
@@ -229,7 +414,7 @@ impl Unbuilder<'_> {
     }
 
     fn unbuild_eventinfo(&self, data: &[u8]) -> Result<()> {
-        println!("Unbuilding event info...");
+        status!("Unbuilding event info...");
         let eventinfo = byml::Byml::from_binary(yaz0::decompress(data)?)?;
         let eventinfo = eventinfo.as_map()?;
         fs::create_dir_all(self.out_content().join("Event/EventInfo"))?;
@@ -257,75 +442,205 @@ impl Unbuilder<'_> {
         Ok(())
     }
 
-    pub fn unbuild_sarc(&self, sarc: Sarc, output: Option<&Path>) -> Result<()> {
-        let output = output
-            .map(|o| o.to_owned())
-            .unwrap_or_else(|| self.out_content());
+    pub fn unbuild_sarc(&self, data: &[u8], output: SarcOutput) -> Result<()> {
+        let data = yaz0::decompress_if(data);
+        let sarc = Sarc::new(&data[..])?;
+        // Root-merged packs are rebuilt from their parts, so they must be unbuilt whole
+        let skip_vanilla = self.hash_table.is_some() && matches!(output, SarcOutput::Folder(_));
+        let (output, root_pack) = match output {
+            SarcOutput::Folder(folder) => (folder.to_owned(), None),
+            SarcOutput::Root(pack) => (self.out_content(), Some(pack)),
+        };
         if !output.exists() {
             fs::create_dir_all(&output)?;
         }
-        if sarc
-            .files()
-            .any(|f| f.name().unwrap_or("").starts_with('/'))
-        {
-            fs::write(output.join(".slash"), b"")?;
-        }
-        if sarc.guess_min_alignment() != 4 {
-            fs::write(
-                output.join(".align"),
-                format!("{}", sarc.guess_min_alignment()),
-            )?;
-        }
+        let mut meta = if root_pack.is_none() {
+            let (meta, unnamed) = SarcMeta::from_binary(&data)?;
+            for (hash, data) in unnamed {
+                fs::write(output.join(SarcMeta::unnamed_file(hash)), data)?;
+            }
+            Some(meta)
+        } else {
+            None
+        };
+        let mut vanilla: Vec<&str> = vec![];
         for file in sarc.files().filter(|f| f.name().is_some()) {
+            if skip_vanilla
+                && self.is_vanilla(
+                    &get_canon_name_without_root(file.name().unwrap()),
+                    file.data(),
+                )
+            {
+                vanilla.push(file.name().unwrap());
+                continue;
+            }
             let name = file.name().unwrap().trim_start_matches('/');
             let out = output.join(name);
             fs::create_dir_all(out.parent().unwrap())?;
-            if let Some(ext) = name.split('.').last() {
-                if &file.data()[0..4] == b"AAMP" {
-                    let out = out.with_extension(jstr!("{ext}.yml"));
-                    if !out.exists() {
-                        unbuild_aamp(file.data(), &out)?;
-                    }
-                } else if BYML_EXTS.contains(&ext) {
-                    if name.ends_with("EventInfo.product.sbyml") {
-                        self.unbuild_eventinfo(file.data())?;
+            let (out, unbuild): (PathBuf, fn(&[u8], &Path) -> Result<()>) =
+                if let Some(ext) = name.split('.').last() {
+                    if &file.data()[0..4] == b"AAMP" {
+                        (out.with_extension(jstr!("{ext}.yml")), unbuild_aamp)
+                    } else if BYML_EXTS.contains(&ext) {
+                        if name.ends_with("EventInfo.product.sbyml") {
+                            self.unbuild_eventinfo(file.data())?;
+                            continue;
+                        }
+                        (out.with_extension(jstr!("{ext}.yml")), unbuild_byml)
+                    } else if file.data().len() > 0x15
+                        && (&file.data()[0..4] == b"SARC" || &file.data()[0x11..0x15] == b"SARC")
+                        && !EXCLUDE_UNPACK.contains(&name)
+                        && !EXCLUDE_UNPACK_EXTS.contains(&ext)
+                    {
+                        self.unbuild_sarc(
+                            file.data(),
+                            if output.file_name().unwrap().to_str().unwrap() == "TitleBG.pack"
+                                && ext == "sbactorpack"
+                            {
+                                SarcOutput::Root(
+                                    Path::new(name).file_name().unwrap().to_str().unwrap(),
+                                )
+                            } else {
+                                SarcOutput::Folder(&out)
+                            },
+                        )?;
+                        continue;
                     } else {
-                        let out = out.with_extension(jstr!("{ext}.yml"));
-                        unbuild_byml(file.data(), &out)?;
+                        (out, write_raw)
                     }
-                } else if file.data().len() > 0x15
-                    && (&file.data()[0..4] == b"SARC" || &file.data()[0x11..0x15] == b"SARC")
-                    && !EXCLUDE_UNPACK.contains(&name)
-                    && !EXCLUDE_UNPACK_EXTS.contains(&ext)
-                {
-                    let subsarc = Sarc::new(file.data())?;
-                    self.unbuild_sarc(
-                        subsarc,
-                        if output.file_name().unwrap().to_str().unwrap() == "TitleBG.pack"
-                            && ext == "sbactorpack"
-                        {
-                            None
-                        } else {
-                            Some(&out)
-                        },
-                    )?;
                 } else {
-                    fs::write(out, file.data())?;
-                }
+                    (out, write_raw)
+                };
+            if let Some(pack) = root_pack {
+                self.unbuild_merged(
+                    pack,
+                    out.strip_prefix(&output)?.to_owned(),
+                    file.data(),
+                    unbuild,
+                )?;
             } else {
-                fs::write(out, file.data())?;
+                unbuild(file.data(), &out)?;
+            }
+        }
+        self.skipped_entries
+            .fetch_add(vanilla.len(), Ordering::Relaxed);
+        if let Some(meta) = meta.as_mut() {
+            meta.vanilla = vanilla.into_iter().map(|f| f.to_owned()).collect();
+            meta.save(&output)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn variant_path(&self, pack: &str, rel: &Path) -> PathBuf {
+        self.out_content().join("Variants").join(pack).join(rel)
+    }
+
+    /// Unbuilds a file from a root-merged pack. When several packs contain different copies of
+    /// the same file, the copy from the first pack by name is kept once every pack is read, in
+    /// [`Unbuilder::write_conflicts`].
+    fn unbuild_merged(
+        &self,
+        pack: &str,
+        rel: PathBuf,
+        data: &[u8],
+        unbuild: UnbuildFn,
+    ) -> Result<()> {
+        let hash = blake3::hash(data);
+        match self.merged.entry(rel.clone()) {
+            Entry::Vacant(entry) => {
+                unbuild(data, &self.out_content().join(&rel))?;
+                entry.insert_entry(Merged {
+                    written: hash,
+                    packs: BTreeMap::from([(pack.to_owned(), hash)]),
+                    copies: HashMap::new(),
+                });
+            }
+            Entry::Occupied(mut entry) => {
+                let merged = entry.get_mut();
+                merged.packs.insert(pack.to_owned(), hash);
+                if hash != merged.written {
+                    merged
+                        .copies
+                        .entry(hash)
+                        .or_insert_with(|| (data.to_vec(), unbuild));
+                }
             }
         }
         Ok(())
     }
 
+    /// Settles every file found with different copies: the first pack's copy is kept, every
+    /// pack with another copy gets it under `Variants/{pack}` if variants are kept, and the
+    /// differences are listed in `conflicts.yml`
+    fn write_conflicts(&self) -> Result<()> {
+        let mut merged = vec![];
+        self.merged.scan(|rel, found| {
+            if !found.copies.is_empty() {
+                merged.push(rel.clone());
+            }
+        });
+        let mut conflicts: BTreeMap<String, Conflict> = BTreeMap::new();
+        for rel in merged {
+            let (_, merged) = self
+                .merged
+                .remove(&rel)
+                .context("Merged file went missing")?;
+            let out = self.out_content().join(&rel);
+            let (kept, kept_hash) = merged
+                .packs
+                .iter()
+                .next()
+                .map(|(p, h)| (p.clone(), *h))
+                .context("Merged file has no source pack")?;
+            let mut differing = vec![];
+            for (pack, hash) in merged.packs.iter().filter(|(_, h)| **h != kept_hash) {
+                differing.push(pack.clone());
+                if !self.keep_variants {
+                    continue;
+                }
+                let variant = self.variant_path(pack, &rel);
+                fs::create_dir_all(variant.parent().unwrap())?;
+                // The first copy found is already unbuilt, and the others are held
+                match merged.copies.get(hash) {
+                    Some((data, unbuild)) => unbuild(data, &variant)?,
+                    None => {
+                        fs::copy(&out, &variant)?;
+                    }
+                }
+            }
+            if kept_hash != merged.written {
+                let (data, unbuild) = &merged.copies[&kept_hash];
+                unbuild(data, &out)?;
+            }
+            conflicts.insert(
+                rel.to_string_lossy().replace('\\', "/"),
+                Conflict { kept, differing },
+            );
+        }
+        if !conflicts.is_empty() {
+            message::warning(
+                Code::PackConflict,
+                &format!(
+                    "{} files differ between packs, see conflicts.yml",
+                    conflicts.len()
+                ),
+            );
+            fs::write(
+                self.output.join("conflicts.yml"),
+                serde_yml::to_string(&conflicts)?,
+            )?;
+        }
+        Ok(())
+    }
+
     fn unbuild_text(&self, sarc: Sarc) -> Result<()> {
         let msg_pack = sarc
             .files()
             .find(|f| f.name().is_some() && f.name().unwrap().contains(".ssarc"))
             .context("{} is missing a message SARC")?;
         let lang = &msg_pack.name().unwrap()[0xC..0x10];
-        println!("Unbuilding {} texts...", lang);
+        status!("Unbuilding {} texts...", lang);
         let msg_sarc = Sarc::new(msg_pack.data())?;
         (0..msg_sarc.len())
             .into_par_iter()
@@ -359,8 +674,10 @@ pub fn unbuild(
     source: Option<PathBuf>,
     directory: Option<PathBuf>,
     config: bool,
+    modified_only: bool,
+    keep_variants: bool,
 ) -> Result<()> {
-    println!("Initializing mod project...");
+    status!("Initializing mod project...");
     let output = directory.unwrap_or_else(|| PathBuf::from("."));
     if output.exists() {
         fs::remove_dir_all(&output)?;
@@ -370,13 +687,13 @@ pub fn unbuild(
         fs::write(output.join("config.yml"), BLANK_META)?;
     }
     if let Some(source) = source {
-        println!("Unbuilding source files...");
-        Unbuilder {
-            be,
-            output: &output,
-            source,
+        status!("Unbuilding source files...");
+        let mut unbuilder = Unbuilder::new(be, &output, source);
+        unbuilder.keep_variants = keep_variants;
+        if modified_only {
+            unbuilder.hash_table = Some(StockHashes::new(&Settings::load(None)?, be));
         }
-        .unbuild()?;
+        unbuilder.unbuild()?;
     } else {
         fs::create_dir_all(output.join(if be {
             "content"
@@ -385,12 +702,44 @@ pub fn unbuild(
         }))?;
     }
     fs::write(output.join(".db"), b"")?;
-    println!("Done");
+    status!("Done");
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn variants_for_every_replaced_copy() {
+        let output = TempDir::new("merged");
+        let mut unbuilder = Unbuilder::new(true, &output, PathBuf::new());
+        unbuilder.keep_variants = true;
+        let rel = PathBuf::from("Actor/AS/Shared.bas");
+        fs::create_dir_all(unbuilder.out_content().join("Actor/AS")).unwrap();
+        for (pack, data) in [
+            ("Obj_B.sbactorpack", b"old"),
+            ("Obj_C.sbactorpack", b"old"),
+            ("Obj_A.sbactorpack", b"new"),
+        ] {
+            unbuilder
+                .unbuild_merged(pack, rel.clone(), data, write_raw)
+                .unwrap();
+        }
+        unbuilder.write_conflicts().unwrap();
+        let content = unbuilder.out_content();
+        assert_eq!(fs::read(content.join(&rel)).unwrap(), b"new");
+        for pack in ["Obj_B.sbactorpack", "Obj_C.sbactorpack"] {
+            assert_eq!(
+                fs::read(unbuilder.variant_path(pack, &rel)).unwrap(),
+                b"old"
+            );
+        }
+        assert!(!unbuilder.variant_path("Obj_A.sbactorpack", &rel).exists());
+        assert!(output.join("conflicts.yml").exists());
+    }
+
     #[test]
     fn unbuild_u() {
         super::unbuild(
@@ -398,6 +747,8 @@ mod tests {
             Some("test/source".into()),
             Some("test/project".into()),
             true,
+            false,
+            false,
         )
         .unwrap();
     }
@@ -409,6 +760,8 @@ mod tests {
             Some("test/source_nx".into()),
             Some("test/project_nx".into()),
             true,
+            false,
+            false,
         )
         .unwrap();
     }
@@ -1,9 +1,10 @@
+use crate::diagnostic::Diagnostic;
 use anyhow::{format_err, Context, Result};
 pub use botw_utils::extensions::{AAMP_EXTS, BYML_EXTS};
 use join_str::jstr;
 use path_slash::PathExt;
 use roead::aamp::ParameterIO;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
 
 pub static PROCESSED_DIRS: &[&str] = &["Actor", "Event", "Map", "Pack"];
@@ -48,7 +49,7 @@ pub fn get_ext(file: &Path) -> Result<&str> {
 pub fn parse_aamp(file: &Path) -> Result<ParameterIO> {
     if file.extension() == Some(std::ffi::OsStr::new("yml")) {
         Ok(ParameterIO::from_text(std::fs::read_to_string(file)?)
-            .with_context(|| jstr!("Failed to parse AAMP file {&file.to_slash_lossy()}"))?)
+            .map_err(|e| Diagnostic::syntax(file, "AAMP", e))?)
     } else {
         Ok(ParameterIO::from_binary(std::fs::read(file)?)
             .with_context(|| jstr!("Failed to parse AAMP file {&file.to_slash_lossy()}"))?)
@@ -62,3 +63,137 @@ pub fn validate_source(source: &Path) -> bool {
         || source.join("01007EF00011E000/romfs").exists()
         || source.join("01007EF00011F001/romfs").exists()
 }
+
+/// Whether `name` appears in `text` as a whole name, not as part of a longer one
+pub fn contains_name(text: &str, name: &str) -> bool {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    text.match_indices(name).any(|(i, _)| {
+        !text[..i].chars().next_back().map(is_name_char).unwrap_or(false)
+            && !text[i + name.len()..]
+                .chars()
+                .next()
+                .map(is_name_char)
+                .unwrap_or(false)
+    })
+}
+
+/// Every YAML and MSYT source file in a project, where references to actors and events live
+pub fn text_sources(project: &Path) -> Result<Vec<std::path::PathBuf>> {
+    Ok(["content", "aoc", "01007EF00011E000", "01007EF00011F001"]
+        .iter()
+        .map(|root| project.join(root))
+        .filter(|root| root.exists())
+        .map(|root| glob::glob(&root.join("**/*").to_string_lossy()))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .filter(|f| {
+            f.is_file()
+                && matches!(
+                    f.extension().and_then(|e| e.to_str()),
+                    Some("yml") | Some("msyt")
+                )
+                && !f
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .map(|n| n.starts_with('.'))
+                    .unwrap_or(true)
+        })
+        .collect())
+}
+
+/// Replaces every whole-name occurrence of `name` in `text`, returning the new text and the
+/// number of replacements
+pub fn replace_name(text: &str, name: &str, new: &str) -> (String, usize) {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+    let mut count = 0;
+    for (i, _) in text.match_indices(name) {
+        if i < last
+            || text[..i].chars().next_back().map(is_name_char).unwrap_or(false)
+            || text[i + name.len()..]
+                .chars()
+                .next()
+                .map(is_name_char)
+                .unwrap_or(false)
+        {
+            continue;
+        }
+        out.push_str(&text[last..i]);
+        out.push_str(new);
+        last = i + name.len();
+        count += 1;
+    }
+    out.push_str(&text[last..]);
+    (out, count)
+}
+
+/// Writes a file by renaming a temporary file over it, so the old file, and any hard link to it
+/// like a deployed copy, is never changed in place or left half written
+pub fn replace_file(
+    path: &Path,
+    write: impl FnOnce(&Path) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let temp = path.with_file_name(format!(
+        ".{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    write(&temp)
+        .and_then(|_| fs_err::rename(&temp, path))
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&temp);
+        })
+}
+
+/// Writes a file's data through [`replace_file`]
+#[inline]
+pub fn write_file(path: &Path, data: impl AsRef<[u8]>) -> std::io::Result<()> {
+    replace_file(path, |temp| fs_err::write(temp, data))
+}
+
+/// A folder in the system temp folder, removed with everything in it when dropped unless it is
+/// to be kept
+pub struct TempDir {
+    pub path: PathBuf,
+    pub keep: bool,
+}
+
+impl TempDir {
+    /// A folder named for its use and this process, so processes running at once don't share it
+    pub fn new(name: &str) -> Self {
+        Self {
+            path: std::env::temp_dir().join(format!(
+                "hyrule_builder_{}_{}",
+                name,
+                std::process::id()
+            )),
+            keep: false,
+        }
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if self.keep {
+            crate::message::status!("Temporary files kept at {}", self.path.display());
+        } else {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+}
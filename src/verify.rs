@@ -0,0 +1,363 @@
+use crate::{
+    archive,
+    diagnostic::Code,
+    message::{self, status},
+    settings::{GameProfile, Settings},
+    util::TempDir,
+};
+use anyhow::{format_err, Context, Result};
+use fs_err as fs;
+use path_slash::PathExt;
+use roead::{aamp::ParameterIO, byml::Byml, sarc::Sarc, yaz0::decompress_if};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// Files rewritten by every build, which are not expected to survive a round trip unchanged
+static SKIP: &[&str] = &[
+    "ResourceSizeTable.product.srsizetable",
+    "rules.txt",
+    "info.json",
+];
+
+/// Differing lines shown for each changed text file
+const MAX_LINES: usize = 5;
+
+/// Loads every game file in a mod folder or archive, keyed by its path relative to the mod root
+fn load_files(source: &Path, be: bool) -> Result<BTreeMap<PathBuf, Vec<u8>>> {
+    let roots = if be {
+        ["content", "aoc/0010"]
+    } else {
+        ["01007EF00011E000/romfs", "01007EF00011F001/romfs"]
+    };
+    let keep = |rel: &Path| {
+        roots.iter().any(|r| rel.starts_with(r))
+            && !rel
+                .file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| SKIP.contains(&n))
+                .unwrap_or(true)
+    };
+    let mut files = BTreeMap::new();
+    if archive::is_archive(source) {
+        let (tx, rx) = std::sync::mpsc::sync_channel(16);
+        std::thread::scope(|scope| -> Result<()> {
+            let reader = scope.spawn(move || archive::read_files(source, tx));
+            for (name, data) in rx {
+                if let Some(rel) = archive::mod_path(&name).filter(|rel| keep(rel)) {
+                    files.insert(rel, data);
+                }
+            }
+            reader
+                .join()
+                .map_err(|_| format_err!("Archive reader panicked"))?
+        })?;
+    } else {
+        for file in glob::glob(&source.join("**/*").to_string_lossy())?
+            .filter_map(Result::ok)
+            .filter(|f| f.is_file())
+        {
+            let rel = file.strip_prefix(source)?.to_owned();
+            if keep(&rel) {
+                files.insert(rel, fs::read(&file)?);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Reports the first lines which differ between two text forms of a file
+fn diff_text(path: &str, old: &str, new: &str, diffs: &mut Vec<String>) {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let mut shown = 0;
+    for i in 0..old_lines.len().max(new_lines.len()) {
+        let (a, b) = (old_lines.get(i), new_lines.get(i));
+        if a == b {
+            continue;
+        }
+        if shown == MAX_LINES {
+            diffs.push(format!("{}: more lines differ", path));
+            break;
+        }
+        diffs.push(format!(
+            "{}, line {}: {} -> {}",
+            path,
+            i + 1,
+            a.map(|l| l.trim()).unwrap_or("<none>"),
+            b.map(|l| l.trim()).unwrap_or("<none>")
+        ));
+        shown += 1;
+    }
+}
+
+fn diff_byml(path: &str, old: &Byml, new: &Byml, diffs: &mut Vec<String>) {
+    match (old, new) {
+        (Byml::Map(old), Byml::Map(new)) => {
+            for (key, value) in old.iter() {
+                match new.get(key) {
+                    Some(other) => diff_byml(&format!("{}.{}", path, key), value, other, diffs),
+                    None => diffs.push(format!("{}: key {} missing", path, key)),
+                }
+            }
+            for key in new.keys().filter(|k| !old.contains_key(*k)) {
+                diffs.push(format!("{}: key {} added", path, key));
+            }
+        }
+        (Byml::Array(old), Byml::Array(new)) => {
+            if old.len() != new.len() {
+                diffs.push(format!(
+                    "{}: array length {} -> {}",
+                    path,
+                    old.len(),
+                    new.len()
+                ));
+            }
+            for (i, (a, b)) in old.iter().zip(new.iter()).enumerate() {
+                diff_byml(&format!("{}[{}]", path, i), a, b, diffs);
+            }
+        }
+        (old, new) if old != new => diffs.push(format!("{}: {:?} -> {:?}", path, old, new)),
+        _ => (),
+    }
+}
+
+fn diff_sarc(path: &str, old: &Sarc, new: &Sarc, diffs: &mut Vec<String>) {
+    let old_files: BTreeMap<&str, &[u8]> = old
+        .files()
+        .filter_map(|f| f.name().map(|n| (n, f.data())))
+        .collect();
+    let new_files: BTreeMap<&str, &[u8]> = new
+        .files()
+        .filter_map(|f| f.name().map(|n| (n, f.data())))
+        .collect();
+    for (name, data) in &old_files {
+        let nested = nested_path(path, name);
+        match new_files.get(name) {
+            Some(other) => diff_file(&nested, data, other, diffs),
+            None => diffs.push(format!("{}: SARC entry dropped", nested)),
+        }
+    }
+    for name in new_files.keys().filter(|n| !old_files.contains_key(*n)) {
+        diffs.push(format!("{}: SARC entry added", nested_path(path, name)));
+    }
+    let (old_unnamed, new_unnamed) = (
+        old.files().filter(|f| f.name().is_none()).count(),
+        new.files().filter(|f| f.name().is_none()).count(),
+    );
+    if old_unnamed != new_unnamed {
+        diffs.push(format!(
+            "{}: {} unnamed SARC entries -> {}",
+            path, old_unnamed, new_unnamed
+        ));
+    }
+}
+
+#[inline]
+fn nested_path(parent: &str, name: &str) -> String {
+    format!("{}//{}", parent, name.trim_start_matches('/'))
+}
+
+/// Compares two versions of a file by their contents, recursing into SARCs. A file which can't
+/// be parsed is reported as a difference, so the rest are still compared.
+fn diff_file(path: &str, old: &[u8], new: &[u8], diffs: &mut Vec<String>) {
+    if let Err(e) = diff_contents(path, old, new, diffs) {
+        diffs.push(format!("{}: could not be compared: {:#}", path, e));
+    }
+}
+
+fn diff_contents(path: &str, old: &[u8], new: &[u8], diffs: &mut Vec<String>) -> Result<()> {
+    if old == new {
+        return Ok(());
+    }
+    if old.starts_with(b"Yaz0") != new.starts_with(b"Yaz0") {
+        diffs.push(format!("{}: Yaz0 compression differs", path));
+    }
+    let (old, new) = (decompress_if(old), decompress_if(new));
+    if old == new {
+        return Ok(());
+    }
+    let magic = |data: &[u8]| data.get(0..8).map(|m| m.to_vec()).unwrap_or_default();
+    match (magic(&old), magic(&new)) {
+        (a, b) if a.starts_with(b"AAMP") && b.starts_with(b"AAMP") => {
+            let (old, new) = (ParameterIO::from_binary(&old)?, ParameterIO::from_binary(&new)?);
+            if old != new {
+                diff_text(path, &old.to_text(), &new.to_text(), diffs);
+            }
+        }
+        (a, b) if (a.starts_with(b"BY") || a.starts_with(b"YB"))
+            && (b.starts_with(b"BY") || b.starts_with(b"YB")) =>
+        {
+            diff_byml(
+                path,
+                &Byml::from_binary(&old)?,
+                &Byml::from_binary(&new)?,
+                diffs,
+            );
+        }
+        (a, b) if a.starts_with(b"SARC") && b.starts_with(b"SARC") => {
+            diff_sarc(path, &Sarc::new(&old[..])?, &Sarc::new(&new[..])?, diffs);
+        }
+        (a, b) if a == b"MsgStdBn" && b == b"MsgStdBn" => {
+            let to_text = |data: &[u8]| -> Result<String> {
+                Ok(serde_yml::to_string(
+                    &msyt::Msyt::from_msbt_bytes(data).map_err(|e| format_err!("{}", e))?,
+                )?)
+            };
+            let (old, new) = (to_text(&old)?, to_text(&new)?);
+            if old != new {
+                diff_text(path, &old, &new, diffs);
+            }
+        }
+        _ => diffs.push(format!(
+            "{}: contents differ ({} bytes -> {} bytes)",
+            path,
+            old.len(),
+            new.len()
+        )),
+    }
+    Ok(())
+}
+
+/// Unbuilds a mod into a temporary project, builds it again, and reports every difference
+/// between the original and rebuilt files.
+pub fn verify_roundtrip(be: bool, source: PathBuf, keep: bool, verbose: bool) -> Result<()> {
+    roundtrip(be, source, keep, verbose, Settings::load(None)?)
+}
+
+/// Runs the round trip, building with the stock files from the given game folders
+fn roundtrip(
+    be: bool,
+    source: PathBuf,
+    keep: bool,
+    verbose: bool,
+    dump: GameProfile,
+) -> Result<()> {
+    let source = dunce::canonicalize(&source)
+        .with_context(|| format!("Source {} not found", source.display()))?;
+    let original = load_files(&source, be)?;
+    if original.is_empty() {
+        return Err(format_err!(
+            "No {} game files found in {}. Is the mod for the other platform?",
+            if be { "Wii U" } else { "Switch" },
+            source.display()
+        ));
+    }
+    // The temporary project and build
+    let mut temp = TempDir::new("verify");
+    temp.keep = keep;
+    let project = temp.path.join("project");
+    let output = temp.path.join("build");
+    crate::unbuilder::unbuild(
+        be,
+        Some(source.clone()),
+        Some(project.clone()),
+        true,
+        false,
+        false,
+    )
+    .context("Failed to unbuild source")?;
+    crate::build(
+        crate::BuildOptions {
+            be,
+            ignore_warnings: !verbose,
+            source: Some(project.clone()),
+            output: Some(output.clone()),
+            no_deploy: true,
+            game_dump: Some(dump),
+            ..Default::default()
+        },
+        verbose,
+    )
+    .context("Failed to build unbuilt project")?;
+
+    status!("Comparing files...");
+    let rebuilt = load_files(&output, be)?;
+    let mut diffs = vec![];
+    for (rel, data) in &original {
+        let path = rel.to_slash_lossy();
+        match rebuilt.get(rel) {
+            Some(other) => diff_file(&path, data, other, &mut diffs),
+            None => diffs.push(format!("{}: missing from build", path)),
+        }
+    }
+    for rel in rebuilt.keys().filter(|f| !original.contains_key(*f)) {
+        diffs.push(format!("{}: not in original mod", rel.to_slash_lossy()));
+    }
+    drop(temp);
+    if diffs.is_empty() {
+        message::success(&format!(
+            "All {} files match after a round trip",
+            original.len()
+        ));
+        Ok(())
+    } else {
+        for diff in &diffs {
+            message::warning(Code::RoundTrip, diff);
+        }
+        Err(format_err!(
+            "{} differences found in {} files",
+            diffs.len(),
+            original.len()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use roead::{byml::Map, sarc::SarcWriter, Endian};
+
+    #[test]
+    fn roundtrip_small_mod() {
+        let source = TempDir::new("verify_src");
+        let content = source.join("content");
+        fs::create_dir_all(content.join("Pack")).unwrap();
+        fs::create_dir_all(content.join("Game")).unwrap();
+        let mut byml = Map::default();
+        byml.insert("Name".into(), Byml::String("Test".into()));
+        byml.insert("Count".into(), Byml::I32(3));
+        let mut pack = SarcWriter::new(Endian::Big);
+        pack.add_file("Data/Test.byml", Byml::Map(byml).to_binary(Endian::Big));
+        pack.add_file("Data/Test.bin", b"Hyrule Builder".to_vec());
+        fs::write(
+            content.join("Pack/HyruleBuilderTest.pack"),
+            pack.to_binary(),
+        )
+        .unwrap();
+        fs::write(content.join("Game/Test.bin"), b"Loose file").unwrap();
+
+        // No game folders, so the result doesn't depend on the settings of whoever runs it
+        roundtrip(
+            true,
+            source.to_path_buf(),
+            false,
+            false,
+            GameProfile::default(),
+        )
+        .unwrap();
+        // Nothing is found for the other platform
+        assert!(roundtrip(
+            false,
+            source.to_path_buf(),
+            false,
+            false,
+            GameProfile::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parse_errors_are_differences() {
+        let mut diffs = vec![];
+        diff_file(
+            "Test.sbyml",
+            b"BY\0\x02broken",
+            b"BY\0\x02changed",
+            &mut diffs,
+        );
+        assert_eq!(diffs.len(), 1);
+        assert!(diffs[0].starts_with("Test.sbyml: could not be compared"));
+    }
+}